@@ -13,36 +13,46 @@ use mini::aio::net::{
 };
 use mini::aio::stdio::{
     InputNotify,
+    Stderr,
     Stdin,
+    Stdout,
 };
 
 struct Connection {
+    stderr: Stderr,
+    stdout: Stdout,
 }
 
 impl Connection {
-    fn new() -> Self {
+    fn new(stdout: Stdout, stderr: Stderr) -> Self {
         Self {
+            stderr,
+            stdout,
         }
     }
 }
 
 impl TcpConnectionNotify for Connection {
     fn connected(&mut self, _connection: &mut TcpConnection) {
-        println!("Connected");
+        let _ = self.stdout.write(b"Connected\n".to_vec());
     }
 
     fn connect_failed(&mut self) {
-        eprintln!("Connect failed");
+        let _ = self.stderr.write(b"Connect failed\n".to_vec());
     }
 
     fn error(&mut self, error: io::Error) {
-        eprintln!("Error: {}", error);
+        let _ = self.stderr.write(format!("Error: {}\n", error).into_bytes());
     }
 
     fn received(&mut self, _connection: &mut TcpConnection, data: Vec<u8>) {
         match String::from_utf8(data) {
-            Ok(text) => print!("-> {}", text),
-            Err(error) => println!("Error: did not receive valid UTF-8: {}", error),
+            Ok(text) => {
+                let _ = self.stdout.write(format!("-> {}", text).into_bytes());
+            },
+            Err(error) => {
+                let _ = self.stderr.write(format!("Error: did not receive valid UTF-8: {}\n", error).into_bytes());
+            },
         }
     }
 }
@@ -68,7 +78,9 @@ impl InputNotify for StdinHandler {
 fn main() {
     let mut event_loop = Loop::new().expect("event loop");
 
-    if let Some(connection) = TcpConnection::ip4(&mut event_loop, "localhost", 1337, Connection::new()) {
+    let stdout = Stdout::new(&mut event_loop, ()).expect("stdout");
+    let stderr = Stderr::new(&mut event_loop, ()).expect("stderr");
+    if let Some(connection) = TcpConnection::ip4(&mut event_loop, "localhost", 1337, Connection::new(stdout, stderr)) {
         Stdin::new(&mut event_loop, StdinHandler::new(connection)).expect("stdin");
 
         event_loop.run().expect("run");
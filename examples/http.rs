@@ -11,13 +11,14 @@ use mini::aio::http::{
     DefaultHttpHandler,
     Http,
     HttpHandlerIgnoreErr,
+    HttpResponse,
 };
 
 use self::Msg::*;
 
 #[derive(Debug)]
 enum Msg {
-    HttpGet(Vec<u8>), // TODO: change to Request.
+    HttpGet(HttpResponse),
     HttpError(io::Error),
 }
 
@@ -29,8 +30,9 @@ impl Handler for HttpHandler {
 
     fn update(&mut self, _stream: &Stream<Msg>, msg: Self::Msg) {
         match msg {
-            HttpGet(body) => {
-                println!("{}", String::from_utf8_lossy(&body));
+            HttpGet(response) => {
+                println!("{} {}", response.status, response.reason);
+                println!("{}", String::from_utf8_lossy(&response.body));
             },
             HttpError(error) => {
                 eprintln!("Error: {}", error);
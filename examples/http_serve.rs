@@ -2,27 +2,52 @@ extern crate mini;
 
 use mini::aio::http_server::{
     self,
-    HttpHandler,
     Request,
+    Response,
+    Router,
 };
-use mini::aio::handler::Loop;
+use mini::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+};
+use mini::aio::net::ListenerMsg;
+use mini::aio::signal::Signal;
+
+fn page(request: &Request) -> Response {
+    let content = format!("You're on page {} and you queried {} via {}", request.param("page").unwrap_or(""),
+        request.query_string(), request.method);
+    Response::html(content)
+}
 
-#[derive(Clone)]
-struct Http {
+struct SignalHandler {
+    event_loop: Loop,
+    listener: Stream<ListenerMsg>,
 }
 
-impl HttpHandler for Http {
-    fn request(&mut self, request: &Request) -> String {
-        let content = format!("You're on page {} and you queried {} via {}", request.path, request.query_string,
-            request.method);
-        content
+impl Handler for SignalHandler {
+    type Msg = Signal;
+
+    fn update(&mut self, _stream: &Stream<Signal>, signal: Signal) {
+        eprintln!("Received {:?}, stopping.", signal);
+        self.listener.send(ListenerMsg::Dispose);
+        self.event_loop.stop();
     }
 }
 
 fn main() {
     let mut event_loop = Loop::new().expect("event loop");
 
-    http_server::serve(&mut event_loop, "127.0.0.1:1337", Http {}).expect("http serve");
+    let listener = http_server::serve(&mut event_loop, "127.0.0.1:1337", Router::new()
+        .get("/*page", page)
+        .post("/*page", page)).expect("http serve");
+
+    let signals = event_loop.spawn(SignalHandler {
+        event_loop: event_loop.clone(),
+        listener,
+    });
+    event_loop.add_signal(Signal::Interrupt, &signals, |signal| signal).expect("add signal");
+    event_loop.add_signal(Signal::Terminate, &signals, |signal| signal).expect("add signal");
 
     event_loop.run().expect("event loop run");
 }
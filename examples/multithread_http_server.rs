@@ -13,26 +13,18 @@ extern crate mini;
 
 use std::mem;
 use std::net;
-use std::os::unix::io::{
-    AsRawFd,
-    FromRawFd,
-    RawFd,
-};
-use std::sync::mpsc::{
-    SyncSender,
-    sync_channel,
-};
-use std::thread;
 
-use mini::aio::poll::Mode;
 use mini::aio::handler::Loop;
 use mini::aio::net::{
-    ListenerMsg::ReadEvent,
     TcpConnection,
     TcpConnectionNotify,
     TcpListener,
     TcpListenNotify,
 };
+use mini::aio::runtime::{
+    Runtime,
+    num_cpus,
+};
 
 struct Listener {
 }
@@ -50,41 +42,6 @@ impl TcpListenNotify for Listener {
     }
 }
 
-struct MasterListener {
-    senders: Vec<SyncSender<RawFd>>,
-}
-
-impl MasterListener {
-    fn new(senders: Vec<SyncSender<RawFd>>) -> Self {
-        Self {
-            senders,
-        }
-    }
-}
-
-impl TcpListenNotify for MasterListener {
-    fn listening(&mut self, listener: &net::TcpListener) {
-        for sender in &self.senders {
-            sender.send(listener.as_raw_fd()).expect("send");
-        }
-
-        match listener.local_addr() {
-            Ok(address) =>
-                println!("Listening on {}:{}.", address.ip(), address.port()),
-            Err(error) =>
-                eprintln!("Could not get local address: {}.", error),
-        }
-    }
-
-    fn not_listening(&mut self) {
-        eprintln!("Could not listen.");
-    }
-
-    fn connected(&mut self, _listener: &net::TcpListener) -> Box<dyn TcpConnectionNotify> {
-        Box::new(Server {})
-    }
-}
-
 struct Server {
 }
 
@@ -116,45 +73,24 @@ impl TcpConnectionNotify for Server {
 }
 
 fn main() {
-    const NUM_CPU: usize = 5 - 1;
-    let mut senders = vec![];
-
-    for cpu in 2..NUM_CPU {
-        let (sender, receiver) = sync_channel(1);
-        senders.push(sender);
-        thread::spawn(move || {
-            unsafe {
-                let tid = pthread_self();
-                let mut set: cpu_set_t = std::mem::zeroed();
-                CPU_SET(cpu, &mut set);
-                pthread_setaffinity_np(tid, std::mem::size_of::<cpu_set_t>(), &set);
-            }
-
-            let fd = receiver.recv().expect("recv");
-            println!("Fd: {}", fd);
-            let mut event_loop = Loop::new().expect("event loop");
-
-            let tcp_listener = unsafe { net::TcpListener::from_raw_fd(fd) };
-            let listener = TcpListener::new(tcp_listener, Listener::new(), &event_loop);
-            let stream = event_loop.spawn(listener);
-            event_loop.add_raw_fd(fd, Mode::Read, &stream, ReadEvent).expect("add raw fd");
-
-            event_loop.run().expect("event loop run");
-        });
-    }
-
-    unsafe {
-        let tid = pthread_self();
-        let mut set: cpu_set_t = std::mem::zeroed();
-        CPU_SET(1, &mut set);
-        pthread_setaffinity_np(tid, std::mem::size_of::<cpu_set_t>(), &set);
-    }
-
-    let mut event_loop = Loop::new().expect("event loop");
+    let tcp_listener = net::TcpListener::bind("127.0.0.1:1337").expect("bind");
+    let address = tcp_listener.local_addr().expect("local address");
+    println!("Listening on {}:{}.", address.ip(), address.port());
+
+    // NOTE: the loops only accept connections, so they do not receive messages.
+    let runtime = Runtime::<()>::new(num_cpus(), move |event_loop: &mut Loop, handle| {
+        unsafe {
+            let tid = pthread_self();
+            let mut set: cpu_set_t = std::mem::zeroed();
+            CPU_SET(handle.index(), &mut set);
+            pthread_setaffinity_np(tid, std::mem::size_of::<cpu_set_t>(), &set);
+        }
 
-    TcpListener::ip4(&mut event_loop, "127.0.0.1:1337", MasterListener::new(senders)).expect("listen");
+        TcpListener::shared(event_loop, &tcp_listener, Listener::new())?;
+        Ok(None)
+    }).expect("runtime");
 
-    event_loop.run().expect("event loop run");
+    runtime.join().expect("runtime join");
 }
 
 #[repr(C)]
@@ -0,0 +1,431 @@
+//! Asynchronous name resolution.
+//!
+//! The lookups are done in resolver threads, so that a slow DNS server does not stall the event
+//! loop, and the results are posted back to the loop.
+//! The names of the hosts file are resolved from it and the other names are queried from the name
+//! servers of `/etc/resolv.conf`, whose answers are cached for the TTL of their records.
+//! The names without a dot, which are completed with the search domains, and the names which
+//! cannot be queried from the name servers are resolved with the blocking `getaddrinfo()`, which
+//! does not give the TTL. The duration of the cache is bounded by `set_cache_ttl()`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec;
+
+use crate::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+    SyncStream,
+};
+use crate::rand::Rng;
+
+use self::LookupMsg::*;
+
+const CLASS_IN: u16 = 1;
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DNS_PORT: u16 = 53;
+const HOSTS_PATH: &str = "/etc/hosts";
+// Time to wait for the answers of a name server before querying the next one.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+const RESOLVER_THREADS: usize = 4;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+/// The resolved addresses of a host.
+pub type Addresses = vec::IntoIter<SocketAddr>;
+
+type Key = (String, u16);
+
+struct Query {
+    key: Key,
+    stream: SyncStream<LookupMsg>,
+}
+
+struct Resolver {
+    // The addresses of a host along with their expiration time.
+    cache: Mutex<HashMap<Key, (Instant, Vec<SocketAddr>)>>,
+    cache_ttl: Mutex<Duration>,
+    // The name servers to query instead of the ones of /etc/resolv.conf.
+    name_servers: Mutex<Option<Vec<SocketAddr>>>,
+    queries: Mutex<Sender<Query>>,
+    timeout: Mutex<Duration>,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        let (sender, receiver) = channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..RESOLVER_THREADS {
+            let receiver = receiver.clone();
+            thread::spawn(move || resolve_queries(&receiver));
+        }
+        Self {
+            cache: Mutex::new(HashMap::new()),
+            cache_ttl: Mutex::new(DEFAULT_CACHE_TTL),
+            name_servers: Mutex::new(None),
+            queries: Mutex::new(sender),
+            timeout: Mutex::new(DEFAULT_TIMEOUT),
+        }
+    }
+
+    fn cached(&self, key: &Key) -> Option<Vec<SocketAddr>> {
+        let mut cache = self.cache.lock().unwrap_or_else(|error| error.into_inner());
+        match cache.get(key) {
+            Some(&(expiration, ref addresses)) if expiration > Instant::now() => Some(addresses.clone()),
+            Some(_) => {
+                cache.remove(key);
+                None
+            },
+            None => None,
+        }
+    }
+
+    // Cache the addresses for their TTL, or for the maximum duration when they have none.
+    fn insert(&self, key: Key, addresses: &[SocketAddr], ttl: Option<Duration>) {
+        let max_ttl = *self.cache_ttl.lock().unwrap_or_else(|error| error.into_inner());
+        let ttl = ttl.map_or(max_ttl, |ttl| ttl.min(max_ttl));
+        if ttl == Duration::from_secs(0) {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap_or_else(|error| error.into_inner());
+        cache.retain(|_, &mut (expiration, _)| expiration > now);
+        cache.insert(key, (now + ttl, addresses.to_vec()));
+    }
+
+    fn name_servers(&self) -> Vec<SocketAddr> {
+        if let Some(ref name_servers) = *self.name_servers.lock().unwrap_or_else(|error| error.into_inner()) {
+            return name_servers.clone();
+        }
+        let resolv_conf = fs::read_to_string(RESOLV_CONF_PATH).unwrap_or_default();
+        resolv_conf.lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                if fields.next() != Some("nameserver") {
+                    return None;
+                }
+                let ip = fields.next()?.parse().ok()?;
+                Some(SocketAddr::new(ip, DNS_PORT))
+            })
+            .collect()
+    }
+}
+
+fn resolver() -> &'static Resolver {
+    static RESOLVER: OnceLock<Resolver> = OnceLock::new();
+    RESOLVER.get_or_init(Resolver::new)
+}
+
+fn resolve_queries(receiver: &Mutex<Receiver<Query>>) {
+    let mut rng = Rng::new();
+    loop {
+        let query = receiver.lock().unwrap_or_else(|error| error.into_inner()).recv();
+        let Query { key, stream } =
+            match query {
+                Ok(query) => query,
+                Err(_) => return,
+            };
+        let result = lookup(&key.0, key.1, &mut rng);
+        let result = result.map(|(addresses, ttl)| {
+            resolver().insert(key, &addresses, ttl);
+            addresses
+        });
+        stream.send(Resolved(result));
+    }
+}
+
+// Look up the addresses of `host`, with their TTL when they come from the name servers.
+fn lookup(host: &str, port: u16, rng: &mut Rng) -> io::Result<(Vec<SocketAddr>, Option<Duration>)> {
+    let with_port = |ips: Vec<IpAddr>| ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect();
+    if let Some(ips) = hosts_file_addresses(host) {
+        return Ok((with_port(ips), None));
+    }
+    if host.contains('.') {
+        match query_name_servers(host, rng) {
+            Ok((ips, ttl)) => return Ok((with_port(ips), Some(ttl))),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(error),
+            // NOTE: getaddrinfo() is used when the name servers cannot be queried.
+            Err(_) => (),
+        }
+    }
+    let addresses = (host, port).to_socket_addrs()?.collect();
+    Ok((addresses, None))
+}
+
+// The addresses of `host` in the hosts file, if it is there.
+fn hosts_file_addresses(host: &str) -> Option<Vec<IpAddr>> {
+    let hosts = fs::read_to_string(HOSTS_PATH).ok()?;
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let ips: Vec<IpAddr> = hosts.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+            let ip = fields.next()?.parse().ok()?;
+            fields.any(|name| name.eq_ignore_ascii_case(host)).then_some(ip)
+        })
+        .collect();
+    if ips.is_empty() {
+        return None;
+    }
+    Some(ips)
+}
+
+// Query the A and AAAA records of `host` from the first name server which answers, giving the
+// addresses with the smallest TTL of the records of the answers.
+fn query_name_servers(host: &str, rng: &mut Rng) -> io::Result<(Vec<IpAddr>, Duration)> {
+    let id = rng.gen_int() as u16;
+    let queries = [encode_query(id, host, TYPE_A)?, encode_query(id ^ 1, host, TYPE_AAAA)?];
+    let mut last_error = io::Error::new(io::ErrorKind::NotConnected, "no name server");
+    for name_server in resolver().name_servers() {
+        match query_name_server(name_server, &queries) {
+            Ok(result) => return Ok(result),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(error),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+fn query_name_server(name_server: SocketAddr, queries: &[Vec<u8>; 2]) -> io::Result<(Vec<IpAddr>, Duration)> {
+    let local_address =
+        match name_server {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        };
+    let socket = UdpSocket::bind(local_address)?;
+    socket.connect(name_server)?;
+    for query in queries {
+        socket.send(query)?;
+    }
+
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut answers = [None, None];
+    let mut buffer = [0; 512];
+    while answers.iter().any(Option::is_none) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "the name server did not answer"));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let size =
+            match socket.recv(&mut buffer) {
+                Ok(size) => size,
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::Interrupted =>
+                    continue,
+                Err(error) => return Err(error),
+            };
+        let response = &buffer[..size];
+        for (answer, query) in answers.iter_mut().zip(queries) {
+            // NOTE: the responses which do not match a query are ignored, like the ones which
+            // cannot be parsed since anyone can send them.
+            if answer.is_none() && response.get(..2) == Some(&query[..2]) {
+                match parse_response(response, query) {
+                    Err(ref error) if error.kind() == io::ErrorKind::InvalidData => (),
+                    result => *answer = Some(result?),
+                }
+            }
+        }
+    }
+
+    let mut ips = vec![];
+    let mut ttl = None;
+    // NOTE: the IPv6 addresses come first, like with getaddrinfo().
+    for (answer_ips, answer_ttl) in answers.iter_mut().rev().flatten() {
+        ips.append(answer_ips);
+        if let Some(answer_ttl) = *answer_ttl {
+            ttl = Some(ttl.map_or(answer_ttl, |ttl: u32| ttl.min(answer_ttl)));
+        }
+    }
+    match ttl {
+        Some(ttl) if !ips.is_empty() => Ok((ips, Duration::from_secs(u64::from(ttl)))),
+        _ => Err(io::Error::new(io::ErrorKind::NotFound, "no address for the host")),
+    }
+}
+
+fn encode_query(id: u16, host: &str, record_type: u16) -> io::Result<Vec<u8>> {
+    let invalid_host = || io::Error::new(io::ErrorKind::InvalidInput, "invalid host name");
+    let name = host.strip_suffix('.').unwrap_or(host);
+    if name.is_empty() || name.len() > 253 {
+        return Err(invalid_host());
+    }
+    let mut query = id.to_be_bytes().to_vec();
+    // Recursion desired, with one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 ||
+            !label.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        {
+            return Err(invalid_host());
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+// Parse the response to `query`, giving the addresses of the answers and the smallest TTL of their
+// records, if any.
+fn parse_response(response: &[u8], query: &[u8]) -> io::Result<(Vec<IpAddr>, Option<u32>)> {
+    let invalid_response = || io::Error::new(io::ErrorKind::InvalidData, "invalid DNS response");
+    // NOTE: the query is a header followed by the question, which must be the one of the response.
+    let flags = read_u16(response, 2).ok_or_else(invalid_response)?;
+    if flags & 0x8000 == 0 || read_u16(response, 4) != Some(1) || response.get(12..query.len()) != Some(&query[12..]) {
+        return Err(invalid_response());
+    }
+    if flags & 0x0200 != 0 {
+        return Err(io::Error::other("truncated DNS response"));
+    }
+    match flags & 0x000F {
+        0 => (),
+        3 => return Err(io::Error::new(io::ErrorKind::NotFound, "host not found")),
+        _ => return Err(io::Error::other("DNS server failure")),
+    }
+
+    let answer_count = read_u16(response, 6).ok_or_else(invalid_response)?;
+    let mut ips = vec![];
+    let mut ttl: Option<u32> = None;
+    let mut position = query.len();
+    for _ in 0..answer_count {
+        position = skip_name(response, position).ok_or_else(invalid_response)?;
+        let record_type = read_u16(response, position).ok_or_else(invalid_response)?;
+        let class = read_u16(response, position + 2).ok_or_else(invalid_response)?;
+        let record_ttl = read_u32(response, position + 4).ok_or_else(invalid_response)?;
+        let length = usize::from(read_u16(response, position + 8).ok_or_else(invalid_response)?);
+        let data = response.get(position + 10..position + 10 + length).ok_or_else(invalid_response)?;
+        position += 10 + length;
+        if class != CLASS_IN {
+            continue;
+        }
+        // NOTE: the TTL of the CNAME records also bounds the duration of the cache.
+        ttl = Some(ttl.map_or(record_ttl, |ttl| ttl.min(record_ttl)));
+        match (record_type, data.len()) {
+            (TYPE_A, 4) => ips.push(IpAddr::from([data[0], data[1], data[2], data[3]])),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                ips.push(IpAddr::from(octets));
+            },
+            _ => (),
+        }
+    }
+    Ok((ips, ttl))
+}
+
+fn read_u16(message: &[u8], position: usize) -> Option<u16> {
+    let bytes = message.get(position..position + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(message: &[u8], position: usize) -> Option<u32> {
+    let bytes = message.get(position..position + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Skip the possibly compressed name at `position`, giving the position following it.
+fn skip_name(message: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(position)?;
+        match length & 0xC0 {
+            0 if length == 0 => return Some(position + 1),
+            0 => position += 1 + usize::from(length),
+            0xC0 if position + 2 <= message.len() => return Some(position + 2),
+            _ => return None,
+        }
+    }
+}
+
+enum LookupMsg {
+    Resolved(io::Result<Vec<SocketAddr>>),
+    Timeout,
+}
+
+// Call the callback of a lookup with the result sent by the resolver thread, or with an error
+// when the timeout expires first.
+struct Lookup<CALLBACK> {
+    callback: Option<CALLBACK>,
+}
+
+impl<CALLBACK> Handler for Lookup<CALLBACK>
+where CALLBACK: FnOnce(io::Result<Addresses>),
+{
+    type Msg = LookupMsg;
+
+    fn update(&mut self, stream: &Stream<Self::Msg>, msg: Self::Msg) {
+        let result =
+            match msg {
+                Resolved(result) => result,
+                Timeout => Err(io::Error::new(io::ErrorKind::TimedOut, "the name resolution timed out")),
+            };
+        if let Some(callback) = self.callback.take() {
+            callback(result.map(Vec::into_iter));
+        }
+        stream.stop();
+    }
+}
+
+/// Resolve the addresses of `host` and call `callback` with them.
+///
+/// The callback is called right away when `host` is an IP address or when its addresses are
+/// cached, otherwise it is called from the event loop once a resolver thread looked them up, or
+/// with a `TimedOut` error when this takes longer than the timeout set by `set_timeout()`.
+pub fn resolve<CALLBACK>(event_loop: &mut Loop, host: &str, port: u16, callback: CALLBACK)
+where CALLBACK: FnOnce(io::Result<Addresses>) + 'static,
+{
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        callback(Ok(vec![SocketAddr::new(ip, port)].into_iter()));
+        return;
+    }
+    let key = (host.to_string(), port);
+    if let Some(addresses) = resolver().cached(&key) {
+        callback(Ok(addresses.into_iter()));
+        return;
+    }
+    let stream = event_loop.spawn(Lookup {
+        callback: Some(callback),
+    });
+    let timeout = *resolver().timeout.lock().unwrap_or_else(|error| error.into_inner());
+    if let Err(error) = event_loop.set_timeout(timeout, &stream, Timeout) {
+        stream.send(Resolved(Err(error)));
+        return;
+    }
+    let query = Query {
+        key,
+        stream: event_loop.sync_stream(&stream),
+    };
+    let sent = resolver().queries.lock().unwrap_or_else(|error| error.into_inner()).send(query);
+    if sent.is_err() {
+        stream.send(Resolved(Err(io::Error::other("the resolver threads stopped"))));
+    }
+}
+
+/// Set the maximum duration during which the resolved addresses are cached: the addresses from
+/// the name servers are cached for the TTL of their records, up to this duration. A duration of
+/// zero disables the cache.
+pub fn set_cache_ttl(ttl: Duration) {
+    *resolver().cache_ttl.lock().unwrap_or_else(|error| error.into_inner()) = ttl;
+    if ttl == Duration::from_secs(0) {
+        resolver().cache.lock().unwrap_or_else(|error| error.into_inner()).clear();
+    }
+}
+
+/// Query `name_servers` instead of the name servers of `/etc/resolv.conf`.
+pub fn set_name_servers(name_servers: Vec<SocketAddr>) {
+    *resolver().name_servers.lock().unwrap_or_else(|error| error.into_inner()) = Some(name_servers);
+}
+
+/// Set the duration after which `resolve()` gives up with a `TimedOut` error. The default is 10
+/// seconds.
+pub fn set_timeout(timeout: Duration) {
+    *resolver().timeout.lock().unwrap_or_else(|error| error.into_inner()) = timeout;
+}
@@ -1,5 +1,5 @@
-use std::cell::RefCell;
-use std::collections::VecDeque;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
 use std::io;
 use std::mem;
 use std::os::unix::io::{
@@ -7,6 +7,9 @@ use std::os::unix::io::{
     RawFd,
 };
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use crate::aio::poll::{
     self,
@@ -14,33 +17,43 @@ use crate::aio::poll::{
     EpollResult,
     EventLoop,
     Mode,
+    SignalWatch,
+    Timer,
+    Waker,
     event_list,
 };
 use crate::aio::poll::ffi::epoll_event;
+use crate::aio::signal::Signal;
 use crate::aio::slab::Slab;
 
 pub struct Stream<MSG> {
     elements: Rc<RefCell<VecDeque<MSG>>>,
     entry: usize,
     registered_entries: Rc<RefCell<Vec<usize>>>,
+    stopped: Rc<Cell<bool>>,
+    stopped_entries: Rc<RefCell<Vec<usize>>>,
 }
 
 impl<MSG> Clone for Stream<MSG> {
     fn clone(&self) -> Self {
         Self {
             elements: self.elements.clone(),
-            entry: self.entry.clone(),
+            entry: self.entry,
             registered_entries: self.registered_entries.clone(),
+            stopped: self.stopped.clone(),
+            stopped_entries: self.stopped_entries.clone(),
         }
     }
 }
 
 impl<MSG> Stream<MSG> {
-    fn new(registered_entries: Rc<RefCell<Vec<usize>>>, entry: usize) -> Self {
+    fn new(registered_entries: Rc<RefCell<Vec<usize>>>, stopped_entries: Rc<RefCell<Vec<usize>>>, entry: usize) -> Self {
         Self {
             elements: Rc::new(RefCell::new(VecDeque::new())),
             entry,
             registered_entries,
+            stopped: Rc::new(Cell::new(false)),
+            stopped_entries,
         }
     }
 
@@ -48,16 +61,83 @@ impl<MSG> Stream<MSG> {
         self.elements.borrow_mut().pop_front()
     }
 
+    /// Send a message to the handler. The message is dropped if the handler was stopped.
     pub fn send(&self, msg: MSG) {
+        if self.stopped.get() {
+            return;
+        }
         self.elements.borrow_mut().push_back(msg);
         self.registered_entries.borrow_mut().push(self.entry);
         EventLoop::wakeup();
     }
+
+    /// Stop the handler: the pending messages are dropped, the fds and timers registered with
+    /// this stream are removed from the event loop and the handler itself is dropped after its
+    /// `stopped()` method was called.
+    pub fn stop(&self) {
+        if !self.stopped.replace(true) {
+            self.elements.borrow_mut().clear();
+            self.stopped_entries.borrow_mut().push(self.entry);
+            EventLoop::wakeup();
+        }
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped.get()
+    }
+}
+
+struct SyncQueue<MSG> {
+    elements: Mutex<VecDeque<MSG>>,
+    pending: AtomicBool,
+    stopped: AtomicBool,
+}
+
+/// A handle to send messages to a `Stream` from another thread.
+///
+/// It is created with `Loop::sync_stream` and wakes up the event loop of the stream when a
+/// message is sent.
+pub struct SyncStream<MSG> {
+    queue: Arc<SyncQueue<MSG>>,
+    waker: Waker,
+}
+
+impl<MSG> Clone for SyncStream<MSG> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+            waker: self.waker,
+        }
+    }
+}
+
+impl<MSG> SyncStream<MSG> {
+    /// Send a message to the handler. The message is dropped if the handler was stopped.
+    pub fn send(&self, msg: MSG) {
+        if self.queue.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        self.queue.elements.lock().unwrap_or_else(|error| error.into_inner()).push_back(msg);
+        self.queue.pending.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.queue.stopped.load(Ordering::SeqCst)
+    }
 }
 
 pub trait Handler {
     type Msg;
 
+    /// Called when the handler is spawned.
+    fn started(&mut self, _stream: &Stream<Self::Msg>) {
+    }
+
+    /// Called when the handler is removed from the event loop after its stream was stopped.
+    fn stopped(&mut self) {
+    }
+
     fn update(&mut self, stream: &Stream<Self::Msg>, msg: Self::Msg);
 }
 
@@ -68,6 +148,7 @@ struct Component<HANDLER: Handler<Msg=MSG>, MSG> {
 
 trait Callable {
     fn process(&mut self);
+    fn stopped(&mut self);
 }
 
 struct NotCallable;
@@ -76,20 +157,44 @@ impl Callable for NotCallable {
     fn process(&mut self) {
         panic!("Not callable");
     }
+
+    fn stopped(&mut self) {
+    }
 }
 
 impl<HANDLER: Handler<Msg=MSG>, MSG> Callable for Component<HANDLER, MSG> {
     fn process(&mut self) {
-        while let Some(msg) = self.stream.pop() {
-            self.handler.update(&self.stream, msg);
+        while !self.stream.stopped() {
+            match self.stream.pop() {
+                Some(msg) => self.handler.update(&self.stream, msg),
+                None => break,
+            }
         }
     }
+
+    fn stopped(&mut self) {
+        self.handler.stopped();
+    }
+}
+
+// Event loop resources associated with a handler, removed when it is stopped.
+#[derive(Default)]
+struct Resources {
+    // The fd along with the identifier of its registration in the event loop, to avoid removing
+    // an fd that was closed and reused by someone else.
+    fds: Vec<(RawFd, u64)>,
+    signals: Vec<SignalWatch>,
+    timers: Vec<Timer>,
 }
 
 struct Inner {
     handlers: Slab<Box<dyn Callable>>,
     registered_entries: Rc<RefCell<Vec<usize>>>,
+    // Move the messages of the sync streams to their stream. Returns false when the stream is stopped.
+    remote_forwarders: Vec<Box<dyn FnMut() -> bool>>,
+    resources: HashMap<usize, Resources>,
     stopped: bool,
+    stopped_entries: Rc<RefCell<Vec<usize>>>,
 }
 
 #[derive(Clone)]
@@ -105,7 +210,10 @@ impl Loop {
             inner: Rc::new(RefCell::new(Inner {
                 handlers: Slab::new(),
                 registered_entries: Rc::new(RefCell::new(vec![])),
+                remote_forwarders: vec![],
+                resources: HashMap::new(),
                 stopped: false,
+                stopped_entries: Rc::new(RefCell::new(vec![])),
             })),
         })
     }
@@ -121,35 +229,68 @@ impl Loop {
     where CALLBACK: Fn(epoll_event) -> MSG + 'static,
           MSG: 'static,
     {
+        let entry = stream.entry;
         let stream = stream.clone();
         self.event_loop.add_raw_fd(fd, mode, move |event| {
             stream.send(callback(event));
             Action::Continue
-        })
+        })?;
+        self.track_fd(entry, fd);
+        Ok(())
+    }
+
+    /// Send the message created by `callback` to `stream` every time `signal` is received.
+    ///
+    /// See `EventLoop::add_signal` for the caveats about threads. The signal is unwatched when the
+    /// handler of `stream` is stopped.
+    pub fn add_signal<CALLBACK, MSG>(&self, signal: Signal, stream: &Stream<MSG>, callback: CALLBACK) -> io::Result<SignalWatch>
+    where CALLBACK: Fn(Signal) -> MSG + 'static,
+          MSG: 'static,
+    {
+        let entry = stream.entry;
+        let stream = stream.clone();
+        let watch = self.event_loop.add_signal(signal, move |signal| stream.send(callback(signal)))?;
+        let mut inner = self.inner.borrow_mut();
+        let resources = inner.resources.entry(entry).or_default();
+        resources.signals.retain(SignalWatch::is_active);
+        resources.signals.push(watch.clone());
+        Ok(watch)
+    }
+
+    pub fn cancel_timer(&self, timer: &Timer) -> io::Result<()> {
+        self.event_loop.cancel_timer(timer)
     }
 
     pub fn event_loop(&self) -> &EventLoop {
         &self.event_loop
     }
 
-    pub fn spawn<HANDLER, MSG>(&mut self, handler: HANDLER) -> Stream<MSG>
+    pub fn spawn<HANDLER, MSG>(&mut self, mut handler: HANDLER) -> Stream<MSG>
     where HANDLER: Handler<Msg=MSG> + 'static,
           MSG: 'static,
     {
-        let mut inner = self.inner.borrow_mut();
-        let registered_entries = inner.registered_entries.clone();
-        let handlers = &mut inner.handlers;
-        let entry = handlers.vacant_entry();
-        let stream = Stream::new(registered_entries, entry.key());
-        entry.insert(Box::new(Component {
+        let stream = {
+            let mut inner = self.inner.borrow_mut();
+            let registered_entries = inner.registered_entries.clone();
+            let stopped_entries = inner.stopped_entries.clone();
+            // NOTE: Reserve the entry because handlers can be added in the started() method.
+            let entry = inner.handlers.insert(Box::new(NotCallable));
+            Stream::new(registered_entries, stopped_entries, entry)
+        };
+        handler.started(&stream);
+        self.inner.borrow_mut().handlers[stream.entry] = Box::new(Component {
             handler,
             stream: stream.clone(),
-        }));
-        // TODO: think about how to remove the components.
+        });
         stream
     }
 
     pub fn iterate(&mut self, event_list: &mut [epoll_event]) -> EpollResult {
+        // NOTE: Take the forwarders because sync streams can be created in the update() method.
+        let mut remote_forwarders = mem::take(&mut self.inner.borrow_mut().remote_forwarders);
+        remote_forwarders.retain_mut(|forward| forward());
+        self.inner.borrow_mut().remote_forwarders.extend(remote_forwarders);
+
         let registered_entries = mem::replace(&mut *self.inner.borrow().registered_entries.borrow_mut(), vec![]);
         for entry in registered_entries {
             if self.inner.borrow().handlers.contains(entry) {
@@ -159,9 +300,40 @@ impl Loop {
                 self.inner.borrow_mut().handlers[entry] = handler;
             }
         }
+        let stopped_entries = mem::take(&mut *self.inner.borrow().stopped_entries.borrow_mut());
+        for entry in stopped_entries {
+            self.remove_handler(entry);
+        }
         self.event_loop.iterate(event_list)
     }
 
+    fn remove_handler(&mut self, entry: usize) {
+        let (handler, resources) = {
+            let mut inner = self.inner.borrow_mut();
+            if !inner.handlers.contains(entry) {
+                return;
+            }
+            (inner.handlers.remove(entry), inner.resources.remove(&entry))
+        };
+        if let Some(resources) = resources {
+            for (fd, registration_id) in resources.fds {
+                if self.event_loop.registration_id(fd) == Some(registration_id) {
+                    let _ = self.event_loop.remove_raw_fd(fd);
+                }
+            }
+            for watch in resources.signals {
+                let _ = self.event_loop.remove_signal(&watch);
+            }
+            for timer in resources.timers {
+                let _ = self.event_loop.cancel_timer(&timer);
+            }
+        }
+        // NOTE: the handler is called after the inner is released because it can spawn or stop
+        // other handlers.
+        let mut handler = handler;
+        handler.stopped();
+    }
+
     pub fn remove_fd<A: AsRawFd>(&self, as_fd: &A) -> io::Result<()> {
         self.event_loop.remove_fd(as_fd)
     }
@@ -170,12 +342,16 @@ impl Loop {
         self.event_loop.remove_raw_fd(fd)
     }
 
+    pub fn remove_signal(&self, watch: &SignalWatch) -> io::Result<()> {
+        self.event_loop.remove_signal(watch)
+    }
+
     pub fn run(&mut self) -> io::Result<()> {
         let mut event_list = event_list();
 
         while !self.inner.borrow().stopped {
             match self.iterate(&mut event_list) {
-                // Restart if interrupted by signal.
+                // NOTE: see EventLoop::run().
                 EpollResult::Interrupted => continue,
                 EpollResult::Error(error) => return Err(error),
                 EpollResult::Ok => (),
@@ -185,32 +361,112 @@ impl Loop {
         Ok(())
     }
 
+    /// Send `msg` to `stream` every `duration` until the timer is cancelled.
+    pub fn set_interval<MSG>(&self, duration: Duration, stream: &Stream<MSG>, msg: MSG) -> io::Result<Timer>
+    where MSG: Clone + 'static,
+    {
+        let entry = stream.entry;
+        let stream = stream.clone();
+        let timer = self.event_loop.add_interval(duration, move || stream.send(msg.clone()))?;
+        self.track_timer(entry, &timer);
+        Ok(timer)
+    }
+
+    /// Send `msg` to `stream` once after `duration`, unless the timer is cancelled before.
+    pub fn set_timeout<MSG>(&self, duration: Duration, stream: &Stream<MSG>, msg: MSG) -> io::Result<Timer>
+    where MSG: 'static,
+    {
+        let entry = stream.entry;
+        let stream = stream.clone();
+        let timer = self.event_loop.add_timeout(duration, move || stream.send(msg))?;
+        self.track_timer(entry, &timer);
+        Ok(timer)
+    }
+
     pub fn stop(&mut self) {
         self.inner.borrow_mut().stopped = true;
         EventLoop::wakeup();
     }
 
+    /// Create a handle to send messages to `stream` from other threads.
+    ///
+    /// The messages are forwarded until the stream is stopped or the handle and all its clones are
+    /// dropped.
+    pub fn sync_stream<MSG>(&self, stream: &Stream<MSG>) -> SyncStream<MSG>
+    where MSG: Send + 'static,
+    {
+        let queue = Arc::new(SyncQueue {
+            elements: Mutex::new(VecDeque::new()),
+            pending: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+        });
+        let forwarder_queue = queue.clone();
+        let stream = stream.clone();
+        self.inner.borrow_mut().remote_forwarders.push(Box::new(move || {
+            if stream.stopped() {
+                forwarder_queue.stopped.store(true, Ordering::SeqCst);
+                forwarder_queue.elements.lock().unwrap_or_else(|error| error.into_inner()).clear();
+                return false;
+            }
+            // NOTE: check if the sync streams were all dropped before forwarding, so that the
+            // messages they sent right before being dropped are not lost.
+            let dropped = Arc::strong_count(&forwarder_queue) == 1;
+            if forwarder_queue.pending.swap(false, Ordering::SeqCst) {
+                let elements = mem::take(&mut *forwarder_queue.elements.lock().unwrap_or_else(|error| error.into_inner()));
+                for element in elements {
+                    stream.send(element);
+                }
+            }
+            !dropped
+        }));
+        SyncStream {
+            queue,
+            waker: self.event_loop.waker(),
+        }
+    }
+
+    fn track_fd(&self, entry: usize, fd: RawFd) {
+        if let Some(registration_id) = self.event_loop.registration_id(fd) {
+            let mut inner = self.inner.borrow_mut();
+            let resources = inner.resources.entry(entry).or_default();
+            let event_loop = &self.event_loop;
+            resources.fds.retain(|&(fd, registration_id)| event_loop.registration_id(fd) == Some(registration_id));
+            resources.fds.push((fd, registration_id));
+        }
+    }
+
+    fn track_timer(&self, entry: usize, timer: &Timer) {
+        let mut inner = self.inner.borrow_mut();
+        let resources = inner.resources.entry(entry).or_default();
+        resources.timers.retain(Timer::is_active);
+        resources.timers.push(timer.clone());
+    }
+
     pub fn try_add_fd<A: AsRawFd>(&self, as_fd: &A, mode: Mode) -> io::Result<Event> {
         self.try_add_raw_fd(as_fd.as_raw_fd(), mode)
     }
 
     pub fn try_add_raw_fd(&self, fd: RawFd, mode: Mode) -> io::Result<Event> {
-        Ok(Event::new(self.event_loop.try_add_raw_fd(fd, mode)?))
+        Ok(Event::new(self.event_loop.try_add_raw_fd(fd, mode)?, fd, self))
     }
 
     pub fn try_add_raw_fd_oneshot(&self, fd: RawFd, mode: Mode) -> io::Result<EventOnce> {
-        Ok(EventOnce::new(self.event_loop.try_add_raw_fd_oneshot(fd, mode)?))
+        Ok(EventOnce::new(self.event_loop.try_add_raw_fd_oneshot(fd, mode)?, fd, self))
     }
 }
 
 pub struct Event {
     event: poll::Event,
+    event_loop: Loop,
+    fd: RawFd,
 }
 
 impl Event {
-    fn new(event: poll::Event) -> Self {
+    fn new(event: poll::Event, fd: RawFd, event_loop: &Loop) -> Self {
         Self {
             event,
+            event_loop: event_loop.clone(),
+            fd,
         }
     }
 
@@ -218,6 +474,7 @@ impl Event {
     where CALLBACK: Fn(epoll_event) -> MSG + 'static,
           MSG: 'static,
     {
+        self.event_loop.track_fd(stream.entry, self.fd);
         let stream = stream.clone();
         self.event.set_callback(move |event| {
             stream.send(callback(event));
@@ -228,12 +485,16 @@ impl Event {
 
 pub struct EventOnce {
     event: poll::EventOnce,
+    event_loop: Loop,
+    fd: RawFd,
 }
 
 impl EventOnce {
-    fn new(event: poll::EventOnce) -> Self {
+    fn new(event: poll::EventOnce, fd: RawFd, event_loop: &Loop) -> Self {
         Self {
             event,
+            event_loop: event_loop.clone(),
+            fd,
         }
     }
 
@@ -241,7 +502,54 @@ impl EventOnce {
     where CALLBACK: FnOnce(epoll_event) -> MSG + 'static,
           MSG: 'static,
     {
+        self.event_loop.track_fd(stream.entry, self.fd);
         let stream = stream.clone();
         self.event.set_callback(move |event| stream.send(callback(event)));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::aio::poll::event_list;
+    use super::{Handler, Loop, Stream};
+
+    struct Values {
+        values: Rc<RefCell<Vec<u32>>>,
+    }
+
+    impl Handler for Values {
+        type Msg = u32;
+
+        fn update(&mut self, _stream: &Stream<u32>, msg: u32) {
+            self.values.borrow_mut().push(msg);
+        }
+    }
+
+    #[test]
+    fn test_sync_stream_dropped() {
+        let mut event_loop = Loop::new().expect("event loop");
+        let values = Rc::new(RefCell::new(vec![]));
+        let stream = event_loop.spawn(Values {
+            values: values.clone(),
+        });
+        let mut event_list = event_list();
+
+        let sync_stream = event_loop.sync_stream(&stream);
+        let other_sync_stream = event_loop.sync_stream(&stream);
+        sync_stream.send(1);
+        drop(sync_stream);
+        let _ = event_loop.iterate(&mut event_list);
+        // The message sent before dropping the sync stream is still delivered.
+        assert_eq!(*values.borrow(), vec![1]);
+        assert_eq!(event_loop.inner.borrow().remote_forwarders.len(), 1);
+
+        drop(other_sync_stream);
+        stream.send(2);
+        let _ = event_loop.iterate(&mut event_list);
+        assert_eq!(*values.borrow(), vec![1, 2]);
+        assert!(event_loop.inner.borrow().remote_forwarders.is_empty());
+    }
+}
@@ -1,11 +1,13 @@
 // TODO: make a web crawler example.
 
 use std::cell::RefCell;
-use std::collections::VecDeque;
-use std::fmt::Debug;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::io;
 use std::mem;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
 
 use crate::aio::handler::{
     Handler,
@@ -13,130 +15,790 @@ use crate::aio::handler::{
     Stream,
 };
 use crate::aio::net::{
+    Idle,
     TcpConnection,
     TcpConnectionNotify,
 };
-use crate::aio::uhttp_uri::HttpUri;
+use crate::aio::http_parser::ResponseParser;
+use crate::aio::http_server::Limits;
+use crate::aio::uhttp_uri::{HttpScheme, HttpUri};
+use crate::aio::uri::Uri;
+pub use crate::aio::uhttp_uri::{
+    Query,
+    form_decode,
+    form_encode,
+    percent_decode,
+    percent_encode,
+};
+use crate::rand::Rng;
 
 use self::Msg::*;
 
-fn deque_compare(buffer: &VecDeque<u8>, start: usize, len: usize, value: &[u8]) -> bool {
-    if value.len() < len {
-        return false;
+/// Configuration of the connection pool of the client.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolConfig {
+    /// Time after which an idle connection is closed.
+    pub idle_timeout: Option<Duration>,
+    /// Maximum number of connections open to a host. The requests are queued when it is reached.
+    pub max_connections_per_host: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Some(Duration::from_secs(30)),
+            max_connections_per_host: 8,
+        }
     }
-    let mut index = 0;
-    for i in start..start + len {
-        if buffer[i] != value[index] {
-            return false;
+}
+
+// Host and port of the server.
+type HostKey = (String, u16);
+
+/// Retry policy for the connection failures and the 5xx responses to idempotent requests.
+///
+/// The delay before a retry is doubled after every retry, up to `max_delay`, and a random jitter
+/// of up to half the delay is subtracted from it.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_retries: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            max_retries: 3,
         }
-        index += 1;
     }
-    true
 }
 
-fn parse_num(buffer: &VecDeque<u8>, start: usize, len: usize) -> Option<usize> {
-    let mut result = 0;
-    for i in start..start + len {
-        if buffer[i] >= b'0' && buffer[i] <= b'9' {
-            result *= 10;
-            result += (buffer[i] - b'0') as usize;
+// Request waiting for a connection or for its response.
+struct Exchange {
+    // Set when the connection is closed after the response.
+    close: bool,
+    // Request data waiting to be sent.
+    data: Vec<u8>,
+    handler: Box<dyn HttpHandler>,
+    key: HostKey,
+    limits: Limits,
+    max_redirects: usize,
+    parser: ResponseParser,
+    redirects: usize,
+    request: HttpRequest,
+    retries: u32,
+    retry_policy: Option<RetryPolicy>,
+    // Set while the request is sent on a connection which was idle and no response data was
+    // received: the server could have closed the connection before receiving the request.
+    reused: bool,
+}
+
+impl Exchange {
+    fn new(request: HttpRequest, handler: Box<dyn HttpHandler>, limits: Limits, max_redirects: usize,
+        retry_policy: Option<RetryPolicy>) -> io::Result<Self>
+    {
+        let mut exchange = Self {
+            close: false,
+            data: vec![],
+            handler,
+            key: (String::new(), 0),
+            limits,
+            max_redirects,
+            parser: ResponseParser::new(false, limits),
+            redirects: 0,
+            request: HttpRequest::get(""),
+            retries: 0,
+            retry_policy,
+            reused: false,
+        };
+        exchange.set_request(request)?;
+        Ok(exchange)
+    }
+
+    fn set_request(&mut self, request: HttpRequest) -> io::Result<()> {
+        let uri = HttpUri::new(&request.uri)
+            .map_err(|()| io::Error::new(io::ErrorKind::InvalidInput, "invalid uri"))?;
+        if uri.scheme != HttpScheme::Http {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "unsupported scheme"));
         }
-        else if result != 0 && buffer[i] != b' ' {
-            return None;
+        self.close = request.headers.has_token("Connection", "close");
+        self.data = request.to_bytes(&uri)?;
+        self.key = (uri.host.to_string(), uri.port);
+        self.parser = ResponseParser::new(request.method == Method::Head, self.limits);
+        self.request = request;
+        Ok(())
+    }
+}
+
+// Exchange in progress on a connection, None while the connection is idle.
+type Slot = Rc<RefCell<Option<Exchange>>>;
+
+#[derive(Default)]
+struct Host {
+    // Number of connections open or being opened.
+    connections: usize,
+    idle: Vec<(TcpConnection, Slot)>,
+    queue: VecDeque<Exchange>,
+}
+
+struct Pool {
+    config: PoolConfig,
+    hosts: HashMap<HostKey, Host>,
+    rng: Rng,
+}
+
+// Send the request on an idle connection, on a new connection or queue it when the host has too
+// many connections.
+fn dispatch(pool: &Rc<RefCell<Pool>>, event_loop: &mut Loop, key: HostKey, exchange: Exchange) {
+    let mut pool_ref = pool.borrow_mut();
+    let max_connections = pool_ref.config.max_connections_per_host;
+    let host = pool_ref.hosts.entry(key.clone()).or_default();
+    if let Some((connection, slot)) = host.idle.pop() {
+        drop(pool_ref);
+        start(pool, event_loop, &connection, &slot, exchange, true);
+    }
+    else if host.connections < max_connections {
+        host.connections += 1;
+        drop(pool_ref);
+        connect(pool, event_loop, key, exchange);
+    }
+    else {
+        host.queue.push_back(exchange);
+    }
+}
+
+// Open a new connection for the exchange, which is counted in the connections of the host.
+fn connect(pool: &Rc<RefCell<Pool>>, event_loop: &mut Loop, key: HostKey, exchange: Exchange) {
+    let (host, port) = key.clone();
+    let connection = Connection::new(pool, event_loop, key, exchange);
+    // NOTE: the connection errors are sent to the Connection.
+    TcpConnection::connect(event_loop, &host, port, connection);
+}
+
+fn start(pool: &Rc<RefCell<Pool>>, event_loop: &mut Loop, connection: &TcpConnection, slot: &Slot, mut exchange: Exchange,
+    reused: bool)
+{
+    let data = mem::take(&mut exchange.data);
+    exchange.reused = reused;
+    *slot.borrow_mut() = Some(exchange);
+    if let Err(error) = connection.write(data) {
+        connection.dispose();
+        let exchange = slot.borrow_mut().take();
+        if let Some(exchange) = exchange {
+            complete(pool, event_loop, exchange, Err(error));
         }
     }
-    Some(result)
 }
 
-fn parse_headers(buffer: &VecDeque<u8>) -> Option<usize> {
-    // TODO: parse other headers.
-    let mut start = 0;
-    for i in 0..buffer.len() {
-        if buffer[i] == b'\n' {
-            let text = b"Content-Length:";
-            let end = start + text.len();
-            if deque_compare(buffer, start, text.len(), text) {
-                let num = parse_num(buffer, end, i - 1 - end); // - 1 to remove the \n.
-                return num;
+// Follow the redirection or retry the request when needed, otherwise send the result to the
+// handler.
+fn complete(pool: &Rc<RefCell<Pool>>, event_loop: &mut Loop, mut exchange: Exchange, result: io::Result<HttpResponse>) {
+    let result =
+        match result {
+            Ok(response) =>
+                match redirection(&exchange, &response) {
+                    Some(Ok(request)) => {
+                        exchange.redirects += 1;
+                        resend(pool, event_loop, exchange, request, None);
+                        return;
+                    },
+                    Some(Err(error)) => Err(error),
+                    None => Ok(response),
+                },
+            Err(error) => Err(error),
+        };
+    if let Some(delay) = retry_delay(pool, &exchange, &result) {
+        exchange.retries += 1;
+        let request = exchange.request.clone();
+        resend(pool, event_loop, exchange, request, Some(delay));
+        return;
+    }
+    match result {
+        Ok(response) => exchange.handler.response(response),
+        Err(error) => exchange.handler.error(error),
+    }
+}
+
+fn resend(pool: &Rc<RefCell<Pool>>, event_loop: &mut Loop, mut exchange: Exchange, request: HttpRequest, delay: Option<Duration>) {
+    if let Err(error) = exchange.set_request(request) {
+        exchange.handler.error(error);
+        return;
+    }
+    match delay {
+        Some(delay) => {
+            let stream = event_loop.spawn(Retry {
+                event_loop: event_loop.clone(),
+                exchange: Some(exchange),
+                pool: pool.clone(),
+            });
+            if event_loop.set_timeout(delay, &stream, ()).is_err() {
+                // NOTE: retry right away when the timer cannot be created.
+                stream.send(());
             }
-            start = i + 1;
+        },
+        None => {
+            let key = exchange.key.clone();
+            dispatch(pool, event_loop, key, exchange);
+        },
+    }
+}
+
+// Create the request following the redirection, if the response is a redirection to follow.
+fn redirection(exchange: &Exchange, response: &HttpResponse) -> Option<io::Result<HttpRequest>> {
+    if exchange.max_redirects == 0 || ![301, 302, 303, 307, 308].contains(&response.status) {
+        return None;
+    }
+    let location = response.headers.get("Location")?;
+    if exchange.redirects >= exchange.max_redirects {
+        return Some(Err(io::Error::other("too many redirections")));
+    }
+    let uri =
+        match Uri::parse(&exchange.request.uri).and_then(|uri| uri.resolve(location)) {
+            // NOTE: TLS is not supported, so the request is not sent in plain text to an https uri.
+            Ok(uri) if uri.scheme() != HttpScheme::Http =>
+                return Some(Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported redirection to {}", uri)))),
+            Ok(uri) => uri.to_string(),
+            Err(_) => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, "invalid redirection location"))),
+        };
+    let mut request = exchange.request.clone();
+    // NOTE: like the browsers, a POST is changed to a GET for the status codes 301 and 302.
+    let change_to_get =
+        (response.status == 303 && request.method != Method::Head) ||
+        ((response.status == 301 || response.status == 302) && request.method == Method::Post);
+    if change_to_get {
+        request.method = Method::Get;
+        request.body.clear();
+        request.headers.remove("Content-Type");
+    }
+    let same_authority = HttpUri::new(&request.uri).ok().map(|uri| uri.authority) ==
+        HttpUri::new(&uri).ok().map(|uri| uri.authority);
+    if !same_authority {
+        // NOTE: the credentials are not sent to another host.
+        request.headers.remove("Authorization");
+        request.headers.remove("Cookie");
+        request.headers.remove("Host");
+    }
+    request.uri = uri;
+    Some(Ok(request))
+}
+
+// Delay before retrying the request, if it should be retried.
+fn retry_delay(pool: &Rc<RefCell<Pool>>, exchange: &Exchange, result: &io::Result<HttpResponse>) -> Option<Duration> {
+    let policy = exchange.retry_policy?;
+    if exchange.retries >= policy.max_retries {
+        return None;
+    }
+    let idempotent =
+        match exchange.request.method {
+            Method::Delete | Method::Get | Method::Head | Method::Options | Method::Put | Method::Trace => true,
+            Method::Connect | Method::Patch | Method::Post | Method::Extension(_) => false,
+        };
+    let retry =
+        match *result {
+            Ok(ref response) => idempotent && response.status >= 500,
+            // NOTE: the request was not sent when the connection was refused, so it can always be retried.
+            Err(ref error) => error.kind() == io::ErrorKind::ConnectionRefused || (idempotent && is_connection_error(error)),
+        };
+    if !retry {
+        return None;
+    }
+    let delay = policy.base_delay.saturating_mul(2_u32.saturating_pow(exchange.retries)).min(policy.max_delay);
+    let jitter = pool.borrow_mut().rng.gen_double_interval_unit();
+    Some(delay.mul_f64(1.0 - jitter / 2.0))
+}
+
+fn is_connection_error(error: &io::Error) -> bool {
+    matches!(error.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionRefused |
+        io::ErrorKind::ConnectionReset | io::ErrorKind::NotConnected | io::ErrorKind::TimedOut |
+        io::ErrorKind::UnexpectedEof)
+}
+
+// Send the request once the retry delay expired.
+struct Retry {
+    event_loop: Loop,
+    exchange: Option<Exchange>,
+    pool: Rc<RefCell<Pool>>,
+}
+
+impl Handler for Retry {
+    type Msg = ();
+
+    fn update(&mut self, stream: &Stream<()>, _msg: ()) {
+        if let Some(exchange) = self.exchange.take() {
+            let key = exchange.key.clone();
+            dispatch(&self.pool, &mut self.event_loop, key, exchange);
         }
+        stream.stop();
     }
-    None
 }
 
-fn remove_until_boundary(buffer: &mut VecDeque<u8>) {
-    let mut index = buffer.len() - 1;
-    for i in 0..buffer.len() {
-        if i + 4 <= buffer.len() && deque_compare(&buffer, i, 4, b"\r\n\r\n") {
-            index = i + 4;
-            break;
+// Send the next queued request on the connection or keep it idle.
+fn release(pool: &Rc<RefCell<Pool>>, event_loop: &mut Loop, key: &HostKey, connection: &TcpConnection, slot: &Slot) {
+    let next = {
+        let mut pool = pool.borrow_mut();
+        let host = pool.hosts.entry(key.clone()).or_default();
+        let next = host.queue.pop_front();
+        if next.is_none() {
+            host.idle.push((connection.clone(), slot.clone()));
         }
+        next
+    };
+    if let Some(exchange) = next {
+        start(pool, event_loop, connection, slot, exchange, true);
     }
-    for _ in 0..index {
-        buffer.pop_front();
+}
+
+fn remove_idle(pool: &Rc<RefCell<Pool>>, key: &HostKey, slot: &Slot) {
+    if let Some(host) = pool.borrow_mut().hosts.get_mut(key) {
+        host.idle.retain(|(_, idle_slot)| !Rc::ptr_eq(idle_slot, slot));
     }
 }
 
-#[derive(Clone)]
-struct Connection<HANDLER> {
-    buffer: VecDeque<u8>,
-    content_length: usize,
-    handler: HANDLER,
-    host: String,
-    method: &'static str,
-    path: String,
+// Open a connection for the next queued request, if any.
+fn connection_closed(pool: &Rc<RefCell<Pool>>, event_loop: &mut Loop, key: &HostKey, slot: &Slot) {
+    remove_idle(pool, key, slot);
+    let next = {
+        let mut pool = pool.borrow_mut();
+        let host =
+            match pool.hosts.get_mut(key) {
+                Some(host) => host,
+                None => return,
+            };
+        host.connections -= 1;
+        let next = host.queue.pop_front();
+        if host.connections == 0 && next.is_none() {
+            pool.hosts.remove(key);
+        }
+        next
+    };
+    if let Some(exchange) = next {
+        dispatch(pool, event_loop, key.clone(), exchange);
+    }
 }
 
-impl<HANDLER> Connection<HANDLER> {
-    fn new(host: &str, handler: HANDLER, path: &str, method: &'static str) -> Self {
+// Whether the server keeps the connection open after the response.
+fn keep_alive(response: &HttpResponse) -> bool {
+    match response.version {
+        Version::Http10 => response.headers.has_token("Connection", "keep-alive"),
+        Version::Http11 => !response.headers.has_token("Connection", "close"),
+    }
+}
+
+struct Connection {
+    // Set when the connection was removed from the pool.
+    closed: bool,
+    event_loop: Loop,
+    key: HostKey,
+    pool: Rc<RefCell<Pool>>,
+    slot: Slot,
+}
+
+impl Connection {
+    fn new(pool: &Rc<RefCell<Pool>>, event_loop: &Loop, key: HostKey, exchange: Exchange) -> Self {
         Self {
-            buffer: VecDeque::new(),
-            content_length: 0,
-            handler,
-            host: host.to_string(),
-            method,
-            path: path.to_string(),
+            closed: false,
+            event_loop: event_loop.clone(),
+            key,
+            pool: pool.clone(),
+            slot: Rc::new(RefCell::new(Some(exchange))),
+        }
+    }
+
+    fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            connection_closed(&self.pool, &mut self.event_loop, &self.key, &self.slot);
+        }
+    }
+
+    fn fail(&mut self, error: io::Error) {
+        let exchange = self.slot.borrow_mut().take();
+        if let Some(exchange) = exchange {
+            complete(&self.pool, &mut self.event_loop, exchange, Err(error));
         }
     }
 }
 
-impl<HANDLER> TcpConnectionNotify for Connection<HANDLER>
-where HANDLER: HttpHandler,
-{
+impl TcpConnectionNotify for Connection {
     fn connected(&mut self, connection: &mut TcpConnection) {
-        if let Err(error) = connection.write(format!("{} {} HTTP/1.1\r\nHost: {}\r\n\r\n", self.method, self.path,
-            self.host).into_bytes())
-        {
-            self.handler.error(error);
+        connection.set_read_idle_timeout(self.pool.borrow().config.idle_timeout);
+        let exchange = self.slot.borrow_mut().take();
+        if let Some(exchange) = exchange {
+            start(&self.pool, &mut self.event_loop, connection, &self.slot, exchange, false);
         }
     }
 
+    fn connect_failed(&mut self) {
+        self.fail(io::Error::new(io::ErrorKind::ConnectionRefused, "could not connect to host"));
+        self.close();
+    }
+
     fn error(&mut self, error: io::Error) {
-        self.handler.error(error);
+        // NOTE: the request is sent again when the connection is closed.
+        let retry = self.slot.borrow().as_ref().is_some_and(|exchange| exchange.reused) && is_connection_error(&error);
+        if !retry {
+            self.fail(error);
+        }
+    }
+
+    fn idle(&mut self, connection: &mut TcpConnection, _idle: Idle) {
+        // NOTE: a connection waiting for a response is not idle.
+        if self.slot.borrow().is_none() {
+            remove_idle(&self.pool, &self.key, &self.slot);
+            connection.dispose();
+        }
     }
 
     fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
-        self.buffer.extend(data);
-        if self.content_length == 0 {
-            match parse_headers(&self.buffer) {
-                Some(content_length) => {
-                    remove_until_boundary(&mut self.buffer);
-                    self.content_length = content_length;
+        let result =
+            match *self.slot.borrow_mut() {
+                Some(ref mut exchange) => {
+                    exchange.reused = false;
+                    exchange.parser.feed(data);
+                    exchange.parser.next_response()
                 },
-                None => (), // Might find the content length in the next data.
+                None => return,
+            };
+        match result {
+            Ok(Some(response)) => {
+                let exchange = self.slot.borrow_mut().take();
+                if let Some(exchange) = exchange {
+                    // NOTE: the connection is released before calling the handler so that it can
+                    // be reused by the requests sent from the handler.
+                    if exchange.close || !keep_alive(&response) {
+                        connection.dispose();
+                    }
+                    else {
+                        release(&self.pool, &mut self.event_loop, &self.key, connection, &self.slot);
+                    }
+                    complete(&self.pool, &mut self.event_loop, exchange, Ok(response));
+                }
+            },
+            Ok(None) => (), // Wait for the rest of the response.
+            Err(error) => {
+                self.fail(error);
+                connection.dispose();
+            },
+        }
+    }
+
+    fn closed(&mut self, _connection: &mut TcpConnection) {
+        let exchange = self.slot.borrow_mut().take();
+        if let Some(mut exchange) = exchange {
+            // NOTE: the server could have closed the idle connection before receiving the request,
+            // so it is sent once again on a new connection, which replaces this one in the pool.
+            if exchange.reused {
+                let request = exchange.request.clone();
+                if exchange.set_request(request).is_ok() {
+                    self.closed = true;
+                    connect(&self.pool, &mut self.event_loop, self.key.clone(), exchange);
+                    return;
+                }
             }
+            let result = exchange.parser.finish();
+            complete(&self.pool, &mut self.event_loop, exchange, result);
         }
-        if self.buffer.len() >= self.content_length {
-            let buffer = mem::replace(&mut self.buffer, VecDeque::new());
-            self.handler.response(buffer.into());
-            connection.dispose();
+        self.close();
+    }
+}
+
+/// HTTP header fields. The names are case-insensitive and a name can appear many times.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self {
+            fields: vec![],
+        }
+    }
+
+    /// Add a field, keeping the other fields with the same name.
+    pub fn append<NAME, VALUE>(&mut self, name: NAME, value: VALUE)
+    where NAME: Into<String>,
+          VALUE: Into<String>,
+    {
+        self.fields.push((name.into(), value.into()));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Get the value of the first field named `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(field_name, _)| field_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get the values of all the fields named `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.fields.iter()
+            .filter(move |(field_name, _)| field_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Check if the comma-separated values of the fields named `name` contain `token`, ignoring
+    /// the case.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Replace all the fields named `name` by a single field.
+    pub fn insert<NAME, VALUE>(&mut self, name: NAME, value: VALUE)
+    where NAME: Into<String>,
+          VALUE: Into<String>,
+    {
+        let name = name.into();
+        self.remove(&name);
+        self.fields.push((name, value.into()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.fields.retain(|(field_name, _)| !field_name.eq_ignore_ascii_case(name));
+    }
+}
+
+/// HTTP request method, as defined by RFC 7231 and RFC 5789 for `PATCH`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Method {
+    Connect,
+    Delete,
+    Get,
+    Head,
+    Options,
+    Patch,
+    Post,
+    Put,
+    Trace,
+    /// Any other method, e.g. the WebDAV `PROPFIND` method.
+    Extension(String),
+}
+
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            Method::Connect => "CONNECT",
+            Method::Delete => "DELETE",
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Patch => "PATCH",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Trace => "TRACE",
+            Method::Extension(ref method) => method,
+        }
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Method {
+    type Err = io::Error;
+
+    /// Parse a method. The methods are case-sensitive and any token is a valid extension method.
+    fn from_str(method: &str) -> io::Result<Self> {
+        let method =
+            match method {
+                "CONNECT" => Method::Connect,
+                "DELETE" => Method::Delete,
+                "GET" => Method::Get,
+                "HEAD" => Method::Head,
+                "OPTIONS" => Method::Options,
+                "PATCH" => Method::Patch,
+                "POST" => Method::Post,
+                "PUT" => Method::Put,
+                "TRACE" => Method::Trace,
+                _ => {
+                    if !is_token(method) {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid method {:?}", method)));
+                    }
+                    Method::Extension(method.to_string())
+                },
+            };
+        Ok(method)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Display for Version {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        let version =
+            match *self {
+                Version::Http10 => "HTTP/1.0",
+                Version::Http11 => "HTTP/1.1",
+            };
+        write!(formatter, "{}", version)
+    }
+}
+
+/// Check if `text` is a token, as defined by RFC 7230.
+pub(crate) fn is_token(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+/// Check if a header field can be written in a head: the name must be a token and the value must
+/// be a valid field value.
+pub(crate) fn is_valid_header_field(name: &str, value: &str) -> bool {
+    is_token(name) && is_valid_field_value(value)
+}
+
+/// Check if `text` does not contain CR, LF or NUL, which would end the field or the head it is
+/// written in.
+pub(crate) fn is_valid_field_value(text: &str) -> bool {
+    !text.bytes().any(|byte| byte == b'\r' || byte == b'\n' || byte == b'\0')
+}
+
+/// Request sent by the client.
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    pub body: Vec<u8>,
+    /// The `Host` header is added when missing and the `Content-Length` header is added when the
+    /// request is sent.
+    pub headers: Headers,
+    pub method: Method,
+    pub uri: String,
+}
+
+impl HttpRequest {
+    pub fn new(method: Method, uri: &str) -> Self {
+        Self {
+            body: vec![],
+            headers: Headers::new(),
+            method,
+            uri: uri.to_string(),
+        }
+    }
+
+    pub fn delete(uri: &str) -> Self {
+        Self::new(Method::Delete, uri)
+    }
+
+    pub fn get(uri: &str) -> Self {
+        Self::new(Method::Get, uri)
+    }
+
+    pub fn head(uri: &str) -> Self {
+        Self::new(Method::Head, uri)
+    }
+
+    pub fn patch(uri: &str) -> Self {
+        Self::new(Method::Patch, uri)
+    }
+
+    pub fn post(uri: &str) -> Self {
+        Self::new(Method::Post, uri)
+    }
+
+    pub fn put(uri: &str) -> Self {
+        Self::new(Method::Put, uri)
+    }
+
+    // Encode the request, failing with InvalidInput when a header field is invalid.
+    fn to_bytes(&self, uri: &HttpUri) -> io::Result<Vec<u8>> {
+        let mut head = format!("{} {}", self.method, uri.resource.path);
+        if let Some(query) = uri.resource.query {
+            head.push('?');
+            head.push_str(query);
+        }
+        head.push_str(" HTTP/1.1\r\n");
+        if !self.headers.contains("Host") {
+            head.push_str(&format!("Host: {}\r\n", uri.authority));
+        }
+        // NOTE: the servers can require a Content-Length for the methods with a body, even if it is empty.
+        let has_body = !self.body.is_empty() || [Method::Patch, Method::Post, Method::Put].contains(&self.method);
+        if has_body {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        for (name, value) in self.headers.iter() {
+            if !is_valid_header_field(name, value) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid header field {:?}", name)));
+            }
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("\r\n");
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        Ok(bytes)
+    }
+
+    pub fn with_body<BODY: Into<Vec<u8>>>(mut self, body: BODY) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Add a header, keeping the other headers with the same name.
+    ///
+    /// Sending the request fails with an `InvalidInput` error when `name` is not a token or when
+    /// `value` contains CR, LF or NUL.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Append the encoded fields of `query` to the query string of the uri.
+    pub fn with_query(mut self, query: &Query) -> Self {
+        if !query.is_empty() {
+            self.uri.push(if self.uri.contains('?') { '&' } else { '?' });
+            self.uri.push_str(&query.to_string());
         }
+        self
+    }
+}
+
+/// Response received by the client.
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub body: Vec<u8>,
+    pub headers: Headers,
+    pub reason: String,
+    pub status: u16,
+    pub version: Version,
+}
+
+impl HttpResponse {
+    /// Whether the status code is 2xx.
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
     }
 }
 
 pub trait HttpHandler {
-    fn response(&mut self, data: Vec<u8>);
+    fn response(&mut self, response: HttpResponse);
 
     fn error(&mut self, _error: io::Error) {
     }
@@ -161,14 +823,14 @@ impl<ErrorMsg, MSG, SuccessMsg> DefaultHttpHandler<ErrorMsg, MSG, SuccessMsg> {
 impl<ErrorMsg, MSG, SuccessMsg> HttpHandler for DefaultHttpHandler<ErrorMsg, MSG, SuccessMsg>
 where MSG: Debug,
       ErrorMsg: Fn(io::Error) -> MSG,
-      SuccessMsg: Fn(Vec<u8>) -> MSG,
+      SuccessMsg: Fn(HttpResponse) -> MSG,
 {
     fn error(&mut self, error: io::Error) {
         self.stream.send((self.error_msg)(error));
     }
 
-    fn response(&mut self, data: Vec<u8>) {
-        self.stream.send((self.success_msg)(data));
+    fn response(&mut self, response: HttpResponse) {
+        self.stream.send((self.success_msg)(response));
     }
 }
 
@@ -188,62 +850,115 @@ impl<MSG, SuccessMsg> HttpHandlerIgnoreErr<MSG, SuccessMsg> {
 
 impl<MSG, SuccessMsg> HttpHandler for HttpHandlerIgnoreErr<MSG, SuccessMsg>
 where MSG: Debug,
-      SuccessMsg: Fn(Vec<u8>) -> MSG,
+      SuccessMsg: Fn(HttpResponse) -> MSG,
 {
-    fn response(&mut self, data: Vec<u8>) {
-        self.stream.send((self.success_msg)(data));
+    fn response(&mut self, response: HttpResponse) {
+        self.stream.send((self.success_msg)(response));
     }
 }
 
+/// HTTP client keeping a pool of idle keep-alive connections per host and port.
+///
+/// The connections are registered in the event loop used to send the requests, so a client
+/// must be used with a single event loop.
+#[derive(Clone)]
 pub struct Http {
+    limits: Limits,
+    max_redirects: usize,
+    pool: Rc<RefCell<Pool>>,
+    retry_policy: Option<RetryPolicy>,
 }
 
 impl Http {
     pub fn new() -> Self {
+        Self::with_pool_config(PoolConfig::default())
+    }
+
+    pub fn with_pool_config(config: PoolConfig) -> Self {
         Self {
+            limits: Limits::default(),
+            max_redirects: 0,
+            pool: Rc::new(RefCell::new(Pool {
+                config,
+                hosts: HashMap::new(),
+                rng: Rng::new(),
+            })),
+            retry_policy: None,
         }
     }
 
-    fn blocking<F: Fn(Rc<RefCell<io::Result<Vec<u8>>>>, &mut Loop) -> io::Result<()>>(&self, callback: F) -> io::Result<Vec<u8>> {
-        let result = Rc::new(RefCell::new(Ok(vec![])));
+    /// Reject the responses exceeding `limits` with an `InvalidData` error. The default limits
+    /// are `Limits::default()`.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Follow up to `max_redirects` redirections. The redirection responses are sent to the
+    /// handler by default.
+    pub fn with_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Retry the requests according to `policy`. The requests are not retried by default.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    fn blocking<F: FnOnce(BlockingResult, &mut Loop) -> io::Result<()>>(&self, callback: F) -> io::Result<HttpResponse> {
+        let result = Rc::new(RefCell::new(None));
         let mut event_loop = Loop::new()?;
         callback(result.clone(), &mut event_loop)?;
         event_loop.run()?;
-        let mut result = result.borrow_mut();
-        mem::replace(&mut *result, Ok(vec![]))
+        let result = result.borrow_mut().take();
+        result.unwrap_or_else(|| Err(io::Error::other("no response")))
     }
 
-    pub fn blocking_get(&self, uri: &str) -> io::Result<Vec<u8>> {
-        self.blocking(|result, event_loop| {
-            let stream = event_loop.spawn(BlockingHttpHandler::new(&event_loop, result));
-            let http = Http::new();
-            http.get(uri, event_loop, DefaultHttpHandler::new(&stream, HttpGet, HttpError))
-                .map_err(|()| io::Error::new(io::ErrorKind::Other, ""))
-        })
+    pub fn blocking_get(&self, uri: &str) -> io::Result<HttpResponse> {
+        self.blocking_send(HttpRequest::get(uri))
+    }
+
+    pub fn blocking_post(&self, uri: &str) -> io::Result<HttpResponse> {
+        self.blocking_send(HttpRequest::post(uri))
     }
 
-    pub fn blocking_post(&self, uri: &str) -> io::Result<Vec<u8>> {
+    /// Send the request and wait for its response.
+    ///
+    /// NOTE: the connection is not reused since it belongs to a temporary event loop.
+    pub fn blocking_send(&self, mut request: HttpRequest) -> io::Result<HttpResponse> {
+        request.headers.insert("Connection", "close");
         self.blocking(|result, event_loop| {
             let stream = event_loop.spawn(BlockingHttpHandler::new(&event_loop, result));
-            let http = Http::new();
-            http.post(uri, event_loop, DefaultHttpHandler::new(&stream, HttpGet, HttpError))
-                .map_err(|()| io::Error::new(io::ErrorKind::Other, ""))
+            let http = Http {
+                limits: self.limits,
+                max_redirects: self.max_redirects,
+                retry_policy: self.retry_policy,
+                ..Http::with_pool_config(self.pool.borrow().config)
+            };
+            http.send(request, event_loop, DefaultHttpHandler::new(&stream, HttpGet, HttpError))
         })
     }
 
-    pub fn get<HANDLER>(&self, uri: &str, event_loop: &mut Loop, handler: HANDLER) -> Result<(), ()>
+    pub fn get<HANDLER>(&self, uri: &str, event_loop: &mut Loop, handler: HANDLER) -> io::Result<()>
     where HANDLER: HttpHandler + 'static,
     {
-        let uri = HttpUri::new(uri)?;
-        TcpConnection::ip4(event_loop, uri.host, uri.port, Connection::new(uri.host, handler, uri.resource.path, "GET"));
-        Ok(())
+        self.send(HttpRequest::get(uri), event_loop, handler)
+    }
+
+    pub fn post<HANDLER>(&self, uri: &str, event_loop: &mut Loop, handler: HANDLER) -> io::Result<()>
+    where HANDLER: HttpHandler + 'static,
+    {
+        self.send(HttpRequest::post(uri), event_loop, handler)
     }
 
-    pub fn post<HANDLER>(&self, uri: &str, event_loop: &mut Loop, handler: HANDLER) -> Result<(), ()>
+    pub fn send<HANDLER>(&self, request: HttpRequest, event_loop: &mut Loop, handler: HANDLER) -> io::Result<()>
     where HANDLER: HttpHandler + 'static,
     {
-        let uri = HttpUri::new(uri)?;
-        TcpConnection::ip4(event_loop, uri.host, uri.port, Connection::new(uri.host, handler, uri.resource.path, "POST"));
+        let exchange = Exchange::new(request, Box::new(handler), self.limits, self.max_redirects, self.retry_policy)?;
+        let key = exchange.key.clone();
+        dispatch(&self.pool, event_loop, key, exchange);
         Ok(())
     }
 }
@@ -256,17 +971,19 @@ impl Default for Http {
 
 #[derive(Debug)]
 enum Msg {
-    HttpGet(Vec<u8>),
+    HttpGet(HttpResponse),
     HttpError(io::Error),
 }
 
+type BlockingResult = Rc<RefCell<Option<io::Result<HttpResponse>>>>;
+
 struct BlockingHttpHandler {
     event_loop: Loop,
-    result: Rc<RefCell<io::Result<Vec<u8>>>>,
+    result: BlockingResult,
 }
 
 impl BlockingHttpHandler {
-    fn new(event_loop: &Loop, result: Rc<RefCell<io::Result<Vec<u8>>>>) -> Self {
+    fn new(event_loop: &Loop, result: BlockingResult) -> Self {
         Self {
             event_loop: event_loop.clone(),
             result,
@@ -279,11 +996,11 @@ impl Handler for BlockingHttpHandler {
 
     fn update(&mut self, _stream: &Stream<Msg>, msg: Self::Msg) {
         match msg {
-            HttpGet(body) => {
-                *self.result.borrow_mut() = Ok(body);
+            HttpGet(response) => {
+                *self.result.borrow_mut() = Some(Ok(response));
             },
             HttpError(error) => {
-                *self.result.borrow_mut() = Err(error);
+                *self.result.borrow_mut() = Some(Err(error));
             },
         }
         self.event_loop.stop()
@@ -0,0 +1,758 @@
+//! Incremental parsers for HTTP/1.x requests and responses.
+//!
+//! The data received is appended to a single buffer which is only scanned once: the parser
+//! remembers where it stopped and continues from there when more data is received.
+//! The requests refer to the buffer instead of copying their parts out of it.
+
+use std::io;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+use std::str;
+
+use crate::aio::http::{
+    Headers,
+    HttpResponse,
+    Method,
+    Version,
+    is_token,
+};
+use crate::aio::http_server::{
+    Limits,
+    Request,
+    RequestHeaders,
+};
+
+use self::ParseError::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseError {
+    BadRequest,
+    HeaderFieldsTooLarge,
+    NotImplemented,
+    PayloadTooLarge,
+}
+
+impl ParseError {
+    /// Status code of the response to send for this error.
+    pub fn status(&self) -> u16 {
+        match *self {
+            BadRequest => 400,
+            HeaderFieldsTooLarge => 431,
+            NotImplemented => 501,
+            PayloadTooLarge => 413,
+        }
+    }
+}
+
+pub struct RequestParser {
+    // The data received, shared with the requests parsed from it.
+    buffer: Rc<Vec<u8>>,
+    header_count: usize,
+    // Head of the request being received, with the end of the head and the content length.
+    head: Option<(Head, usize, usize)>,
+    limits: Limits,
+    // Start of the line being scanned, relative to start.
+    line_start: usize,
+    // Index from which to continue searching for the end of the line, relative to start.
+    scanned: usize,
+    // Start of the request being received: the data before belongs to the previous requests.
+    start: usize,
+}
+
+impl RequestParser {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            buffer: Rc::new(vec![]),
+            header_count: 0,
+            head: None,
+            limits,
+            line_start: 0,
+            scanned: 0,
+            start: 0,
+        }
+    }
+
+    pub fn feed(&mut self, data: Vec<u8>) {
+        if self.start == self.buffer.len() {
+            self.buffer = Rc::new(data);
+        }
+        else if let Some(buffer) = Rc::get_mut(&mut self.buffer) {
+            buffer.drain(..self.start);
+            buffer.extend_from_slice(&data);
+        }
+        else {
+            // NOTE: the requests parsed before still refer to the buffer, so the start of the
+            // next request is moved to a new one.
+            let mut buffer = self.buffer[self.start..].to_vec();
+            buffer.extend_from_slice(&data);
+            self.buffer = Rc::new(buffer);
+        }
+        self.start = 0;
+    }
+
+    /// Parse the next request from the data received so far.
+    ///
+    /// Returns `Ok(None)` when more data is needed. The data following the request is kept for
+    /// the next call.
+    pub fn next_request(&mut self) -> Result<Option<Request>, ParseError> {
+        if self.head.is_none() {
+            if self.scanned == 0 {
+                // NOTE: RFC 7230 says to ignore the empty lines received before a request.
+                let empty_lines = self.buffer[self.start..].iter().take_while(|&&byte| byte == b'\r' || byte == b'\n').count();
+                self.start += empty_lines;
+            }
+            let head_end =
+                match self.scan_head()? {
+                    Some(head_end) => head_end,
+                    None => return Ok(None),
+                };
+            let data = &self.buffer[self.start..self.start + head_end];
+            let head = parse_head(data)?;
+            let content_length = content_length(&RequestHeaders::new(data, &head.headers), &self.limits)?;
+            self.header_count = 0;
+            self.line_start = 0;
+            self.scanned = 0;
+            self.head = Some((head, head_end, content_length));
+        }
+
+        match self.head {
+            Some((_, head_end, content_length)) if self.buffer.len() - self.start >= head_end + content_length => {
+                let (head, head_end, content_length) = self.head.take().expect("head");
+                let start = self.start;
+                self.start += head_end + content_length;
+                let shift = |range: Range<usize>| start + range.start..start + range.end;
+                Ok(Some(Request {
+                    body: shift(head_end..head_end + content_length),
+                    data: self.buffer.clone(),
+                    headers: head.headers.into_iter().map(|(name, value)| (shift(name), shift(value))).collect(),
+                    method: head.method,
+                    params: vec![],
+                    path: shift(head.path),
+                    query_string: shift(head.query_string),
+                    version: head.version,
+                }))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn check_line_length(&self, length: usize) -> Result<(), ParseError> {
+        if length > self.limits.max_line_length {
+            if self.line_start == 0 {
+                return Err(BadRequest);
+            }
+            return Err(HeaderFieldsTooLarge);
+        }
+        Ok(())
+    }
+
+    // Find the end of the head, checking the limits on the lines received so far.
+    fn scan_head(&mut self) -> Result<Option<usize>, ParseError> {
+        let data = &self.buffer[self.start..];
+        while let Some(position) = data[self.scanned..].iter().position(|&byte| byte == b'\n') {
+            let line_end = self.scanned + position;
+            let line = trim_carriage_return(&data[self.line_start..line_end]);
+            self.check_line_length(line.len())?;
+            self.scanned = line_end + 1;
+            if line.is_empty() {
+                return Ok(Some(self.scanned));
+            }
+            if self.line_start != 0 {
+                self.header_count += 1;
+                if self.header_count > self.limits.max_header_count {
+                    return Err(HeaderFieldsTooLarge);
+                }
+            }
+            self.line_start = self.scanned;
+        }
+        self.check_line_length(data.len() - self.line_start)?;
+        Ok(None)
+    }
+}
+
+// The parts of a request head, as ranges of the head.
+struct Head {
+    headers: Vec<(Range<usize>, Range<usize>)>,
+    method: Method,
+    path: Range<usize>,
+    query_string: Range<usize>,
+    version: Version,
+}
+
+enum Chunk {
+    Data(usize),
+    DataEnd,
+    Size,
+    // The number of trailer fields received.
+    Trailers(usize),
+}
+
+enum Body {
+    Chunked(Chunk),
+    Length(usize),
+    UntilClose,
+}
+
+pub struct ResponseParser {
+    buffer: Vec<u8>,
+    // Response whose head was parsed, with the framing of its body.
+    head: Option<(HttpResponse, Body)>,
+    head_request: bool,
+    limits: Limits,
+}
+
+impl ResponseParser {
+    /// Create a parser for the response to a request, which has no body for a `HEAD` request.
+    ///
+    /// A response exceeding `limits` gives an `InvalidData` error.
+    pub fn new(head_request: bool, limits: Limits) -> Self {
+        Self {
+            buffer: vec![],
+            head: None,
+            head_request,
+            limits,
+        }
+    }
+
+    pub fn feed(&mut self, data: Vec<u8>) {
+        self.buffer.extend_from_slice(&data);
+    }
+
+    /// Complete the response when the connection is closed.
+    ///
+    /// Only a response without `Content-Length` and chunked encoding can be delimited by the
+    /// end of the connection.
+    pub fn finish(&mut self) -> io::Result<HttpResponse> {
+        match self.head.take() {
+            Some((mut response, Body::UntilClose)) => {
+                check_body_size(self.buffer.len(), &self.limits)?;
+                response.body = mem::take(&mut self.buffer);
+                Ok(response)
+            },
+            _ => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the end of the response")),
+        }
+    }
+
+    /// Parse the response from the data received so far.
+    ///
+    /// Returns `Ok(None)` when more data is needed. The interim 1xx responses are skipped.
+    pub fn next_response(&mut self) -> io::Result<Option<HttpResponse>> {
+        while self.head.is_none() {
+            let head_end =
+                match scan_response_head(&self.buffer, &self.limits)? {
+                    Some(head_end) => head_end,
+                    None => return Ok(None),
+                };
+            let response = parse_response_head(&self.buffer[..head_end])
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid response head"))?;
+            self.buffer.drain(..head_end);
+            if response.status >= 200 {
+                let body = response_body(&response, self.head_request, &self.limits)?;
+                self.head = Some((response, body));
+            }
+        }
+
+        let complete =
+            match self.head {
+                Some((ref mut response, Body::Chunked(ref mut chunk))) => decode_chunks(chunk, &mut self.buffer, response, &self.limits)?,
+                Some((ref mut response, Body::Length(length))) if self.buffer.len() >= length => {
+                    let rest = self.buffer.split_off(length);
+                    response.body = mem::replace(&mut self.buffer, rest);
+                    true
+                },
+                Some((_, Body::UntilClose)) => {
+                    check_body_size(self.buffer.len(), &self.limits)?;
+                    false
+                },
+                Some((_, Body::Length(_))) | None => false,
+            };
+        if !complete {
+            return Ok(None);
+        }
+        Ok(self.head.take().map(|(response, _)| response))
+    }
+}
+
+fn content_length(headers: &RequestHeaders, limits: &Limits) -> Result<usize, ParseError> {
+    if headers.contains("Transfer-Encoding") {
+        return Err(NotImplemented);
+    }
+    let mut content_length = None;
+    for value in headers.get_all_bytes("Content-Length") {
+        if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+            return Err(BadRequest);
+        }
+        let length: usize = str::from_utf8(value).map_err(|_| BadRequest)?.parse().map_err(|_| PayloadTooLarge)?;
+        if content_length.is_some_and(|content_length| content_length != length) {
+            return Err(BadRequest);
+        }
+        content_length = Some(length);
+    }
+    let content_length = content_length.unwrap_or(0);
+    if content_length > limits.max_body_size {
+        return Err(PayloadTooLarge);
+    }
+    Ok(content_length)
+}
+
+// Decode the chunks received so far, returning true when the last chunk and the trailer fields
+// are decoded.
+fn decode_chunks(chunk: &mut Chunk, buffer: &mut Vec<u8>, response: &mut HttpResponse, limits: &Limits) -> io::Result<bool> {
+    let invalid_chunk = || io::Error::new(io::ErrorKind::InvalidData, "invalid chunk");
+    loop {
+        if let Chunk::Data(ref mut size) = *chunk {
+            let length = buffer.len().min(*size);
+            response.body.extend(buffer.drain(..length));
+            *size -= length;
+            if *size > 0 {
+                return Ok(false);
+            }
+            *chunk = Chunk::DataEnd;
+            continue;
+        }
+
+        let line_end =
+            match buffer.iter().position(|&byte| byte == b'\n') {
+                Some(position) => position,
+                None => {
+                    check_line_length(buffer.len(), limits)?;
+                    return Ok(false);
+                },
+            };
+        check_line_length(trim_carriage_return(&buffer[..line_end]).len(), limits)?;
+        let line: Vec<u8> = buffer.drain(..line_end + 1).collect();
+        let line = trim_carriage_return(&line[..line_end]);
+        match *chunk {
+            Chunk::DataEnd => {
+                if !line.is_empty() {
+                    return Err(invalid_chunk());
+                }
+                *chunk = Chunk::Size;
+            },
+            Chunk::Size => {
+                // NOTE: the chunk extensions are ignored.
+                let size = line.split(|&byte| byte == b';').next().unwrap_or(b"");
+                let size = str::from_utf8(size).map_err(|_| invalid_chunk())?.trim();
+                let size = usize::from_str_radix(size, 16).map_err(|_| invalid_chunk())?;
+                check_body_size(response.body.len().saturating_add(size), limits)?;
+                *chunk =
+                    if size == 0 {
+                        Chunk::Trailers(0)
+                    }
+                    else {
+                        Chunk::Data(size)
+                    };
+            },
+            Chunk::Trailers(ref mut count) => {
+                if line.is_empty() {
+                    return Ok(true);
+                }
+                *count += 1;
+                if *count > limits.max_header_count {
+                    return Err(too_large("trailer fields"));
+                }
+                // NOTE: the trailer fields are added to the header fields.
+                append_header_field(&mut response.headers, line).ok_or_else(invalid_chunk)?;
+            },
+            Chunk::Data(_) => unreachable!(),
+        }
+    }
+}
+
+fn check_body_size(size: usize, limits: &Limits) -> io::Result<()> {
+    if size > limits.max_body_size {
+        return Err(too_large("body"));
+    }
+    Ok(())
+}
+
+fn check_line_length(length: usize, limits: &Limits) -> io::Result<()> {
+    if length > limits.max_line_length {
+        return Err(too_large("line"));
+    }
+    Ok(())
+}
+
+// Find the end of the response head, after the empty line, checking the limits on the lines
+// received so far.
+fn scan_response_head(buffer: &[u8], limits: &Limits) -> io::Result<Option<usize>> {
+    let mut header_count = 0;
+    let mut line_start = 0;
+    while let Some(position) = buffer[line_start..].iter().position(|&byte| byte == b'\n') {
+        let line_end = line_start + position;
+        let line = trim_carriage_return(&buffer[line_start..line_end]);
+        check_line_length(line.len(), limits)?;
+        if line.is_empty() {
+            return Ok(Some(line_end + 1));
+        }
+        if line_start != 0 {
+            header_count += 1;
+            if header_count > limits.max_header_count {
+                return Err(too_large("header fields"));
+            }
+        }
+        line_start = line_end + 1;
+    }
+    check_line_length(buffer.len() - line_start, limits)?;
+    Ok(None)
+}
+
+fn parse_head(head: &[u8]) -> Result<Head, ParseError> {
+    let mut lines = lines(head);
+    let request_line = lines.next().ok_or(BadRequest)?;
+    let line = &head[request_line.clone()];
+    let mut parts = line.split(|&byte| byte == b' ');
+    let (method, target, version) =
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version), None) => (method, target, version),
+            _ => return Err(BadRequest),
+        };
+    let target_start = request_line.start + method.len() + 1;
+    let method = str::from_utf8(method).map_err(|_| BadRequest)?.parse().map_err(|_| BadRequest)?;
+    // NOTE: the request target is made of visible ASCII characters (RFC 7230 section 3.1.1).
+    if target.is_empty() || !target.iter().all(u8::is_ascii_graphic) {
+        return Err(BadRequest);
+    }
+    let version =
+        match version {
+            b"HTTP/1.0" => Version::Http10,
+            b"HTTP/1.1" => Version::Http11,
+            _ => return Err(BadRequest),
+        };
+
+    let mut headers = vec![];
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = header_field(&head[line.clone()]).ok_or(BadRequest)?;
+        headers.push((line.start + name.start..line.start + name.end, line.start + value.start..line.start + value.end));
+    }
+
+    let target_end = target_start + target.len();
+    let (path, query_string) =
+        match target.iter().position(|&byte| byte == b'?') {
+            Some(index) => (target_start..target_start + index, target_start + index + 1..target_end),
+            None => (target_start..target_end, target_end..target_end),
+        };
+    Ok(Head {
+        headers,
+        method,
+        path,
+        query_string,
+        version,
+    })
+}
+
+// Split a header field line into the ranges of its name and of its value, without the
+// surrounding whitespace.
+fn header_field(line: &[u8]) -> Option<(Range<usize>, Range<usize>)> {
+    // NOTE: obsolete line folding is rejected, as allowed by RFC 7230.
+    if line.starts_with(b" ") || line.starts_with(b"\t") {
+        return None;
+    }
+    let colon = line.iter().position(|&byte| byte == b':')?;
+    if !str::from_utf8(&line[..colon]).is_ok_and(is_token) {
+        return None;
+    }
+    let is_whitespace = |byte: &&u8| **byte == b' ' || **byte == b'\t';
+    let value_start = colon + 1 + line[colon + 1..].iter().take_while(is_whitespace).count();
+    let value_end = line.len() - line[value_start..].iter().rev().take_while(is_whitespace).count();
+    Some((0..colon, value_start..value_end))
+}
+
+// Add the header field of line to headers. The invalid UTF-8 sequences of the value are replaced.
+fn append_header_field(headers: &mut Headers, line: &[u8]) -> Option<()> {
+    let (name, value) = header_field(line)?;
+    headers.append(String::from_utf8_lossy(&line[name]), String::from_utf8_lossy(&line[value]));
+    Some(())
+}
+
+// The ranges of the lines of head, without their line endings.
+fn lines(head: &[u8]) -> impl Iterator<Item=Range<usize>> + '_ {
+    let mut start = 0;
+    head.split(|&byte| byte == b'\n').map(move |line| {
+        let range = start..start + trim_carriage_return(line).len();
+        start += line.len() + 1;
+        range
+    })
+}
+
+fn parse_response_head(head: &[u8]) -> Option<HttpResponse> {
+    let mut lines = lines(head);
+    let status_line = &head[lines.next()?];
+    let mut parts = status_line.splitn(3, |&byte| byte == b' ');
+    let version =
+        match parts.next()? {
+            b"HTTP/1.0" => Version::Http10,
+            b"HTTP/1.1" => Version::Http11,
+            _ => return None,
+        };
+    let status = parts.next()?;
+    if status.len() != 3 {
+        return None;
+    }
+    let status = str::from_utf8(status).ok()?.parse().ok()?;
+    let reason = String::from_utf8_lossy(parts.next().unwrap_or(b"")).into_owned();
+
+    let mut headers = Headers::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        append_header_field(&mut headers, &head[line])?;
+    }
+    Some(HttpResponse {
+        body: vec![],
+        headers,
+        reason,
+        status,
+        version,
+    })
+}
+
+fn response_body(head: &HttpResponse, head_request: bool, limits: &Limits) -> io::Result<Body> {
+    // NOTE: the responses to HEAD requests and the status codes 1xx, 204 and 304 have no body.
+    if head_request || head.status == 204 || head.status == 304 {
+        return Ok(Body::Length(0));
+    }
+    // NOTE: when chunked is not the last transfer coding, the body is delimited by the end of the
+    // connection.
+    if let Some(encodings) = head.headers.get_all("Transfer-Encoding").last() {
+        if encodings.rsplit(',').next().is_some_and(|encoding| encoding.trim().eq_ignore_ascii_case("chunked")) {
+            return Ok(Body::Chunked(Chunk::Size));
+        }
+        return Ok(Body::UntilClose);
+    }
+    let mut content_length = None;
+    for value in head.headers.get_all("Content-Length") {
+        let length = value.trim().parse::<usize>().ok();
+        if length.is_none() || (content_length.is_some() && content_length != length) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid content length"));
+        }
+        content_length = length;
+    }
+    if let Some(content_length) = content_length {
+        check_body_size(content_length, limits)?;
+    }
+    Ok(content_length.map_or(Body::UntilClose, Body::Length))
+}
+
+fn too_large(part: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{} of the response too large", part))
+}
+
+fn trim_carriage_return(line: &[u8]) -> &[u8] {
+    match line.split_last() {
+        Some((b'\r', line)) => line,
+        _ => line,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(data: &[&[u8]]) -> Result<Vec<Request>, ParseError> {
+        let mut parser = RequestParser::new(Limits::default());
+        let mut requests = vec![];
+        for &chunk in data {
+            parser.feed(chunk.to_vec());
+            while let Some(request) = parser.next_request()? {
+                requests.push(request);
+            }
+        }
+        Ok(requests)
+    }
+
+    #[test]
+    fn test_split_request() {
+        let requests = parse(&[b"GET /pa", b"th?key=val HTTP/1.1\r\nHo", b"st: localhost\r", b"\nX-Custom:  value \r\n", b"\r\n"])
+            .expect("parse");
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert!(request.method == Method::Get);
+        assert_eq!(request.path(), "/path");
+        assert_eq!(request.query_string(), "key=val");
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.headers().get("host"), Some("localhost"));
+        assert_eq!(request.headers().get("x-custom"), Some("value"));
+        assert!(request.body().is_empty());
+    }
+
+    #[test]
+    fn test_body() {
+        let requests = parse(&[b"POST / HTTP/1.0\nContent-Length: 11\n\nhello", b" world", b"GET / HTTP/1.1\r\n\r\n"])
+            .expect("parse");
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].method == Method::Post);
+        assert_eq!(requests[0].version, Version::Http10);
+        assert_eq!(requests[0].body(), b"hello world");
+        assert!(requests[1].method == Method::Get);
+        assert!(requests[1].body().is_empty());
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let mut parser = RequestParser::new(Limits::default());
+        parser.feed(b"GET /a HTTP/1.1\r\n\r\nPOST /b HTTP/1.1\r\nContent-Length: 2\r\n\r\nokGET /c HT".to_vec());
+        let first = parser.next_request().expect("parse").expect("request");
+        let second = parser.next_request().expect("parse").expect("request");
+        assert!(parser.next_request().expect("parse").is_none());
+        // The requests refer to the data received instead of copying it.
+        assert!(Rc::ptr_eq(&first.data, &second.data));
+        assert_eq!(first.path(), "/a");
+        assert_eq!(second.path(), "/b");
+        assert_eq!(second.body(), b"ok");
+
+        // The rest of the pipelined request is moved to a new buffer since the others still refer
+        // to the previous one.
+        parser.feed(b"TP/1.1\r\n\r\n".to_vec());
+        let third = parser.next_request().expect("parse").expect("request");
+        assert!(!Rc::ptr_eq(&first.data, &third.data));
+        assert_eq!(third.path(), "/c");
+        assert_eq!(first.path(), "/a");
+    }
+
+    #[test]
+    fn test_obs_text() {
+        // A header value with obsolete non-ASCII text does not make the request invalid.
+        let requests = parse(&[b"GET / HTTP/1.1\r\nX-Name: caf\xe9\r\nX-Other: ok\r\n\r\n"]).expect("parse");
+        let headers = requests[0].headers();
+        assert_eq!(headers.get("x-name"), None);
+        assert_eq!(headers.get_bytes("x-name"), Some(&b"caf\xe9"[..]));
+        assert_eq!(headers.get("x-other"), Some("ok"));
+        assert_eq!(headers.len(), 2);
+
+        assert_eq!(parse(&[b"GET /caf\xe9 HTTP/1.1\r\n\r\n"]).err(), Some(BadRequest));
+        assert_eq!(parse(&[b"GET / HTTP/1.1\r\nX-Caf\xe9: 1\r\n\r\n"]).err(), Some(BadRequest));
+    }
+
+    #[test]
+    fn test_methods() {
+        let requests = parse(&[b"DELETE / HTTP/1.1\r\n\r\nPATCH / HTTP/1.1\r\n\r\nPROPFIND / HTTP/1.1\r\n\r\n"])
+            .expect("parse");
+        let methods: Vec<_> = requests.into_iter().map(|request| request.method).collect();
+        assert_eq!(methods, vec![Method::Delete, Method::Patch, Method::Extension("PROPFIND".to_string())]);
+        // The methods are case-sensitive.
+        let requests = parse(&[b"get / HTTP/1.1\r\n\r\n"]).expect("parse");
+        assert_eq!(requests[0].method, Method::Extension("get".to_string()));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse(&[b"GET /\r\n\r\n"]).err(), Some(BadRequest));
+        assert_eq!(parse(&[b"GET / HTTP/2.0\r\n\r\n"]).err(), Some(BadRequest));
+        assert_eq!(parse(&[b"G(ET / HTTP/1.1\r\n\r\n"]).err(), Some(BadRequest));
+        assert_eq!(parse(&[b"GET / HTTP/1.1\r\nNo colon\r\n\r\n"]).err(), Some(BadRequest));
+        assert_eq!(parse(&[b"GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n"]).err(), Some(BadRequest));
+        assert_eq!(parse(&[b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"]).err(), Some(BadRequest));
+        assert_eq!(parse(&[b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"]).err(), Some(BadRequest));
+        assert_eq!(parse(&[b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"]).err(), Some(NotImplemented));
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_body_size: 4,
+            max_header_count: 2,
+            max_line_length: 32,
+        };
+        let parse = |data: &[u8]| {
+            let mut parser = RequestParser::new(limits);
+            parser.feed(data.to_vec());
+            parser.next_request().map(|request| request.is_some())
+        };
+        assert_eq!(parse(b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody"), Ok(true));
+        assert_eq!(parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n"), Err(PayloadTooLarge));
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"), Err(HeaderFieldsTooLarge));
+        // The errors are detected before the end of the line is received.
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nA: 0123456789012345678901234567890123"), Err(HeaderFieldsTooLarge));
+        assert_eq!(parse(b"GET /0123456789012345678901234567890123"), Err(BadRequest));
+    }
+
+    fn parse_response(data: &[&[u8]], head_request: bool) -> io::Result<Option<HttpResponse>> {
+        let mut parser = ResponseParser::new(head_request, Limits::default());
+        for &chunk in data {
+            parser.feed(chunk.to_vec());
+            if let Some(response) = parser.next_response()? {
+                return Ok(Some(response));
+            }
+        }
+        Ok(None)
+    }
+
+    #[test]
+    fn test_chunked_response() {
+        let response = parse_response(&[b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel", b"lo\r\n7;ext=1\r",
+            b"\n, world\r\n0\r\nX-Checksum: 42\r\n", b"\r\n"], false)
+            .expect("parse").expect("response");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.reason, "OK");
+        assert_eq!(response.version, Version::Http11);
+        assert_eq!(response.body, b"hello, world");
+        assert_eq!(response.headers.get("x-checksum"), Some("42"));
+
+        assert!(parse_response(&[b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nZ\r\n"], false).is_err());
+        assert!(parse_response(&[b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nab\r\n"], false).is_err());
+    }
+
+    #[test]
+    fn test_response_without_body() {
+        let response = parse_response(&[b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n"], false)
+            .expect("parse").expect("response");
+        assert_eq!(response.status, 204);
+        assert_eq!(response.reason, "No Content");
+        assert!(response.body.is_empty());
+
+        let response = parse_response(&[b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n"], true)
+            .expect("parse").expect("response");
+        assert_eq!(response.headers.get("content-length"), Some("10"));
+        assert!(response.body.is_empty());
+
+        let response = parse_response(&[b"HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n"], false)
+            .expect("parse").expect("response");
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn test_response_limits() {
+        let limits = Limits {
+            max_body_size: 4,
+            max_header_count: 2,
+            max_line_length: 32,
+        };
+        let parse = |data: &[u8]| {
+            let mut parser = ResponseParser::new(false, limits);
+            parser.feed(data.to_vec());
+            parser.next_response().map(|response| response.is_some()).map_err(|error| error.kind())
+        };
+        let too_large = Err(io::ErrorKind::InvalidData);
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nbody"), Ok(true));
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"), too_large);
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"), too_large);
+        // The errors are detected before the end of the line is received.
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\nA: 0123456789012345678901234567890123"), too_large);
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n3\r\n"), too_large);
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n00000000000000000000000000000000000"), too_large);
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"), too_large);
+        assert_eq!(parse(b"HTTP/1.1 200 OK\r\n\r\nhello"), too_large);
+
+        let mut parser = ResponseParser::new(false, limits);
+        parser.feed(b"HTTP/1.0 200 OK\r\n\r\nbody".to_vec());
+        assert!(parser.next_response().expect("parse").is_none());
+        parser.feed(b"!".to_vec());
+        assert_eq!(parser.finish().map_err(|error| error.kind()).err(), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_response_until_close() {
+        let mut parser = ResponseParser::new(false, Limits::default());
+        parser.feed(b"HTTP/1.0 200 OK\r\n\r\nhello".to_vec());
+        assert!(parser.next_response().expect("parse").is_none());
+        parser.feed(b" world".to_vec());
+        let response = parser.finish().expect("finish");
+        assert_eq!(response.version, Version::Http10);
+        assert_eq!(response.body, b"hello world");
+
+        let mut parser = ResponseParser::new(false, Limits::default());
+        parser.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello".to_vec());
+        assert!(parser.next_response().expect("parse").is_none());
+        assert!(parser.finish().is_err());
+    }
+}
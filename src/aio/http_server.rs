@@ -1,14 +1,34 @@
 /*
  * TODO: use a proper URL parser.
- * TODO: do proper HTTP protocol handling.
  */
 
-use std::fmt::{self, Display, Formatter};
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::io;
 use std::net;
+use std::ops::Range;
+use std::rc::Rc;
+use std::str;
+use std::time::{Duration, Instant};
 
 use crate::aio::handler::{Loop, Stream};
+use crate::aio::http::{
+    Headers,
+    Query,
+    is_valid_field_value,
+    is_valid_header_field,
+    percent_decode,
+};
+pub use crate::aio::http::{
+    Method,
+    Version,
+};
+use crate::aio::http_parser::{
+    ParseError,
+    RequestParser,
+};
 use crate::aio::net::{
+    Idle,
     ListenerMsg,
     TcpConnection,
     TcpConnectionNotify,
@@ -16,6 +36,8 @@ use crate::aio::net::{
 };
 use crate::aio::net::TcpListener;
 
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
 struct Listener<HANDLER> {
     handler: HANDLER,
 }
@@ -28,7 +50,7 @@ impl<HANDLER> Listener<HANDLER> {
     }
 }
 
-impl<HANDLER: HttpHandler + 'static> TcpListenNotify for Listener<HANDLER> {
+impl<HANDLER: DeferredHttpHandler + 'static> TcpListenNotify for Listener<HANDLER> {
     fn listening(&mut self, listener: &net::TcpListener) {
         if let Err(error) = listener.local_addr() {
             eprintln!("Could not get local address: {}.", error);
@@ -44,84 +66,744 @@ impl<HANDLER: HttpHandler + 'static> TcpListenNotify for Listener<HANDLER> {
     }
 }
 
+// Responses of the requests received on a connection, sent in the order of the requests.
+struct ResponseQueue {
+    // Set when the connection is closed after a response.
+    closed: bool,
+    // Id of the first response of the queue.
+    first_id: usize,
+    last_response: Instant,
+    // The responses that are not ready yet are None. The boolean is set when the connection is
+    // kept alive after the response.
+    responses: VecDeque<Option<(Vec<u8>, bool)>>,
+}
+
+impl ResponseQueue {
+    fn new() -> Self {
+        Self {
+            closed: false,
+            first_id: 0,
+            last_response: Instant::now(),
+            responses: VecDeque::new(),
+        }
+    }
+
+    // Send the responses which are ready and not waiting for a previous response.
+    fn flush(&mut self, connection: &TcpConnection) {
+        while self.responses.front().is_some_and(Option::is_some) {
+            if let Some(Some((bytes, keep_alive))) = self.responses.pop_front() {
+                self.first_id += 1;
+                self.last_response = Instant::now();
+                let _ = connection.write(bytes); // TODO: handle errors.
+                if !keep_alive {
+                    // NOTE: the requests pipelined after this one are not answered.
+                    connection.dispose();
+                    self.closed = true;
+                    self.responses.clear();
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, response: Option<(Vec<u8>, bool)>) -> usize {
+        self.responses.push_back(response);
+        self.first_id + self.responses.len() - 1
+    }
+}
+
+/// Handle to send the response of a request, possibly later, e.g. from the `update` of another
+/// `Handler`.
+///
+/// The responses are sent in the order of the requests, so a pending response delays the
+/// responses of the requests pipelined after it. A `500` response is sent when the responder is
+/// dropped without responding.
+pub struct Responder {
+    connection: TcpConnection,
+    head: bool,
+    id: usize,
+    keep_alive: bool,
+    queue: Rc<RefCell<ResponseQueue>>,
+    responded: bool,
+    version: Version,
+}
+
+impl Responder {
+    pub fn respond(mut self, response: Response) {
+        self.send(response);
+    }
+
+    fn send(&mut self, mut response: Response) {
+        self.responded = true;
+        let keep_alive = self.keep_alive && !response.headers.has_token("Connection", "close");
+        if !keep_alive {
+            response.headers.insert("Connection", "close");
+        }
+        else if self.version == Version::Http10 {
+            response.headers.insert("Connection", "keep-alive");
+        }
+        let mut queue = self.queue.borrow_mut();
+        // NOTE: the connection could have been closed while waiting for the response.
+        if queue.closed {
+            return;
+        }
+        let index = self.id - queue.first_id;
+        // NOTE: the response to a HEAD request has the headers of the GET response without its body.
+        queue.responses[index] = Some((response.encode(!self.head), keep_alive));
+        queue.flush(&self.connection);
+    }
+}
+
+impl Drop for Responder {
+    fn drop(&mut self) {
+        if !self.responded {
+            self.send(Response::new(500));
+        }
+    }
+}
+
 struct Server<HANDLER> {
+    // Set when no more requests are handled on the connection.
+    closing: bool,
     handler: HANDLER,
+    parser: RequestParser,
+    responses: Rc<RefCell<ResponseQueue>>,
 }
 
-impl<HANDLER: HttpHandler> Server<HANDLER> {
+impl<HANDLER: DeferredHttpHandler> Server<HANDLER> {
     fn new(handler: HANDLER) -> Self {
         Self {
+            closing: false,
+            parser: RequestParser::new(handler.limits()),
             handler,
+            responses: Rc::new(RefCell::new(ResponseQueue::new())),
+        }
+    }
+
+    fn responder(&self, connection: &TcpConnection, request: &Request) -> Responder {
+        Responder {
+            connection: connection.clone(),
+            head: request.method == Method::Head,
+            id: self.responses.borrow_mut().push(None),
+            keep_alive: request.keep_alive(),
+            queue: self.responses.clone(),
+            responded: false,
+            version: request.version,
         }
     }
 }
 
-impl<HANDLER: HttpHandler> TcpConnectionNotify for Server<HANDLER> {
-    fn accepted(&mut self, _connection: &mut TcpConnection) {
+impl<HANDLER: DeferredHttpHandler> TcpConnectionNotify for Server<HANDLER> {
+    fn accepted(&mut self, connection: &mut TcpConnection) {
+        connection.set_read_idle_timeout(self.handler.idle_timeout());
+    }
+
+    fn idle(&mut self, connection: &mut TcpConnection, _idle: Idle) {
+        let responses = self.responses.borrow();
+        // NOTE: the connection is not idle while waiting for a response or shortly after sending it.
+        let recent_response = self.handler.idle_timeout()
+            .is_some_and(|timeout| responses.last_response.elapsed() < timeout);
+        // NOTE: a client slowly reading a big response is not idle.
+        if connection.pending_bytes() == 0 && responses.responses.is_empty() && !recent_response {
+            connection.dispose();
+            self.closing = true;
+        }
     }
 
     fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
-        let request = String::from_utf8(data).unwrap_or_else(|_| String::new());
-        let mut lines = request.lines();
-        let first_line = lines.next().unwrap_or("GET");
-        let mut parts = first_line.split_whitespace();
-        let method = parts.next().unwrap_or("GET");
-        let url = parts.next().unwrap_or("/");
-        let mut url_parts = url.split('?');
-        let request = Request {
-            method: Method::from_str(method),
-            path: url_parts.next().unwrap_or("/").to_string(),
-            query_string: url_parts.next().unwrap_or("").to_string(),
-        };
-        let content = self.handler.request(&request);
-        let len = content.len();
-        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/html\r\n\r\n{}", len, content);
-        let _ = connection.write(response.into_bytes()); // TODO: handle errors.
+        if self.closing || self.responses.borrow().closed {
+            return;
+        }
+        self.parser.feed(data);
+        loop {
+            let result = self.parser.next_request()
+                .and_then(|request| match request {
+                    Some(Request { method: Method::Extension(ref method), .. })
+                        if !self.handler.implements_extension(method) => Err(ParseError::NotImplemented),
+                    request => Ok(request),
+                });
+            match result {
+                Ok(Some(request)) => {
+                    let responder = self.responder(connection, &request);
+                    if !request.keep_alive() {
+                        self.closing = true;
+                    }
+                    self.handler.request(&request, responder);
+                    if self.closing || self.responses.borrow().closed {
+                        break;
+                    }
+                },
+                Ok(None) => break,
+                Err(error) => {
+                    let response = Response::new(error.status())
+                        .with_header("Connection", "close");
+                    let mut responses = self.responses.borrow_mut();
+                    // NOTE: the error is sent after the responses to the previous requests.
+                    responses.push(Some((response.to_bytes(), false)));
+                    responses.flush(connection);
+                    self.closing = true;
+                    break;
+                },
+            }
+        }
     }
 
     fn closed(&mut self, _connection: &mut TcpConnection) {
+        self.responses.borrow_mut().closed = true;
+    }
+}
+
+/// A request received by the server.
+///
+/// Its parts refer to the data received instead of being copied out of it, so cloning a request
+/// is cheap.
+#[derive(Clone)]
+pub struct Request {
+    pub(crate) body: Range<usize>,
+    // The data received, which can also contain the requests pipelined with this one.
+    pub(crate) data: Rc<Vec<u8>>,
+    pub(crate) headers: Vec<(Range<usize>, Range<usize>)>,
+    pub method: Method,
+    /// Percent-decoded captures of the route matching the request, when using a `Router`.
+    pub params: Vec<(String, String)>,
+    pub(crate) path: Range<usize>,
+    pub(crate) query_string: Range<usize>,
+    pub version: Version,
+}
+
+impl Request {
+    pub fn body(&self) -> &[u8] {
+        &self.data[self.body.clone()]
+    }
+
+    pub fn headers(&self) -> RequestHeaders<'_> {
+        RequestHeaders::new(&self.data, &self.headers)
+    }
+
+    /// Get the value captured by the route segment `:name`, percent-decoded, or `*name`,
+    /// percent-encoded.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The percent-encoded path of the request target.
+    pub fn path(&self) -> &str {
+        // NOTE: the parser checked that the request target is ASCII.
+        str::from_utf8(&self.data[self.path.clone()]).unwrap_or("")
+    }
+
+    /// Decode the query string.
+    pub fn query(&self) -> io::Result<Query> {
+        Query::parse(self.query_string())
+    }
+
+    /// The query string of the request target, without the leading `?`.
+    pub fn query_string(&self) -> &str {
+        str::from_utf8(&self.data[self.query_string.clone()]).unwrap_or("")
+    }
+
+    /// Whether the client wants to keep the connection open after the response: this is the
+    /// default for HTTP/1.1, while HTTP/1.0 requires `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http10 => self.headers().has_token("Connection", "keep-alive"),
+            Version::Http11 => !self.headers().has_token("Connection", "close"),
+        }
     }
 }
 
-#[derive(PartialEq)]
-pub enum Method {
-    Get,
-    Post,
+/// The header fields of a `Request`, referring to the data received.
+///
+/// The values are only checked to be valid UTF-8 when they are accessed as `str`, so that a value
+/// containing obsolete non-ASCII text is still available with `get_bytes()`.
+#[derive(Clone, Copy)]
+pub struct RequestHeaders<'a> {
+    data: &'a [u8],
+    fields: &'a [(Range<usize>, Range<usize>)],
 }
 
-impl Method {
-    pub fn from_str(method: &str) -> Method {
-        match method {
-            "POST" => Method::Post,
-            "GET" | _ => Method::Get,
+impl<'a> RequestHeaders<'a> {
+    pub(crate) fn new(data: &'a [u8], fields: &'a [(Range<usize>, Range<usize>)]) -> Self {
+        Self {
+            data,
+            fields,
         }
     }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get_all_bytes(name).next().is_some()
+    }
+
+    /// Get the value of the first field named `name`, if it is valid UTF-8.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.get_bytes(name).and_then(|value| str::from_utf8(value).ok())
+    }
+
+    /// Get the values of all the fields named `name` which are valid UTF-8, in order.
+    pub fn get_all(&self, name: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.get_all_bytes(name)
+            .filter_map(|value| str::from_utf8(value).ok())
+    }
+
+    /// Get the values of all the fields named `name`, in order.
+    pub fn get_all_bytes(&self, name: &'a str) -> impl Iterator<Item=&'a [u8]> + 'a {
+        let data = self.data;
+        self.fields.iter()
+            .filter(move |(field_name, _)| data[field_name.clone()].eq_ignore_ascii_case(name.as_bytes()))
+            .map(move |(_, value)| &data[value.clone()])
+    }
+
+    /// Get the value of the first field named `name`.
+    pub fn get_bytes(&self, name: &str) -> Option<&'a [u8]> {
+        let data = self.data;
+        self.fields.iter()
+            .find(|(field_name, _)| data[field_name.clone()].eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| &data[value.clone()])
+    }
+
+    /// Check if the comma-separated values of the fields named `name` contain `token`, ignoring
+    /// the case.
+    pub fn has_token(&self, name: &'a str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Iterate over the names and the values of the fields, in order.
+    pub fn iter(&self) -> impl Iterator<Item=(&'a str, &'a [u8])> + 'a {
+        let data = self.data;
+        // NOTE: the parser checked that the names are tokens, which are ASCII.
+        self.fields.iter()
+            .map(move |(name, value)| (str::from_utf8(&data[name.clone()]).unwrap_or(""), &data[value.clone()]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
 }
 
-impl Display for Method {
-    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
-        let method =
-            match *self {
-                Method::Get => "GET",
-                Method::Post => "POST",
-            };
-        write!(formatter, "{}", method)
+/// Standard reason phrase of the status code `status`, or an empty string if it is unknown.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
     }
 }
 
-pub struct Request {
-    pub method: Method,
-    pub path: String,
-    pub query_string: String,
+pub struct Response {
+    pub body: Vec<u8>,
+    /// The `Content-Length` header is added when the response is sent.
+    pub headers: Headers,
+    pub reason: String,
+    pub status: u16,
+}
+
+impl Response {
+    /// Create an empty response with the standard reason phrase of `status`.
+    pub fn new(status: u16) -> Self {
+        Self {
+            body: vec![],
+            headers: Headers::new(),
+            reason: reason_phrase(status).to_string(),
+            status,
+        }
+    }
+
+    pub fn html<BODY: Into<Vec<u8>>>(body: BODY) -> Self {
+        Self::new(200)
+            .with_header("Content-Type", "text/html")
+            .with_body(body)
+    }
+
+    pub fn json<BODY: Into<Vec<u8>>>(body: BODY) -> Self {
+        Self::new(200)
+            .with_header("Content-Type", "application/json")
+            .with_body(body)
+    }
+
+    pub fn text<BODY: Into<Vec<u8>>>(body: BODY) -> Self {
+        Self::new(200)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// Create a redirection to `location` with a 3xx `status`.
+    ///
+    /// NOTE: a `location` containing CR, LF or NUL gives a `500` response when sent.
+    pub fn redirect(status: u16, location: &str) -> Self {
+        Self::new(status)
+            .with_header("Location", location)
+    }
+
+    // The status codes 1xx, 204 and 304 cannot have a body.
+    fn has_body(&self) -> bool {
+        self.status >= 200 && self.status != 204 && self.status != 304
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode(true)
+    }
+
+    // NOTE: a response whose reason phrase or header fields could split the head, like a header
+    // name which is not a token or a value containing CR or LF, is replaced by an empty 500
+    // response.
+    fn encode(&self, with_body: bool) -> Vec<u8> {
+        let valid = is_valid_field_value(&self.reason) &&
+            self.headers.iter().all(|(name, value)| is_valid_header_field(name, value));
+        if !valid {
+            return Self::new(500).encode(with_body);
+        }
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason);
+        if self.has_body() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("\r\n");
+        let mut bytes = head.into_bytes();
+        if with_body && self.has_body() {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+
+    pub fn with_body<BODY: Into<Vec<u8>>>(mut self, body: BODY) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Add a header, keeping the other headers with the same name.
+    ///
+    /// NOTE: a `name` which is not a token or a `value` containing CR, LF or NUL gives a `500`
+    /// response when sent.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+}
+
+impl From<String> for Response {
+    fn from(body: String) -> Self {
+        Response::html(body)
+    }
+}
+
+impl<'a> From<&'a str> for Response {
+    fn from(body: &'a str) -> Self {
+        Response::html(body)
+    }
+}
+
+/// Limits on the requests accepted by the server. Exceeding them results in a `413` response
+/// for the body and a `431` response for the header fields.
+///
+/// The http client applies the same limits to the responses, see `Http::with_limits()`.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub max_body_size: usize,
+    pub max_header_count: usize,
+    /// Maximum length of the start line and of every header field line.
+    pub max_line_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_body_size: 1024 * 1024,
+            max_header_count: 100,
+            max_line_length: 8192,
+        }
+    }
 }
 
 pub trait HttpHandler: Clone {
-    fn request(&mut self, request: &Request) -> String;
+    /// Time after which a connection without requests is closed.
+    fn idle_timeout(&self) -> Option<Duration> {
+        Some(DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// Whether the extension method `method` is implemented. The requests with other extension
+    /// methods get a `501` response.
+    fn implements_extension(&self, _method: &str) -> bool {
+        false
+    }
+
+    fn limits(&self) -> Limits {
+        Limits::default()
+    }
+
+    fn request(&mut self, request: &Request) -> Response;
+}
+
+/// Handler of requests whose responses are sent with a `Responder`, possibly after `request`
+/// returns, e.g. from the `update` of another `Handler`.
+///
+/// Every `HttpHandler` is a `DeferredHttpHandler` which responds right away.
+pub trait DeferredHttpHandler: Clone {
+    /// Time after which a connection without requests is closed.
+    fn idle_timeout(&self) -> Option<Duration> {
+        Some(DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// Whether the extension method `method` is implemented. The requests with other extension
+    /// methods get a `501` response.
+    fn implements_extension(&self, _method: &str) -> bool {
+        false
+    }
+
+    fn limits(&self) -> Limits {
+        Limits::default()
+    }
+
+    fn request(&mut self, request: &Request, responder: Responder);
+}
+
+impl<HANDLER: HttpHandler> DeferredHttpHandler for HANDLER {
+    fn idle_timeout(&self) -> Option<Duration> {
+        HttpHandler::idle_timeout(self)
+    }
+
+    fn implements_extension(&self, method: &str) -> bool {
+        HttpHandler::implements_extension(self, method)
+    }
+
+    fn limits(&self) -> Limits {
+        HttpHandler::limits(self)
+    }
+
+    fn request(&mut self, request: &Request, responder: Responder) {
+        responder.respond(HttpHandler::request(self, request));
+    }
+}
+
+enum Segment {
+    Param(String),
+    Static(String),
+    Wildcard(String),
+}
+
+struct Route {
+    handler: Box<dyn Fn(&Request) -> Response>,
+    method: Method,
+    segments: Vec<Segment>,
+}
+
+impl Route {
+    fn new(method: Method, pattern: &str, handler: Box<dyn Fn(&Request) -> Response>) -> Self {
+        let parts: Vec<_> = pattern.strip_prefix('/').unwrap_or(pattern).split('/').collect();
+        let mut segments = vec![];
+        for (index, part) in parts.iter().enumerate() {
+            let segment =
+                if let Some(name) = part.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                }
+                else if let Some(name) = part.strip_prefix('*') {
+                    assert!(index == parts.len() - 1, "the wildcard must be the last segment of the route {}", pattern);
+                    Segment::Wildcard(name.to_string())
+                }
+                else {
+                    Segment::Static(part.to_string())
+                };
+            segments.push(segment);
+        }
+        Self {
+            handler,
+            method,
+            segments,
+        }
+    }
+
+    // Match the path against the pattern of the route, returning the captures, or an error when a
+    // capture has an invalid percent-encoding.
+    fn captures(&self, path: &str) -> Option<io::Result<Vec<(String, String)>>> {
+        let mut captures = vec![];
+        let mut rest = Some(path.strip_prefix('/').unwrap_or(path));
+        for segment in &self.segments {
+            let path = rest.unwrap_or("");
+            let (part, next) =
+                match path.find('/') {
+                    Some(index) => (&path[..index], Some(&path[index + 1..])),
+                    None => (path, None),
+                };
+            match *segment {
+                Segment::Param(ref name) => {
+                    if rest.is_none() || part.is_empty() {
+                        return None;
+                    }
+                    captures.push(percent_decode(part).map(|value| (name.clone(), value)));
+                },
+                Segment::Static(ref text) => {
+                    if rest.is_none() || part != text {
+                        return None;
+                    }
+                },
+                Segment::Wildcard(ref name) => {
+                    // NOTE: the wildcard also matches an empty tail. It is left percent-encoded
+                    // so that an encoded `/` is not confused with a separator.
+                    captures.push(Ok((name.clone(), path.to_string())));
+                    return Some(captures.into_iter().collect());
+                },
+            }
+            rest = next;
+        }
+        if rest.is_some() {
+            return None;
+        }
+        Some(captures.into_iter().collect())
+    }
+}
+
+/// Handler dispatching the requests to the first route matching their method and path.
+///
+/// The patterns are made of static segments, `:name` segments capturing one segment and a
+/// `*name` segment capturing the rest of the path, e.g. `/users/:id/files/*path`. The captures
+/// are available with `Request::param()`: the `:name` captures are percent-decoded, with a `400`
+/// response sent for an invalid percent-encoding, while the `*name` capture is left
+/// percent-encoded.
+/// A `404` response is sent when no route matches the path and a `405` response when no route
+/// matches the method. The `HEAD` requests are handled by the `GET` routes when no `HEAD` route
+/// matches first.
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Vec<Rc<Route>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get<HANDLER>(self, pattern: &str, handler: HANDLER) -> Self
+    where HANDLER: Fn(&Request) -> Response + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn delete<HANDLER>(self, pattern: &str, handler: HANDLER) -> Self
+    where HANDLER: Fn(&Request) -> Response + 'static,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    pub fn patch<HANDLER>(self, pattern: &str, handler: HANDLER) -> Self
+    where HANDLER: Fn(&Request) -> Response + 'static,
+    {
+        self.route(Method::Patch, pattern, handler)
+    }
+
+    pub fn post<HANDLER>(self, pattern: &str, handler: HANDLER) -> Self
+    where HANDLER: Fn(&Request) -> Response + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<HANDLER>(self, pattern: &str, handler: HANDLER) -> Self
+    where HANDLER: Fn(&Request) -> Response + 'static,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    /// Add a route. Panics if the wildcard is not the last segment of `pattern`.
+    pub fn route<HANDLER>(mut self, method: Method, pattern: &str, handler: HANDLER) -> Self
+    where HANDLER: Fn(&Request) -> Response + 'static,
+    {
+        self.routes.push(Rc::new(Route::new(method, pattern, Box::new(handler))));
+        self
+    }
+}
+
+impl HttpHandler for Router {
+    fn implements_extension(&self, method: &str) -> bool {
+        self.routes.iter()
+            .any(|route| route.method.as_str() == method)
+    }
+
+    fn request(&mut self, request: &Request) -> Response {
+        let mut allowed_methods = vec![];
+        for route in &self.routes {
+            if let Some(captures) = route.captures(request.path()) {
+                if route.method == request.method || (route.method == Method::Get && request.method == Method::Head) {
+                    // NOTE: the clone refers to the same data as the request.
+                    let mut request = request.clone();
+                    request.params =
+                        match captures {
+                            Ok(captures) => captures,
+                            Err(_) => return Response::new(400),
+                        };
+                    return (route.handler)(&request);
+                }
+                if !allowed_methods.contains(&route.method) {
+                    allowed_methods.push(route.method.clone());
+                    if route.method == Method::Get && !allowed_methods.contains(&Method::Head) {
+                        allowed_methods.push(Method::Head);
+                    }
+                }
+            }
+        }
+        if allowed_methods.is_empty() {
+            return Response::new(404);
+        }
+        let allow: Vec<_> = allowed_methods.iter().map(ToString::to_string).collect();
+        Response::new(405)
+            .with_header("Allow", &allow.join(", "))
+    }
 }
 
 pub fn serve<HANDLER>(event_loop: &mut Loop, addr: &str, handler: HANDLER) -> io::Result<Stream<ListenerMsg>>
-where HANDLER: HttpHandler + 'static,
+where HANDLER: DeferredHttpHandler + 'static,
 {
-    TcpListener::ip4(event_loop, addr, Listener::new(handler))
+    TcpListener::bind(event_loop, addr, Listener::new(handler))
         .map(|(stream, _addr)| stream)
 }
+
+/// Serve HTTP requests from a listener shared with the event loops of other threads, e.g. the
+/// loops of a `Runtime`.
+pub fn serve_shared<HANDLER>(event_loop: &mut Loop, listener: &net::TcpListener, handler: HANDLER)
+    -> io::Result<Stream<ListenerMsg>>
+where HANDLER: DeferredHttpHandler + 'static,
+{
+    TcpListener::shared(event_loop, listener, Listener::new(handler))
+}
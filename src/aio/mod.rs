@@ -1,8 +1,13 @@
 pub mod poll;
 pub mod handler;
+pub mod dns;
 pub mod http;
+mod http_parser;
 pub mod http_server;
 pub mod net;
+pub mod runtime;
+pub mod signal;
 mod slab;
 pub mod stdio;
 mod uhttp_uri;
+pub mod uri;
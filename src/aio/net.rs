@@ -1,6 +1,7 @@
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::ffi::{CStr, CString};
+use std::fs;
 use std::io;
 use std::io::{
     ErrorKind,
@@ -8,19 +9,35 @@ use std::io::{
     Write,
 };
 use std::mem;
+use std::ops::Deref;
 use std::net::{
     self,
+    Ipv4Addr,
+    Ipv6Addr,
+    SocketAddr,
     TcpStream,
+    ToSocketAddrs,
 };
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::io::{
     AsRawFd,
+    FromRawFd,
     RawFd,
 };
+use std::os::unix::net::{
+    self as unix_net,
+    UnixDatagram,
+    UnixStream,
+};
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::rc::Rc;
 use std::str;
+use std::time::{Duration, Instant};
 
-use crate::aio::poll::{self, Mode};
+use crate::aio::poll::{self, Mode, Timer};
 use crate::aio::poll::ffi::epoll_event;
 use crate::aio::handler::{
     Loop,
@@ -46,7 +63,21 @@ enum StatusMode {
 
 // TODO: move this function elsewhere?
 pub fn set_nonblocking<A: AsRawFd>(socket: &A) -> io::Result<()> {
-    let val = unsafe { ffi::fcntl(socket.as_raw_fd(), ffi::F_SETFL, ffi::O_NONBLOCK) };
+    // NOTE: keep the other flags, like O_APPEND for an output redirected to a file.
+    let flags = file_status_flags(socket)?;
+    set_file_status_flags(socket, flags | ffi::O_NONBLOCK)
+}
+
+pub(crate) fn file_status_flags<A: AsRawFd>(file: &A) -> io::Result<i32> {
+    let flags = unsafe { ffi::fcntl(file.as_raw_fd(), ffi::F_GETFL, 0) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags)
+}
+
+pub(crate) fn set_file_status_flags<A: AsRawFd>(file: &A, flags: i32) -> io::Result<()> {
+    let val = unsafe { ffi::fcntl(file.as_raw_fd(), ffi::F_SETFL, flags) };
     if val < 0 {
         return Err(io::Error::last_os_error());
     }
@@ -54,55 +85,195 @@ pub fn set_nonblocking<A: AsRawFd>(socket: &A) -> io::Result<()> {
 }
 
 pub mod tcp {
+    use std::collections::VecDeque;
     use std::mem;
-    use std::net::TcpStream;
+    use std::net::{SocketAddr, TcpStream};
     use std::os::unix::io::FromRawFd;
-    use std::marker::PhantomData;
+    use std::time::Duration;
 
-    use crate::aio::poll::Mode;
+    use crate::aio::poll::{Mode, Timer};
     use crate::aio::poll::ffi::epoll_event;
     use crate::aio::handler::{
         Handler,
         Loop,
         Stream,
     };
+    use crate::aio::dns::{self, Addresses};
     use self::ffi::ErrNo;
     use self::Msg::*;
     use super::{
-        AddrInfoIter,
         ConnectionComponentMsg,
         ConnectionMsg,
         StatusMode,
         TcpConnection,
         TcpConnectionNotify,
-        close,
-        connect,
+        connect_address,
         ffi,
-        getaddrinfo,
         getsockopt,
         manage_connection,
         socket,
     };
 
+    /// Delay before starting the next connection attempt while the previous ones are still
+    /// pending, as recommended by RFC 8305.
+    const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
     pub enum Msg<NOTIFY> {
-        TryingConnectionToHost(NOTIFY, AddrInfoIter, u32),
-        WriteEvent(epoll_event, TcpConnection, NOTIFY, AddrInfoIter, u32),
+        TryingConnectionToHost(NOTIFY, Addresses, u32),
+        ConnectTimeout(u32),
+        NextAttempt,
+        WriteEvent(epoll_event, u32),
     }
 
+    // A connection attempt waiting for the socket to be writable.
+    struct Attempt {
+        connection: TcpConnection,
+        count: u32,
+        timer: Option<Timer>,
+    }
+
+    // Connect to the addresses of a host with the Happy Eyeballs algorithm (RFC 8305): a new
+    // attempt is started when the previous one fails or is still pending after a short delay, and
+    // the first one to succeed wins.
     struct Connector<NOTIFY> {
+        addresses: VecDeque<SocketAddr>,
+        attempts: Vec<Attempt>,
+        connection_notify: Option<NOTIFY>,
         connection_stream: Stream<ConnectionMsg>,
+        count: u32,
+        delay_timer: Option<Timer>,
         event_loop: Loop,
-        _phantom: PhantomData<NOTIFY>,
     }
 
-    impl<NOTIFY> Connector<NOTIFY> {
+    impl<NOTIFY> Connector<NOTIFY>
+    where NOTIFY: TcpConnectionNotify + 'static,
+    {
         fn new(connection_stream: &Stream<ConnectionMsg>, event_loop: &Loop) -> Self {
             Self {
+                addresses: VecDeque::new(),
+                attempts: vec![],
+                connection_notify: None,
                 connection_stream: connection_stream.clone(),
+                count: 0,
+                delay_timer: None,
                 event_loop: event_loop.clone(),
-                _phantom: PhantomData,
             }
         }
+
+        // Cancel the delay before the next attempt and close the pending attempts.
+        fn close_attempts(&mut self) {
+            if let Some(timer) = self.delay_timer.take() {
+                let _ = self.event_loop.cancel_timer(&timer);
+            }
+            for attempt in mem::take(&mut self.attempts) {
+                if let Some(ref timer) = attempt.timer {
+                    let _ = self.event_loop.cancel_timer(timer);
+                }
+                if let Some(fd) = attempt.connection.as_raw_fd() {
+                    let _ = self.event_loop.remove_raw_fd(fd);
+                }
+                attempt.connection.close();
+            }
+        }
+
+        // Close the attempts which lost the race and hand the connection to the notify.
+        fn connected(&mut self, stream: &Stream<Msg<NOTIFY>>, connection: TcpConnection, connection_notify: NOTIFY) {
+            self.close_attempts();
+            manage_connection(&mut self.event_loop, connection, Box::new(connection_notify), Some(&self.connection_stream));
+            stream.stop();
+        }
+
+        // Start a connection attempt with the next address, or give up when all of them failed.
+        fn start_attempt(&mut self, stream: &Stream<Msg<NOTIFY>>) {
+            if let Some(timer) = self.delay_timer.take() {
+                let _ = self.event_loop.cancel_timer(&timer);
+            }
+            let mut connection_notify =
+                match self.connection_notify.take() {
+                    Some(connection_notify) => connection_notify,
+                    None => return,
+                };
+            while let Some(address) = self.addresses.pop_front() {
+                let count = self.count;
+                self.count += 1;
+                let family = if address.is_ipv4() { ffi::AF_INET } else { ffi::AF_INET6 };
+                let fd =
+                    match socket(family, ffi::SOCK_STREAM | ffi::SOCK_NONBLOCK, 0) {
+                        Ok(fd) => fd,
+                        Err(_) => continue,
+                    };
+                let tcp_stream = unsafe { TcpStream::from_raw_fd(fd) };
+                let mut connection = TcpConnection::new(tcp_stream);
+                connection_notify.connecting(&mut connection, count);
+                match connect_address(fd, &address) {
+                    Ok(()) => {
+                        self.connected(stream, connection, connection_notify);
+                        return;
+                    },
+                    Err(ref error) if error.raw_os_error() == Some(ErrNo::InProgress as i32) => {
+                        match self.event_loop.try_add_raw_fd_oneshot(fd, Mode::Write) {
+                            Ok(event) => {
+                                event.set_callback(stream, move |event| WriteEvent(event, count));
+                                let timer =
+                                    match connection.connect_timeout() {
+                                        Some(duration) =>
+                                            match self.event_loop.set_timeout(duration, stream, ConnectTimeout(count)) {
+                                                Ok(timer) => Some(timer),
+                                                Err(error) => {
+                                                    connection_notify.error(error);
+                                                    None
+                                                },
+                                            },
+                                        None => None,
+                                    };
+                                self.attempts.push(Attempt {
+                                    connection,
+                                    count,
+                                    timer,
+                                });
+                                if !self.addresses.is_empty() {
+                                    match self.event_loop.set_timeout(CONNECTION_ATTEMPT_DELAY, stream, NextAttempt) {
+                                        Ok(timer) => self.delay_timer = Some(timer),
+                                        Err(error) => connection_notify.error(error),
+                                    }
+                                }
+                                self.connection_notify = Some(connection_notify);
+                            },
+                            Err(error) => {
+                                // NOTE: the socket of this attempt is closed when the connection
+                                // is dropped.
+                                self.close_attempts();
+                                connection_notify.error(error);
+                                connection_notify.connect_failed();
+                                self.connection_stream.stop();
+                                stream.stop();
+                            },
+                        }
+                        return;
+                    },
+                    // NOTE: the socket is closed when the connection is dropped.
+                    Err(_) => (),
+                }
+            }
+            if self.attempts.is_empty() {
+                connection_notify.connect_failed();
+                self.connection_stream.stop();
+                stream.stop();
+            }
+            else {
+                self.connection_notify = Some(connection_notify);
+            }
+        }
+
+        // Take the pending attempt identified by count.
+        fn take_attempt(&mut self, count: u32) -> Option<Attempt> {
+            let index = self.attempts.iter().position(|attempt| attempt.count == count)?;
+            let attempt = self.attempts.remove(index);
+            if let Some(ref timer) = attempt.timer {
+                let _ = self.event_loop.cancel_timer(timer);
+            }
+            Some(attempt)
+        }
     }
 
     impl<NOTIFY> Handler for Connector<NOTIFY>
@@ -112,94 +283,88 @@ pub mod tcp {
 
         fn update(&mut self, stream: &Stream<Msg<NOTIFY>>, msg: Msg<NOTIFY>) {
             match msg {
-                TryingConnectionToHost(mut connection_notify, mut address_infos, count) => {
-                    match address_infos.next() {
-                        Some(address_info) => {
-                            match socket(address_info.ai_family, address_info.ai_socktype | ffi::SOCK_NONBLOCK,
-                                         address_info.ai_protocol)
-                            {
-                                Ok(fd) => {
-                                    let tcp_stream = unsafe { TcpStream::from_raw_fd(fd) };
-                                    let mut connection = TcpConnection::new(tcp_stream);
-                                    connection_notify.connecting(&mut connection, count);
-                                    match unsafe { connect(fd, address_info.ai_addr, address_info.ai_addrlen) } {
-                                        Ok(()) => {
-                                            manage_connection(&mut self.event_loop, connection, Box::new(connection_notify),
-                                                Some(&self.connection_stream));
-                                            //return ProcessContinuation::Stop;
-                                        },
-                                        Err(ref error) if error.raw_os_error() == Some(ErrNo::InProgress as i32) => {
-                                            let result = self.event_loop.try_add_raw_fd_oneshot(fd, Mode::Write);
-                                            match result {
-                                                Ok(event) => {
-                                                    event.set_callback(&stream,
-                                                        // TODO: check if it should be count + 1.
-                                                        move |event| WriteEvent(event, connection, connection_notify, address_infos, count)
-                                                    );
-                                                },
-                                                Err(error) => connection_notify.error(error),
-                                            }
-                                        },
-                                        Err(_) => {
-                                            stream.send(TryingConnectionToHost(connection_notify, address_infos, count + 1));
-
-                                            // Note that errors are ignored when closing a file descriptor. The
-                                            // reason for this is that if an error occurs we don't actually know if
-                                            // the file descriptor was closed or not, and if we retried (for
-                                            // something like EINTR), we might close another valid file descriptor
-                                            // opened after we closed ours.
-                                            let _ = close(fd);
-                                        },
-                                    }
-                                },
-                                Err(_) => stream.send(TryingConnectionToHost(connection_notify, address_infos, count + 1)),
-                            }
-                        },
-                        None => connection_notify.connect_failed(),
+                TryingConnectionToHost(connection_notify, addresses, count) => {
+                    self.addresses = interleave_families(addresses);
+                    self.connection_notify = Some(connection_notify);
+                    self.count = count;
+                    self.start_attempt(stream);
+                },
+                ConnectTimeout(count) => {
+                    if let Some(mut attempt) = self.take_attempt(count) {
+                        if let Some(fd) = attempt.connection.as_raw_fd() {
+                            let _ = self.event_loop.remove_raw_fd(fd);
+                        }
+                        if let Some(ref mut connection_notify) = self.connection_notify {
+                            connection_notify.connect_timeout(&mut attempt.connection, count);
+                        }
+                        attempt.connection.close();
+                        self.start_attempt(stream);
                     }
                 },
-                WriteEvent(event, connection, mut connection_notify, address_infos, count) => {
+                NextAttempt => {
+                    self.delay_timer = None;
+                    self.start_attempt(stream);
+                },
+                WriteEvent(event, count) => {
+                    let attempt =
+                        match self.take_attempt(count) {
+                            Some(attempt) => attempt,
+                            None => return,
+                        };
                     let fd =
-                        match connection.as_raw_fd() {
+                        match attempt.connection.as_raw_fd() {
                             Some(fd) => fd,
                             None => return,
                         };
-                    if (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0 {
-                        stream.send(TryingConnectionToHost(connection_notify, address_infos, count + 1));
-                    }
-                    // TODO: should we check for Write when there's an error?
-                    else if event.events & Mode::Write as u32 != 0 {
-                        let result = getsockopt(fd, ffi::SOL_SOCKET, ffi::SO_ERROR);
-                        match result {
-                            Ok(value) if value != 0 => {
-                                let _ = close(fd);
-                                stream.send(TryingConnectionToHost(connection_notify, address_infos, count + 1));
-                            },
-                            Ok(_) => {
-                                if let Err(error) = self.event_loop.remove_raw_fd(fd) {
-                                    // TODO: not sure if it makes sense to report this error to the user.
-                                    connection_notify.error(error);
-                                }
-                                manage_connection(&mut self.event_loop, connection, Box::new(connection_notify), Some(&self.connection_stream));
-                                // TODO: stop handler here.
-                            },
-                            Err(_) => {
-                                let _ = close(fd);
-                                stream.send(TryingConnectionToHost(connection_notify, address_infos, count + 1));
-                            },
+                    let connected =
+                        (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) == 0 &&
+                        event.events & Mode::Write as u32 != 0 &&
+                        getsockopt(fd, ffi::SOL_SOCKET, ffi::SO_ERROR).ok() == Some(0);
+                    if connected {
+                        if let Some(mut connection_notify) = self.connection_notify.take() {
+                            if let Err(error) = self.event_loop.remove_raw_fd(fd) {
+                                // TODO: not sure if it makes sense to report this error to the user.
+                                connection_notify.error(error);
+                            }
+                            self.connected(stream, attempt.connection, connection_notify);
                         }
                     }
+                    else {
+                        let _ = self.event_loop.remove_raw_fd(fd);
+                        self.start_attempt(stream);
+                    }
+                },
+            }
+        }
+    }
+
+    // Order the addresses by alternating their families, starting with the family of the first
+    // address, which is the one preferred by getaddrinfo() (RFC 8305 section 4).
+    fn interleave_families(addresses: Addresses) -> VecDeque<SocketAddr> {
+        let mut addresses = addresses.peekable();
+        let ipv6_first = addresses.peek().is_some_and(SocketAddr::is_ipv6);
+        let (preferred, others): (Vec<_>, Vec<_>) = addresses.partition(|address| address.is_ipv6() == ipv6_first);
+        let mut preferred = preferred.into_iter();
+        let mut others = others.into_iter();
+        let mut result = VecDeque::new();
+        loop {
+            match (preferred.next(), others.next()) {
+                (None, None) => break,
+                (first, second) => {
+                    result.extend(first);
+                    result.extend(second);
                 },
             }
         }
+        result
     }
 
-    struct Connection {
+    pub(super) struct Connection {
         connection: Option<Stream<ConnectionComponentMsg>>,
     }
 
     impl Connection {
-        fn new() -> Self {
+        pub(super) fn new() -> Self {
             Self {
                 connection: None,
             }
@@ -209,6 +374,12 @@ pub mod tcp {
     impl Handler for Connection {
         type Msg = ConnectionMsg;
 
+        fn stopped(&mut self) {
+            if let Some(ref connection) = self.connection {
+                connection.stop();
+            }
+        }
+
         fn update(&mut self, _stream: &Stream<Self::Msg>, msg: Self::Msg) {
             match msg {
                 ConnectionMsg::Connected(connection) => self.connection = Some(connection),
@@ -224,24 +395,184 @@ pub mod tcp {
         }
     }
 
-    pub fn connect_to_host<NOTIFY>(host: &str, port: &str, event_loop: &mut Loop, mut connection_notify: NOTIFY) -> Option<Stream<ConnectionMsg>>
+    /// Connect to `host` once its addresses are resolved without blocking the event loop.
+    ///
+    /// When the resolution fails, `error()` is called with the reason, followed by
+    /// `connect_failed()`.
+    pub fn connect_to_host<NOTIFY>(host: &str, port: u16, event_loop: &mut Loop, connection_notify: NOTIFY) -> Option<Stream<ConnectionMsg>>
     where NOTIFY: TcpConnectionNotify + 'static,
     {
-        let mut hints: ffi::addrinfo = unsafe { mem::zeroed() };
-        hints.ai_socktype = ffi::SOCK_STREAM as i32;
-        // TODO: use getaddrinfo_a which is asynchronous. Maybe not: https://medium.com/where-the-flamingcow-roams/asynchronous-name-resolution-in-c-268ff5df3081
-        match getaddrinfo(Some(host), Some(port), Some(hints)) {
-            Ok(address_infos) => {
-                let connection_stream = event_loop.spawn(Connection::new());
-                let connector = Connector::new(&connection_stream, event_loop);
-                let stream = event_loop.spawn(connector);
-                stream.send(TryingConnectionToHost(connection_notify, address_infos, 0));
-                Some(connection_stream)
-            },
-            Err(error) => {
-                connection_notify.error(error); // FIXME: do we really want to both notify and return the error?
-                None
-            },
+        connect_to_host_addresses(host, port, event_loop, connection_notify, |_| true)
+    }
+
+    // Connect to the addresses of `host` accepted by the filter.
+    pub(super) fn connect_to_host_addresses<NOTIFY>(host: &str, port: u16, event_loop: &mut Loop, mut connection_notify: NOTIFY,
+        filter: fn(&SocketAddr) -> bool) -> Option<Stream<ConnectionMsg>>
+    where NOTIFY: TcpConnectionNotify + 'static,
+    {
+        let connection_stream = event_loop.spawn(Connection::new());
+        let connector = Connector::new(&connection_stream, event_loop);
+        let stream = event_loop.spawn(connector);
+        let resolved_connection_stream = connection_stream.clone();
+        dns::resolve(event_loop, host, port, move |result| {
+            match result {
+                Ok(addresses) => {
+                    let addresses = addresses.filter(filter).collect::<Vec<_>>().into_iter();
+                    stream.send(TryingConnectionToHost(connection_notify, addresses, 0));
+                },
+                Err(error) => {
+                    connection_notify.error(error);
+                    connection_notify.connect_failed();
+                    resolved_connection_stream.stop();
+                    stream.stop();
+                },
+            }
+        });
+        Some(connection_stream)
+    }
+
+    #[cfg(test)]
+    mod test {
+        use std::cell::RefCell;
+        use std::fs;
+        use std::net::{self, SocketAddr};
+        use std::os::unix::io::RawFd;
+        use std::path::PathBuf;
+        use std::rc::Rc;
+        use std::time::{Duration, Instant};
+
+        use crate::aio::handler::Loop;
+        use super::{CONNECTION_ATTEMPT_DELAY, Connection, Connector, Msg};
+        use super::super::{TcpConnection, TcpConnectionNotify};
+
+        // Fill the accept queue of a listener which never accepts, so that the next connections
+        // hang.
+        fn blackhole() -> (net::TcpListener, Vec<net::TcpStream>) {
+            let listener = net::TcpListener::bind("127.0.0.1:0").expect("bind");
+            let address = listener.local_addr().expect("local address");
+            let mut streams = vec![];
+            while let Ok(stream) = net::TcpStream::connect_timeout(&address, Duration::from_millis(100)) {
+                streams.push(stream);
+            }
+            (listener, streams)
+        }
+
+        // Connect to the addresses in order, without resolving a host.
+        fn connect<NOTIFY>(event_loop: &mut Loop, addresses: Vec<SocketAddr>, connection_notify: NOTIFY)
+        where NOTIFY: TcpConnectionNotify + 'static,
+        {
+            let connection_stream = event_loop.spawn(Connection::new());
+            let connector = Connector::new(&connection_stream, event_loop);
+            let stream = event_loop.spawn(connector);
+            stream.send(Msg::TryingConnectionToHost(connection_notify, addresses.into_iter(), 0));
+        }
+
+        // The file of the socket of a file descriptor.
+        fn socket_file(fd: RawFd) -> Option<PathBuf> {
+            fs::read_link(format!("/proc/self/fd/{}", fd)).ok()
+        }
+
+        struct Client {
+            connect_timeout: Option<Duration>,
+            event_loop: Loop,
+            events: Rc<RefCell<Vec<String>>>,
+            // The file descriptor and the socket file of every attempt.
+            sockets: Rc<RefCell<Vec<(RawFd, PathBuf)>>>,
+        }
+
+        impl Client {
+            fn new(event_loop: &Loop, connect_timeout: Option<Duration>) -> Self {
+                Self {
+                    connect_timeout,
+                    event_loop: event_loop.clone(),
+                    events: Rc::new(RefCell::new(vec![])),
+                    sockets: Rc::new(RefCell::new(vec![])),
+                }
+            }
+        }
+
+        impl TcpConnectionNotify for Client {
+            fn connecting(&mut self, connection: &mut TcpConnection, count: u32) {
+                connection.set_connect_timeout(self.connect_timeout);
+                self.events.borrow_mut().push(format!("connecting {}", count));
+                let fd = connection.as_raw_fd().expect("fd");
+                self.sockets.borrow_mut().push((fd, socket_file(fd).expect("socket file")));
+            }
+
+            fn connect_failed(&mut self) {
+                self.events.borrow_mut().push("connect failed".to_string());
+                self.event_loop.stop();
+            }
+
+            fn connect_timeout(&mut self, _connection: &mut TcpConnection, count: u32) {
+                self.events.borrow_mut().push(format!("connect timeout {}", count));
+            }
+
+            fn connected(&mut self, connection: &mut TcpConnection) {
+                self.events.borrow_mut().push("connected".to_string());
+                connection.dispose();
+                self.event_loop.stop();
+            }
+        }
+
+        #[test]
+        fn test_connect_timeout() {
+            let (blackhole, _streams) = blackhole();
+            let port = blackhole.local_addr().expect("local address").port();
+
+            let mut event_loop = Loop::new().expect("event loop");
+            let client = Client::new(&event_loop, Some(Duration::from_millis(50)));
+            let events = client.events.clone();
+            assert!(TcpConnection::ip4(&mut event_loop, "127.0.0.1", port, client).is_some());
+            let start = Instant::now();
+            event_loop.run().expect("event loop run");
+            assert!(start.elapsed() >= Duration::from_millis(50));
+            assert_eq!(*events.borrow(), vec!["connecting 0", "connect timeout 0", "connect failed"]);
+        }
+
+        #[test]
+        fn test_connect_timeout_next_address() {
+            let (blackhole, _streams) = blackhole();
+            let listener = net::TcpListener::bind("127.0.0.1:0").expect("bind");
+            let addresses = vec![
+                blackhole.local_addr().expect("local address"),
+                listener.local_addr().expect("local address"),
+            ];
+
+            let mut event_loop = Loop::new().expect("event loop");
+            // The timeout is shorter than the delay between attempts, so it is what starts the
+            // second attempt.
+            let client = Client::new(&event_loop, Some(Duration::from_millis(50)));
+            let events = client.events.clone();
+            connect(&mut event_loop, addresses, client);
+            event_loop.run().expect("event loop run");
+            assert_eq!(*events.borrow(), vec!["connecting 0", "connect timeout 0", "connecting 1", "connected"]);
+        }
+
+        #[test]
+        fn test_next_attempt_wins() {
+            let (blackhole, _streams) = blackhole();
+            let listener = net::TcpListener::bind("127.0.0.1:0").expect("bind");
+            let addresses = vec![
+                blackhole.local_addr().expect("local address"),
+                listener.local_addr().expect("local address"),
+            ];
+
+            let mut event_loop = Loop::new().expect("event loop");
+            let client = Client::new(&event_loop, None);
+            let events = client.events.clone();
+            let sockets = client.sockets.clone();
+            let start = Instant::now();
+            connect(&mut event_loop, addresses, client);
+            event_loop.run().expect("event loop run");
+            // The second attempt starts after the delay while the first one is still pending.
+            assert_eq!(*events.borrow(), vec!["connecting 0", "connecting 1", "connected"]);
+            let elapsed = start.elapsed();
+            assert!(elapsed >= CONNECTION_ATTEMPT_DELAY && elapsed < CONNECTION_ATTEMPT_DELAY * 2, "{:?}", elapsed);
+
+            // The socket of the attempt which lost the race is closed.
+            let (fd, ref file) = sockets.borrow()[0];
+            assert_ne!(socket_file(fd).as_ref(), Some(file));
         }
     }
 }
@@ -294,6 +625,35 @@ pub unsafe fn connect(socket: RawFd, address: *const ffi::sockaddr, address_len:
     Ok(())
 }
 
+/// Connect `socket` to `address`.
+pub fn connect_address(socket: RawFd, address: &SocketAddr) -> io::Result<()> {
+    match *address {
+        SocketAddr::V4(ref address) => {
+            let address = ffi::sockaddr_in {
+                sin_family: ffi::AF_INET as u16,
+                sin_port: address.port().to_be(),
+                sin_addr: address.ip().octets(),
+                sin_zero: [0; 8],
+            };
+            unsafe {
+                connect(socket, &address as *const _ as *const ffi::sockaddr, mem::size_of_val(&address) as ffi::socklen_t)
+            }
+        },
+        SocketAddr::V6(ref address) => {
+            let address = ffi::sockaddr_in6 {
+                sin6_family: ffi::AF_INET6 as u16,
+                sin6_port: address.port().to_be(),
+                sin6_flowinfo: address.flowinfo(),
+                sin6_addr: address.ip().octets(),
+                sin6_scope_id: address.scope_id(),
+            };
+            unsafe {
+                connect(socket, &address as *const _ as *const ffi::sockaddr, mem::size_of_val(&address) as ffi::socklen_t)
+            }
+        },
+    }
+}
+
 pub fn getaddrinfo(hostname: Option<&str>, service: Option<&str>, hints: Option<ffi::addrinfo>) ->
     io::Result<AddrInfoIter>
 {
@@ -379,24 +739,82 @@ impl Buffer {
     }
 }
 
+// The socket of a connection.
+enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Socket::Tcp(ref stream) => stream.as_raw_fd(),
+            Socket::Unix(ref stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref mut stream) => stream.read(buffer),
+            Socket::Unix(ref mut stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref mut stream) => stream.write(buffer),
+            Socket::Unix(ref mut stream) => stream.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Socket::Tcp(ref mut stream) => stream.flush(),
+            Socket::Unix(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
 pub enum ConnectionMsg {
     Connected(Stream<ConnectionComponentMsg>),
     Write(Vec<u8>),
 }
 
 pub enum ConnectionComponentMsg {
+    IdleCheck,
     ReadWrite(epoll_event),
     Send,
+    ThrottleCheck,
     Write(Vec<u8>),
 }
 
+/// Direction in which a connection stayed quiet for longer than its idle timeout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Idle {
+    Read,
+    Write,
+}
+
 struct _TcpConnection {
-    // TODO: should the VecDeque be bounded?
+    // NOTE: the VecDeque is bounded by the watermarks, if the user stops writing when throttled.
     buffers: VecDeque<Buffer>, // The system should probably reuse the buffer and keep adding to it even if the trait does not consume its data. That should be better than a Vec inside a VecDeque.
+    connect_timeout: Option<Duration>,
     disposed: bool,
     handle: Option<Stream<ConnectionComponentMsg>>,
+    last_read: Instant,
+    last_write: Instant,
     muted: bool,
-    stream: Option<TcpStream>,
+    pending_bytes: usize,
+    read_idle_timeout: Option<Duration>,
+    stream: Option<Socket>,
+    throttled: bool,
+    // Low and high watermarks of the pending bytes.
+    watermarks: Option<(usize, usize)>,
+    write_idle_timeout: Option<Duration>,
 }
 
 impl _TcpConnection {
@@ -406,6 +824,8 @@ impl _TcpConnection {
             if let Some(ref mut stream) = self.stream {
                 match stream.write(first_buffer.slice()) {
                     Ok(written) => {
+                        self.last_write = Instant::now();
+                        self.pending_bytes -= written;
                         connection_notify.sent();
                         first_buffer.advance(written);
                         if first_buffer.exhausted() {
@@ -417,7 +837,9 @@ impl _TcpConnection {
                     Err(error) => {
                         connection_notify.error(error);
                         let _ = event_loop.remove_fd(stream);
-                        // TODO: remove the handler as well.
+                        if let Some(ref handle) = self.handle {
+                            handle.stop();
+                        }
                     },
                 }
             }
@@ -425,6 +847,30 @@ impl _TcpConnection {
         if remove_buffer {
             self.buffers.pop_front();
         }
+        self.update_throttled();
+    }
+
+    fn queue(&mut self, buffer: Buffer) {
+        self.pending_bytes += buffer.slice().len();
+        self.buffers.push_back(buffer);
+        if self.update_throttled() {
+            if let Some(ref handle) = self.handle {
+                handle.send(ConnectionComponentMsg::ThrottleCheck);
+            }
+        }
+    }
+
+    // Return true if the throttled state changed.
+    fn update_throttled(&mut self) -> bool {
+        let throttled =
+            match self.watermarks {
+                Some((_low, high)) if !self.throttled => self.pending_bytes > high,
+                Some((low, _high)) => self.pending_bytes > low,
+                None => false,
+            };
+        let changed = throttled != self.throttled;
+        self.throttled = throttled;
+        changed
     }
 }
 
@@ -435,13 +881,26 @@ pub struct TcpConnection {
 
 impl TcpConnection {
     pub fn new(stream: TcpStream) -> Self {
+        Self::with_socket(Socket::Tcp(stream))
+    }
+
+    fn with_socket(stream: Socket) -> Self {
+        let now = Instant::now();
         Self {
             connection: Rc::new(RefCell::new(_TcpConnection {
                 buffers: VecDeque::new(),
+                connect_timeout: None,
                 disposed: false,
                 handle: None,
+                last_read: now,
+                last_write: now,
                 muted: false,
+                pending_bytes: 0,
+                read_idle_timeout: None,
                 stream: Some(stream),
+                throttled: false,
+                watermarks: None,
+                write_idle_timeout: None,
             })),
         }
     }
@@ -450,9 +909,14 @@ impl TcpConnection {
         self.connection.borrow_mut().stream.take();
     }
 
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connection.borrow().connect_timeout
+    }
+
     // TODO: in debug mode, warn if dispose is not called (to help in detecting leaks). Maybe
     // easier to just check if the difference of the number of callbacks allocation - the number of
     // callbacks deallocation is greater than 0.
+    /// Close the connection once the pending bytes are sent.
     pub fn dispose(&self) {
         self.connection.borrow_mut().disposed = true;
     }
@@ -461,10 +925,25 @@ impl TcpConnection {
         self.connection.borrow().disposed
     }
 
+    /// Connect to `host` over IPv4 or IPv6, whichever connects first (Happy Eyeballs).
+    pub fn connect<NOTIFY>(event_loop: &mut Loop, host: &str, port: u16, connection: NOTIFY) -> Option<Stream<ConnectionMsg>>
+    where NOTIFY: TcpConnectionNotify + 'static,
+    {
+        tcp::connect_to_host(host, port, event_loop, connection)
+    }
+
+    /// Connect to the IPv4 addresses of `host`.
     pub fn ip4<NOTIFY>(event_loop: &mut Loop, host: &str, port: u16, connection: NOTIFY) -> Option<Stream<ConnectionMsg>>
     where NOTIFY: TcpConnectionNotify + 'static,
     {
-        tcp::connect_to_host(host, &port.to_string(), event_loop, connection)
+        tcp::connect_to_host_addresses(host, port, event_loop, connection, SocketAddr::is_ipv4)
+    }
+
+    /// Connect to the IPv6 addresses of `host`.
+    pub fn ip6<NOTIFY>(event_loop: &mut Loop, host: &str, port: u16, connection: NOTIFY) -> Option<Stream<ConnectionMsg>>
+    where NOTIFY: TcpConnectionNotify + 'static,
+    {
+        tcp::connect_to_host_addresses(host, port, event_loop, connection, SocketAddr::is_ipv6)
     }
 
     pub fn mute(&self) {
@@ -476,12 +955,29 @@ impl TcpConnection {
     }
 
     fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
-        if let Some(ref mut stream) = self.connection.borrow_mut().stream {
-            stream.read(buffer)
-        }
-        else {
-            Ok(0)
+        let mut connection = self.connection.borrow_mut();
+        let result =
+            if let Some(ref mut stream) = connection.stream {
+                stream.read(buffer)
+            }
+            else {
+                Ok(0)
+            };
+        if let Ok(bytes_read) = result {
+            if bytes_read > 0 {
+                connection.last_read = Instant::now();
+            }
         }
+        result
+    }
+
+    /// Number of bytes written to the connection that are waiting for the socket to be writable.
+    pub fn pending_bytes(&self) -> usize {
+        self.connection.borrow().pending_bytes
+    }
+
+    pub fn read_idle_timeout(&self) -> Option<Duration> {
+        self.connection.borrow().read_idle_timeout
     }
 
     fn send(&self, event_loop: &mut Loop, connection_notify: &mut dyn TcpConnectionNotify) {
@@ -489,18 +985,74 @@ impl TcpConnection {
         connection.send(event_loop, connection_notify);
     }
 
+    /// Set the maximum time to wait for the connection to be established.
+    ///
+    /// This must be called from `TcpConnectionNotify::connecting` to have an effect. When it
+    /// expires, `TcpConnectionNotify::connect_timeout` is called and the next address is tried.
+    pub fn set_connect_timeout(&self, timeout: Option<Duration>) {
+        self.connection.borrow_mut().connect_timeout = timeout;
+    }
+
+    /// Call `TcpConnectionNotify::throttled` when the pending bytes go above `high`, and
+    /// `TcpConnectionNotify::unthrottled` when they go back to `low` or below.
+    pub fn set_watermarks(&self, low: usize, high: usize) {
+        let mut connection = self.connection.borrow_mut();
+        connection.watermarks = Some((low.min(high), high));
+        if connection.update_throttled() {
+            if let Some(ref handle) = connection.handle {
+                handle.send(ConnectionComponentMsg::ThrottleCheck);
+            }
+        }
+    }
+
     fn set_handle(&self, handle: &Stream<ConnectionComponentMsg>) {
         self.connection.borrow_mut().handle = Some(handle.clone());
     }
 
+    /// Set the maximum time without receiving data before `TcpConnectionNotify::idle` is called.
+    pub fn set_read_idle_timeout(&self, timeout: Option<Duration>) {
+        self.connection.borrow_mut().read_idle_timeout = timeout;
+        self.check_idle();
+    }
+
+    /// Set the maximum time without sending data before `TcpConnectionNotify::idle` is called.
+    pub fn set_write_idle_timeout(&self, timeout: Option<Duration>) {
+        self.connection.borrow_mut().write_idle_timeout = timeout;
+        self.check_idle();
+    }
+
+    fn check_idle(&self) {
+        if let Some(ref handle) = self.connection.borrow().handle {
+            handle.send(ConnectionComponentMsg::IdleCheck);
+        }
+    }
+
+    /// Whether the pending bytes went above the high watermark and did not go back to the low
+    /// watermark yet.
+    pub fn throttled(&self) -> bool {
+        self.connection.borrow().throttled
+    }
+
     pub fn unmute(&self) {
         self.connection.borrow_mut().muted = false;
     }
 
+    pub fn write_idle_timeout(&self) -> Option<Duration> {
+        self.connection.borrow().write_idle_timeout
+    }
+
+    /// Write `buffer` to the connection.
+    ///
+    /// What cannot be written without blocking is queued and sent when the socket is writable.
     pub fn write(&self, buffer: Vec<u8>) -> io::Result<()> {
         let buffer_size = buffer.len();
         let mut index = 0;
         let mut connection = self.connection.borrow_mut();
+        // NOTE: queue behind the pending buffers to keep the data in order.
+        if !connection.buffers.is_empty() {
+            connection.queue(Buffer::new(buffer, index));
+            return Ok(());
+        }
         while index < buffer.len() {
             // TODO: yield to avoid starvation?
             let stream =
@@ -510,11 +1062,12 @@ impl TcpConnection {
                 };
             match stream.write(&buffer[index..]) {
                 Err(ref error) if error.kind() == ErrorKind::WouldBlock => {
-                    connection.buffers.push_back(Buffer::new(buffer, index));
+                    connection.queue(Buffer::new(buffer, index));
                     return Ok(());
                 },
                 Err(error) => return Err(error),
                 Ok(written) => {
+                    connection.last_write = Instant::now();
                     if let Some(ref handle) = connection.handle {
                         handle.send(ConnectionComponentMsg::Send);
                     }
@@ -533,89 +1086,401 @@ impl TcpConnection {
     }
 }
 
-struct ConnectionComponent {
+/// A connection to a Unix domain socket.
+///
+/// It has the API of `TcpConnection`, which is what the `TcpConnectionNotify` callbacks receive.
+#[derive(Clone)]
+pub struct UnixConnection {
     connection: TcpConnection,
-    connection_notify: Box<dyn TcpConnectionNotify>,
-    event_loop: Loop,
 }
 
-impl ConnectionComponent {
-    fn new(connection: TcpConnection, connection_notify: Box<dyn TcpConnectionNotify>, event_loop: &Loop) -> Self {
+impl UnixConnection {
+    pub fn new(stream: UnixStream) -> Self {
         Self {
-            connection,
-            connection_notify,
-            event_loop: event_loop.clone(),
+            connection: TcpConnection::with_socket(Socket::Unix(stream)),
         }
     }
-}
 
-impl Handler for ConnectionComponent {
-    type Msg = ConnectionComponentMsg;
+    /// Connect to the Unix domain socket at `path`.
+    ///
+    /// When the connection cannot be established, `error()` is called with the reason, followed by
+    /// `connect_failed()`.
+    pub fn connect<P, NOTIFY>(event_loop: &mut Loop, path: P, connection: NOTIFY) -> Option<Stream<ConnectionMsg>>
+    where P: AsRef<Path>,
+          NOTIFY: TcpConnectionNotify + 'static,
+    {
+        connect_unix(event_loop, unix_net::SocketAddr::from_pathname(path), connection)
+    }
 
-    fn update(&mut self, _stream: &Stream<Self::Msg>, msg: Self::Msg) {
-        match msg {
-            ConnectionComponentMsg::ReadWrite(event) => {
-                if (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0 {
-                    // TODO: do we want to signal these errors to the trait?
-                    // TODO: are we sure we want to remove the fd from epoll when there's an error?
-                    if let Some(fd) = self.connection.as_raw_fd() {
-                        if let Err(error) = self.event_loop.remove_raw_fd(fd) {
-                            // TODO: not sure if it makes sense to report this error to the user.
-                            self.connection_notify.error(error);
-                        }
-                        self.connection_notify.closed(&mut self.connection); // FIXME: should it only be called for HangupError?
-                        self.connection.close();
-                        // TODO: stop handler.
-                    }
-                }
-                if event.events & Mode::Read as u32 != 0 && !self.connection.muted() {
-                    let mut buffer = vec![0; 4096];
-                    match self.connection.read(&mut buffer) {
-                        Err(ref error) if error.kind() == ErrorKind::WouldBlock ||
-                            error.kind() == ErrorKind::Interrupted => (),
-                        Ok(bytes_read) => {
-                            if bytes_read > 0 {
-                                buffer.truncate(bytes_read);
-                                self.connection_notify.received(&mut self.connection, buffer);
-                            }
-                            else {
-                                if let Some(fd) = self.connection.as_raw_fd() {
-                                    let _ = self.event_loop.remove_raw_fd(fd);
-                                }
-                                self.connection_notify.closed(&mut self.connection);
-                                self.connection.close();
-                                // TODO: remove the handler as well.
-                            }
-                        },
-                        Err(_) => {
-                            if let Some(fd) = self.connection.as_raw_fd() {
-                                let _ = self.event_loop.remove_raw_fd(fd);
-                            }
-                            // TODO: remove the handler as well.
-                        },
-                    }
-                }
-                if event.events & Mode::Write as u32 != 0 {
-                    self.connection.send(&mut self.event_loop, &mut *self.connection_notify);
-                }
-                if self.connection.disposed() {
-                    self.connection_notify.closed(&mut self.connection);
-                    self.connection.close();
-                    // TODO: stop handler.
-                }
-            },
-            ConnectionComponentMsg::Send => {
-                self.connection_notify.sent();
-            },
-            ConnectionComponentMsg::Write(data) =>
-                if let Err(error) = self.connection.write(data) {
-                    self.connection_notify.error(error);
-                    if let Some(fd) = self.connection.as_raw_fd() {
-                        let _ = self.event_loop.remove_raw_fd(fd);
-                    }
-                    // TODO: remove the handler as well.
-                },
-        }
+    /// Connect to the abstract Unix domain socket `name`.
+    pub fn connect_abstract<NOTIFY>(event_loop: &mut Loop, name: &[u8], connection: NOTIFY) -> Option<Stream<ConnectionMsg>>
+    where NOTIFY: TcpConnectionNotify + 'static,
+    {
+        connect_unix(event_loop, unix_net::SocketAddr::from_abstract_name(name), connection)
+    }
+}
+
+impl Deref for UnixConnection {
+    type Target = TcpConnection;
+
+    fn deref(&self) -> &TcpConnection {
+        &self.connection
+    }
+}
+
+impl From<UnixConnection> for TcpConnection {
+    fn from(connection: UnixConnection) -> Self {
+        connection.connection
+    }
+}
+
+/// Delay before trying again to connect to a Unix domain socket whose accept queue is full.
+const UNIX_CONNECT_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+enum UnixConnectorMsg {
+    Connect,
+    ConnectTimeout,
+    WriteEvent(epoll_event),
+}
+
+// Connect to a Unix domain socket without blocking.
+//
+// NOTE: the connection is usually established right away, but the kernel answers EAGAIN instead
+// of waiting when the accept queue of the server is full. Since nothing notifies when there is
+// room in that queue again, the connection is retried after a delay.
+struct UnixConnector<NOTIFY> {
+    address: ffi::sockaddr_un,
+    address_len: ffi::socklen_t,
+    connection: TcpConnection,
+    connection_notify: Option<NOTIFY>,
+    connection_stream: Stream<ConnectionMsg>,
+    event_loop: Loop,
+}
+
+impl<NOTIFY> UnixConnector<NOTIFY>
+where NOTIFY: TcpConnectionNotify + 'static,
+{
+    fn connect(&mut self, stream: &Stream<UnixConnectorMsg>) {
+        let fd =
+            match self.connection.as_raw_fd() {
+                Some(fd) => fd,
+                None => return,
+            };
+        let result = unsafe {
+            connect(fd, &self.address as *const _ as *const ffi::sockaddr, self.address_len)
+        };
+        match result {
+            Ok(()) => self.connected(stream),
+            Err(ref error) if error.raw_os_error() == Some(ffi::ErrNo::InProgress as i32) => {
+                match self.event_loop.try_add_raw_fd_oneshot(fd, Mode::Write) {
+                    Ok(event) => event.set_callback(stream, UnixConnectorMsg::WriteEvent),
+                    Err(error) => self.failed(stream, error),
+                }
+            },
+            Err(ref error) if error.raw_os_error() == Some(ffi::ErrNo::Again as i32) => {
+                if let Err(error) = self.event_loop.set_timeout(UNIX_CONNECT_RETRY_DELAY, stream, UnixConnectorMsg::Connect) {
+                    self.failed(stream, error);
+                }
+            },
+            Err(error) => self.failed(stream, error),
+        }
+    }
+
+    fn connected(&mut self, stream: &Stream<UnixConnectorMsg>) {
+        if let Some(connection_notify) = self.connection_notify.take() {
+            if let Some(fd) = self.connection.as_raw_fd() {
+                let _ = self.event_loop.remove_raw_fd(fd);
+            }
+            manage_connection(&mut self.event_loop, self.connection.clone(), Box::new(connection_notify),
+                Some(&self.connection_stream));
+        }
+        stream.stop();
+    }
+
+    fn failed(&mut self, stream: &Stream<UnixConnectorMsg>, error: io::Error) {
+        if let Some(mut connection_notify) = self.connection_notify.take() {
+            connection_notify.error(error);
+            connection_notify.connect_failed();
+        }
+        self.connection.close();
+        self.connection_stream.stop();
+        stream.stop();
+    }
+}
+
+impl<NOTIFY> Handler for UnixConnector<NOTIFY>
+where NOTIFY: TcpConnectionNotify + 'static,
+{
+    type Msg = UnixConnectorMsg;
+
+    fn started(&mut self, stream: &Stream<UnixConnectorMsg>) {
+        if let Some(duration) = self.connection.connect_timeout() {
+            if let Err(error) = self.event_loop.set_timeout(duration, stream, UnixConnectorMsg::ConnectTimeout) {
+                self.failed(stream, error);
+                return;
+            }
+        }
+        stream.send(UnixConnectorMsg::Connect);
+    }
+
+    fn update(&mut self, stream: &Stream<UnixConnectorMsg>, msg: UnixConnectorMsg) {
+        match msg {
+            UnixConnectorMsg::Connect => self.connect(stream),
+            UnixConnectorMsg::ConnectTimeout => {
+                if let Some(mut connection_notify) = self.connection_notify.take() {
+                    connection_notify.connect_timeout(&mut self.connection, 0);
+                    connection_notify.connect_failed();
+                }
+                self.connection.close();
+                self.connection_stream.stop();
+                stream.stop();
+            },
+            UnixConnectorMsg::WriteEvent(event) => {
+                let fd =
+                    match self.connection.as_raw_fd() {
+                        Some(fd) => fd,
+                        None => return,
+                    };
+                let hangup = (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0;
+                match getsockopt(fd, ffi::SOL_SOCKET, ffi::SO_ERROR) {
+                    Ok(0) if !hangup => self.connected(stream),
+                    Ok(0) => self.failed(stream, io::Error::from(ErrorKind::ConnectionReset)),
+                    Ok(error) => self.failed(stream, io::Error::from_raw_os_error(error)),
+                    Err(error) => self.failed(stream, error),
+                }
+            },
+        }
+    }
+}
+
+fn connect_unix<NOTIFY>(event_loop: &mut Loop, address: io::Result<unix_net::SocketAddr>, mut connection_notify: NOTIFY)
+    -> Option<Stream<ConnectionMsg>>
+where NOTIFY: TcpConnectionNotify + 'static,
+{
+    let socket = address
+        .and_then(|address| unix_address(&address))
+        .and_then(|address| {
+            let fd = socket(ffi::AF_UNIX, ffi::SOCK_STREAM | ffi::SOCK_NONBLOCK, 0)?;
+            Ok((address, unsafe { UnixStream::from_raw_fd(fd) }))
+        });
+    let ((address, address_len), stream) =
+        match socket {
+            Ok(socket) => socket,
+            Err(error) => {
+                connection_notify.error(error);
+                connection_notify.connect_failed();
+                return None;
+            },
+        };
+    let mut connection = UnixConnection::new(stream).into();
+    connection_notify.connecting(&mut connection, 0);
+    let connection_stream = event_loop.spawn(tcp::Connection::new());
+    let connector = UnixConnector {
+        address,
+        address_len,
+        connection,
+        connection_notify: Some(connection_notify),
+        connection_stream: connection_stream.clone(),
+        event_loop: event_loop.clone(),
+    };
+    event_loop.spawn(connector);
+    Some(connection_stream)
+}
+
+// Convert the address of a path or abstract Unix domain socket to its C representation.
+fn unix_address(address: &unix_net::SocketAddr) -> io::Result<(ffi::sockaddr_un, ffi::socklen_t)> {
+    let mut sockaddr = ffi::sockaddr_un {
+        sun_family: ffi::AF_UNIX as u16,
+        sun_path: [0; 108],
+    };
+    // NOTE: the path is NUL-terminated, while the name of an abstract socket starts with a NUL.
+    let (path, offset) =
+        if let Some(path) = address.as_pathname() {
+            (path.as_os_str().as_bytes(), 0)
+        }
+        else if let Some(name) = address.as_abstract_name() {
+            (name, 1)
+        }
+        else {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "unnamed unix socket address"));
+        };
+    if offset + path.len() >= sockaddr.sun_path.len() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "unix socket path too long"));
+    }
+    sockaddr.sun_path[offset..offset + path.len()].copy_from_slice(path);
+    let path_len = if offset == 0 { path.len() + 1 } else { offset + path.len() };
+    let len = mem::size_of::<u16>() + path_len;
+    Ok((sockaddr, len as ffi::socklen_t))
+}
+
+struct ConnectionComponent {
+    connection: TcpConnection,
+    connection_notify: Box<dyn TcpConnectionNotify>,
+    connection_stream: Option<Stream<ConnectionMsg>>,
+    event_loop: Loop,
+    idle_timer: Option<Timer>,
+    // Whether throttled() was called without a matching unthrottled().
+    throttled: bool,
+}
+
+impl ConnectionComponent {
+    fn new(connection: TcpConnection, connection_notify: Box<dyn TcpConnectionNotify>,
+        connection_stream: Option<&Stream<ConnectionMsg>>, event_loop: &Loop) -> Self
+    {
+        Self {
+            connection,
+            connection_notify,
+            connection_stream: connection_stream.cloned(),
+            event_loop: event_loop.clone(),
+            idle_timer: None,
+            throttled: false,
+        }
+    }
+
+    fn check_idle(&mut self, stream: &Stream<ConnectionComponentMsg>) {
+        if let Some(timer) = self.idle_timer.take() {
+            let _ = self.event_loop.cancel_timer(&timer);
+        }
+        if self.connection.as_raw_fd().is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        let (last_read, last_write) = {
+            let connection = self.connection.connection.borrow();
+            (connection.last_read, connection.last_write)
+        };
+        let mut next_check: Option<Duration> = None;
+        let timeouts = [
+            (Idle::Read, self.connection.read_idle_timeout(), last_read),
+            (Idle::Write, self.connection.write_idle_timeout(), last_write),
+        ];
+        for &(idle, timeout, last_activity) in &timeouts {
+            if let Some(timeout) = timeout {
+                let elapsed = now.duration_since(last_activity);
+                let remaining =
+                    if elapsed >= timeout {
+                        self.connection_notify.idle(&mut self.connection, idle);
+                        timeout
+                    }
+                    else {
+                        timeout - elapsed
+                    };
+                next_check = Some(next_check.map_or(remaining, |next_check| next_check.min(remaining)));
+            }
+        }
+
+        // NOTE: the callback could have closed the connection.
+        if self.connection.as_raw_fd().is_none() {
+            return;
+        }
+        if let Some(next_check) = next_check {
+            match self.event_loop.set_timeout(next_check, stream, ConnectionComponentMsg::IdleCheck) {
+                Ok(timer) => self.idle_timer = Some(timer),
+                Err(error) => self.connection_notify.error(error),
+            }
+        }
+    }
+
+    fn check_throttled(&mut self) {
+        let throttled = self.connection.throttled();
+        if throttled != self.throttled && self.connection.as_raw_fd().is_some() {
+            self.throttled = throttled;
+            if throttled {
+                self.connection_notify.throttled(&mut self.connection);
+            }
+            else {
+                self.connection_notify.unthrottled(&mut self.connection);
+            }
+        }
+    }
+}
+
+impl Handler for ConnectionComponent {
+    type Msg = ConnectionComponentMsg;
+
+    fn stopped(&mut self) {
+        // NOTE: the stream was stopped by its owner while the connection was still open.
+        if self.connection.as_raw_fd().is_some() {
+            self.connection_notify.closed(&mut self.connection);
+            self.connection.close();
+        }
+        if let Some(ref connection_stream) = self.connection_stream {
+            connection_stream.stop();
+        }
+    }
+
+    fn update(&mut self, stream: &Stream<Self::Msg>, msg: Self::Msg) {
+        match msg {
+            ConnectionComponentMsg::IdleCheck => self.check_idle(stream),
+            ConnectionComponentMsg::ReadWrite(event) => {
+                if (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0 {
+                    // TODO: do we want to signal these errors to the trait?
+                    // TODO: are we sure we want to remove the fd from epoll when there's an error?
+                    if let Some(fd) = self.connection.as_raw_fd() {
+                        if let Err(error) = self.event_loop.remove_raw_fd(fd) {
+                            // TODO: not sure if it makes sense to report this error to the user.
+                            self.connection_notify.error(error);
+                        }
+                        self.connection_notify.closed(&mut self.connection); // FIXME: should it only be called for HangupError?
+                        self.connection.close();
+                        stream.stop();
+                        return;
+                    }
+                }
+                if event.events & Mode::Read as u32 != 0 && !self.connection.muted() {
+                    let mut buffer = vec![0; 4096];
+                    match self.connection.read(&mut buffer) {
+                        Err(ref error) if error.kind() == ErrorKind::WouldBlock ||
+                            error.kind() == ErrorKind::Interrupted => (),
+                        Ok(bytes_read) => {
+                            if bytes_read > 0 {
+                                buffer.truncate(bytes_read);
+                                self.connection_notify.received(&mut self.connection, buffer);
+                            }
+                            else {
+                                if let Some(fd) = self.connection.as_raw_fd() {
+                                    let _ = self.event_loop.remove_raw_fd(fd);
+                                }
+                                self.connection_notify.closed(&mut self.connection);
+                                self.connection.close();
+                                stream.stop();
+                                return;
+                            }
+                        },
+                        Err(_) => {
+                            if let Some(fd) = self.connection.as_raw_fd() {
+                                let _ = self.event_loop.remove_raw_fd(fd);
+                            }
+                            stream.stop();
+                            return;
+                        },
+                    }
+                }
+                if event.events & Mode::Write as u32 != 0 {
+                    self.connection.send(&mut self.event_loop, &mut *self.connection_notify);
+                }
+                if self.connection.disposed() && self.connection.pending_bytes() == 0 {
+                    self.connection_notify.closed(&mut self.connection);
+                    self.connection.close();
+                    stream.stop();
+                }
+            },
+            ConnectionComponentMsg::Send => {
+                self.connection_notify.sent();
+            },
+            // NOTE: the throttled state is checked after every message.
+            ConnectionComponentMsg::ThrottleCheck => (),
+            ConnectionComponentMsg::Write(data) =>
+                if let Err(error) = self.connection.write(data) {
+                    self.connection_notify.error(error);
+                    if let Some(fd) = self.connection.as_raw_fd() {
+                        let _ = self.event_loop.remove_raw_fd(fd);
+                    }
+                    stream.stop();
+                },
+        }
+        self.check_throttled();
     }
 }
 
@@ -648,6 +1513,11 @@ pub trait TcpConnectionNotify {
     fn connect_failed(&mut self) { // TODO: Pony accepts a TcpConnection here. Not sure how we could get one, though.
     }
 
+    /// Called when the connection attempt `count` did not succeed before the connect timeout.
+    /// The next address of the host will be tried afterwards.
+    fn connect_timeout(&mut self, _connection: &mut TcpConnection, _count: u32) {
+    }
+
     fn auth_failed(&mut self, _connection: &mut TcpConnection) {
     }
 
@@ -670,10 +1540,18 @@ pub trait TcpConnectionNotify {
         // TODO: since EPOLLEXCLUSIVE cannot be used with EPOLLRDHUP, not sure how useful this is.
     }
 
+    /// Called when no data was received (or sent) for longer than the read (or write) idle
+    /// timeout. It is called again every timeout period as long as the connection stays idle.
+    fn idle(&mut self, _connection: &mut TcpConnection, _idle: Idle) {
+    }
+
+    /// Called when the bytes waiting to be sent go above the high watermark set by
+    /// `TcpConnection::set_watermarks`.
     fn throttled(&mut self, _connection: &mut TcpConnection) {
-        // TODO: call when there is TCP backpressure.
     }
 
+    /// Called when the bytes waiting to be sent go back to the low watermark after the connection
+    /// was throttled.
     fn unthrottled(&mut self, _connection: &mut TcpConnection) {
     }
 }
@@ -694,15 +1572,27 @@ fn manage_connection(event_loop: &mut Loop, mut connection: TcpConnection, mut c
         };
     match event_loop.try_add_raw_fd(fd, Mode::ReadWrite) {
         Ok(event) => {
-            let component = ConnectionComponent::new(connection.clone(), connection_notify, event_loop);
+            let component = ConnectionComponent::new(connection.clone(), connection_notify, connection_stream, event_loop);
             let stream = event_loop.spawn(component);
             event.set_callback(&stream, ConnectionComponentMsg::ReadWrite);
             connection.set_handle(&stream);
+            if connection.read_idle_timeout().is_some() || connection.write_idle_timeout().is_some() {
+                stream.send(ConnectionComponentMsg::IdleCheck);
+            }
+            // NOTE: the connection could have been throttled by writes done before it had a handle.
+            if connection.throttled() {
+                stream.send(ConnectionComponentMsg::ThrottleCheck);
+            }
             if let Some(ref connection_stream) = connection_stream {
                 connection_stream.send(ConnectionMsg::Connected(stream));
             }
         },
-        Err(error) => connection_notify.error(error),
+        Err(error) => {
+            connection_notify.error(error);
+            if let Some(connection_stream) = connection_stream {
+                connection_stream.stop();
+            }
+        },
     }
 }
 
@@ -721,13 +1611,42 @@ impl<L> TcpListener<L> {
         }
     }
 
+    /// Listen on the first address of `host`, which can be an IPv4 or IPv6 address like
+    /// `[::1]:8080`.
+    pub fn bind(event_loop: &mut Loop, host: &str, listen_notify: L)
+        -> io::Result<(Stream<ListenerMsg>, net::SocketAddr)>
+    where L: TcpListenNotify + 'static,
+    {
+        Self::listen(event_loop, host, |_| true, listen_notify)
+    }
+
     // FIXME: host should probably be impl ToSocketAddr.
-    pub fn ip4(event_loop: &mut Loop, host: &str, mut listen_notify: L)
+    /// Listen on the first IPv4 address of `host`.
+    pub fn ip4(event_loop: &mut Loop, host: &str, listen_notify: L)
         -> io::Result<(Stream<ListenerMsg>, net::SocketAddr)>
     where L: TcpListenNotify + 'static,
     {
+        Self::listen(event_loop, host, SocketAddr::is_ipv4, listen_notify)
+    }
+
+    /// Listen on the first IPv6 address of `host`.
+    ///
+    /// NOTE: listening on `[::]` also accepts the IPv4 connections, unless the system disables it.
+    pub fn ip6(event_loop: &mut Loop, host: &str, listen_notify: L)
+        -> io::Result<(Stream<ListenerMsg>, net::SocketAddr)>
+    where L: TcpListenNotify + 'static,
+    {
+        Self::listen(event_loop, host, SocketAddr::is_ipv6, listen_notify)
+    }
+
+    fn listen(event_loop: &mut Loop, host: &str, filter: fn(&SocketAddr) -> bool, mut listen_notify: L)
+        -> io::Result<(Stream<ListenerMsg>, net::SocketAddr)>
+    where L: TcpListenNotify + 'static,
+    {
+        let addresses = host.to_socket_addrs()
+            .map(|addresses| addresses.filter(filter).collect::<Vec<_>>());
         let tcp_listener =
-            match net::TcpListener::bind(host) {
+            match addresses.and_then(|addresses| net::TcpListener::bind(&addresses[..])) {
                 Ok(tcp_listener) => {
                     listen_notify.listening(&tcp_listener);
                     tcp_listener
@@ -745,6 +1664,23 @@ impl<L> TcpListener<L> {
         event_loop.add_raw_fd(fd, Mode::Read, &stream, ReadEvent)?;
         Ok((stream, addr))
     }
+
+    /// Accept connections from a listener shared with the event loops of other threads.
+    ///
+    /// The socket is registered with `EPOLLEXCLUSIVE`, so that only one event loop is woken up
+    /// when a new connection arrives.
+    pub fn shared(event_loop: &mut Loop, tcp_listener: &net::TcpListener, listen_notify: L)
+        -> io::Result<Stream<ListenerMsg>>
+    where L: TcpListenNotify + 'static,
+    {
+        let tcp_listener = tcp_listener.try_clone()?;
+        tcp_listener.set_nonblocking(true)?;
+        let fd = tcp_listener.as_raw_fd();
+        let listener = TcpListener::new(tcp_listener, listen_notify, event_loop);
+        let stream = event_loop.spawn(listener);
+        event_loop.add_raw_fd(fd, Mode::Read, &stream, ReadEvent)?;
+        Ok(stream)
+    }
 }
 
 impl<L> Handler for TcpListener<L>
@@ -752,11 +1688,15 @@ where L: TcpListenNotify,
 {
     type Msg = ListenerMsg;
 
-    fn update(&mut self, _stream: &Stream<Self::Msg>, msg: Self::Msg) {
+    fn stopped(&mut self) {
+        if let Some(tcp_listener) = self.tcp_listener.take() {
+            self.listen_notify.closed(&tcp_listener);
+        }
+    }
+
+    fn update(&mut self, stream: &Stream<Self::Msg>, msg: Self::Msg) {
         match msg {
-            Dispose => {
-                self.tcp_listener.take();
-            },
+            Dispose => stream.stop(),
             ReadEvent(event) => {
                 if let Some(ref tcp_listener) = self.tcp_listener {
                     if (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0 {
@@ -766,8 +1706,9 @@ where L: TcpListenNotify,
                             // TODO: not sure if it makes sense to report this error to the user.
                             self.listen_notify.error(error);
                         }
-                        self.listen_notify.closed(&tcp_listener); // FIXME: should it only be called for HangupError?
-                        // TODO: remove this handler.
+                        // NOTE: closed() is called when the handler is stopped.
+                        // FIXME: should it only be called for HangupError?
+                        stream.stop();
                     }
                     else if event.events & Mode::Read as u32 != 0 {
                         // TODO: accept many times?
@@ -787,13 +1728,437 @@ where L: TcpListenNotify,
                             Err(error) => self.listen_notify.error(error),
                         }
                     }
-                    // TODO: call listen_notify.closed().
                 }
             },
         }
     }
 }
 
+pub trait UnixListenNotify {
+    fn listening(&mut self, _listener: &unix_net::UnixListener) {
+    }
+
+    fn not_listening(&mut self) {
+    }
+
+    fn closed(&mut self, _listener: &unix_net::UnixListener) {
+    }
+
+    fn connected(&mut self, listener: &unix_net::UnixListener) -> Box<dyn TcpConnectionNotify>;
+
+    fn error(&mut self, _error: io::Error) {
+    }
+}
+
+// Remove the socket file at path if no process is listening on it anymore.
+//
+// NOTE: the probe connects a datagram socket, which does not queue a connection on a live stream
+// listener like a stream socket would: the kernel answers EPROTOTYPE instead, while a file without
+// a socket bound to it gives ECONNREFUSED.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            let in_use = || io::Error::new(ErrorKind::AddrInUse, "socket already in use");
+            match UnixDatagram::unbound()?.connect(path) {
+                Err(ref error) if error.kind() == ErrorKind::ConnectionRefused => fs::remove_file(path),
+                Err(ref error) if error.raw_os_error() == Some(ffi::ErrNo::ProtocolType as i32) => Err(in_use()),
+                Err(error) => Err(error),
+                // NOTE: a datagram socket is bound to the path.
+                Ok(()) => Err(in_use()),
+            }
+        },
+        // NOTE: bind() reports the error if the file is not a socket.
+        _ => Ok(()),
+    }
+}
+
+pub struct UnixListener<L> {
+    event_loop: Loop,
+    listen_notify: L,
+    // The socket file, removed when the listener is closed.
+    path: Option<PathBuf>,
+    unix_listener: Option<unix_net::UnixListener>,
+}
+
+impl<L> UnixListener<L> {
+    pub fn new(unix_listener: unix_net::UnixListener, listen_notify: L, event_loop: &Loop) -> Self {
+        Self {
+            event_loop: event_loop.clone(),
+            listen_notify,
+            path: None,
+            unix_listener: Some(unix_listener),
+        }
+    }
+
+    /// Listen on the socket file at `path`.
+    ///
+    /// A stale socket file left by a process that is not listening anymore is removed first. The
+    /// socket file is removed when the listener is closed.
+    pub fn bind<P>(event_loop: &mut Loop, path: P, listen_notify: L) -> io::Result<Stream<ListenerMsg>>
+    where L: UnixListenNotify + 'static,
+          P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let unix_listener = remove_stale_socket(path)
+            .and_then(|()| unix_net::UnixListener::bind(path));
+        Self::listen(event_loop, unix_listener, Some(path.to_path_buf()), listen_notify)
+    }
+
+    /// Listen on the abstract socket `name`, which does not exist in the filesystem.
+    pub fn bind_abstract(event_loop: &mut Loop, name: &[u8], listen_notify: L) -> io::Result<Stream<ListenerMsg>>
+    where L: UnixListenNotify + 'static,
+    {
+        let unix_listener = unix_net::SocketAddr::from_abstract_name(name)
+            .and_then(|address| unix_net::UnixListener::bind_addr(&address));
+        Self::listen(event_loop, unix_listener, None, listen_notify)
+    }
+
+    fn listen(event_loop: &mut Loop, unix_listener: io::Result<unix_net::UnixListener>, path: Option<PathBuf>,
+        mut listen_notify: L) -> io::Result<Stream<ListenerMsg>>
+    where L: UnixListenNotify + 'static,
+    {
+        let unix_listener =
+            match unix_listener {
+                Ok(unix_listener) => {
+                    listen_notify.listening(&unix_listener);
+                    unix_listener
+                },
+                Err(error) => {
+                    listen_notify.not_listening();
+                    return Err(error);
+                },
+            };
+        unix_listener.set_nonblocking(true)?;
+        let fd = unix_listener.as_raw_fd();
+        let mut listener = UnixListener::new(unix_listener, listen_notify, event_loop);
+        listener.path = path;
+        let stream = event_loop.spawn(listener);
+        event_loop.add_raw_fd(fd, Mode::Read, &stream, ReadEvent)?;
+        Ok(stream)
+    }
+}
+
+impl<L> Handler for UnixListener<L>
+where L: UnixListenNotify,
+{
+    type Msg = ListenerMsg;
+
+    fn stopped(&mut self) {
+        if let Some(unix_listener) = self.unix_listener.take() {
+            self.listen_notify.closed(&unix_listener);
+        }
+        if let Some(path) = self.path.take() {
+            if let Err(error) = fs::remove_file(path) {
+                self.listen_notify.error(error);
+            }
+        }
+    }
+
+    fn update(&mut self, stream: &Stream<Self::Msg>, msg: Self::Msg) {
+        match msg {
+            Dispose => stream.stop(),
+            ReadEvent(event) => {
+                if let Some(ref unix_listener) = self.unix_listener {
+                    if (event.events & (StatusMode::HangupError as u32 | StatusMode::Error as u32)) != 0 {
+                        if let Err(error) = self.event_loop.remove_raw_fd(unix_listener.as_raw_fd()) {
+                            self.listen_notify.error(error);
+                        }
+                        // NOTE: closed() is called when the handler is stopped.
+                        stream.stop();
+                    }
+                    else if event.events & Mode::Read as u32 != 0 {
+                        match unix_listener.accept() {
+                            Ok((stream, _addr)) => {
+                                match stream.set_nonblocking(true) {
+                                    Ok(()) => {
+                                        let mut connection_notify = self.listen_notify.connected(unix_listener);
+                                        let mut connection = UnixConnection::new(stream).into();
+                                        connection_notify.accepted(&mut connection);
+                                        manage_connection(&mut self.event_loop, connection, connection_notify, None);
+                                    },
+                                    Err(error) => self.listen_notify.error(error),
+                                }
+                            },
+                            Err(ref error) if error.kind() == ErrorKind::WouldBlock => (),
+                            Err(error) => self.listen_notify.error(error),
+                        }
+                    }
+                }
+            },
+        }
+    }
+}
+
+pub enum UdpMsg {
+    Dispose,
+    Queued,
+    ReadEvent(epoll_event),
+    WriteEvent(epoll_event),
+}
+
+pub trait UdpNotify {
+    fn listening(&mut self, _socket: &mut UdpSocket) {
+    }
+
+    fn not_listening(&mut self) {
+    }
+
+    fn closed(&mut self, _socket: &mut UdpSocket) {
+    }
+
+    fn error(&mut self, _error: io::Error) {
+    }
+
+    fn received(&mut self, socket: &mut UdpSocket, data: Vec<u8>, peer_addr: SocketAddr);
+}
+
+struct _UdpSocket {
+    // TODO: should the VecDeque be bounded?
+    datagrams: VecDeque<(Vec<u8>, SocketAddr)>,
+    handle: Option<Stream<UdpMsg>>,
+    socket: Option<net::UdpSocket>,
+    // NOTE: a duplicate of the socket is registered for write events only while datagrams are
+    // queued, because a UDP socket is almost always writable.
+    write_socket: Option<net::UdpSocket>,
+}
+
+#[derive(Clone)]
+pub struct UdpSocket {
+    socket: Rc<RefCell<_UdpSocket>>,
+}
+
+impl UdpSocket {
+    /// Bind a UDP socket to `addr` and call `udp_notify` for every datagram received.
+    pub fn bind<NOTIFY>(event_loop: &mut Loop, addr: &str, mut udp_notify: NOTIFY) -> io::Result<Self>
+    where NOTIFY: UdpNotify + 'static,
+    {
+        let udp_socket =
+            match net::UdpSocket::bind(addr) {
+                Ok(udp_socket) => udp_socket,
+                Err(error) => {
+                    udp_notify.not_listening();
+                    return Err(error);
+                },
+            };
+        udp_socket.set_nonblocking(true)?;
+        let fd = udp_socket.as_raw_fd();
+        let mut socket = UdpSocket {
+            socket: Rc::new(RefCell::new(_UdpSocket {
+                datagrams: VecDeque::new(),
+                handle: None,
+                socket: Some(udp_socket),
+                write_socket: None,
+            })),
+        };
+        udp_notify.listening(&mut socket);
+        let component = UdpComponent {
+            buffer: vec![0; 65536],
+            event_loop: event_loop.clone(),
+            socket: socket.clone(),
+            udp_notify,
+        };
+        let stream = event_loop.spawn(component);
+        socket.socket.borrow_mut().handle = Some(stream.clone());
+        if let Err(error) = event_loop.add_raw_fd(fd, Mode::Read, &stream, UdpMsg::ReadEvent) {
+            stream.stop();
+            return Err(error);
+        }
+        Ok(socket)
+    }
+
+    pub fn as_raw_fd(&self) -> Option<RawFd> {
+        self.socket.borrow().socket.as_ref().map(|socket| socket.as_raw_fd())
+    }
+
+    fn close(&self) {
+        let mut socket = self.socket.borrow_mut();
+        socket.datagrams.clear();
+        socket.socket.take();
+        socket.write_socket.take();
+    }
+
+    /// Close the socket. `UdpNotify::closed` is called afterwards.
+    pub fn dispose(&self) {
+        if let Some(ref handle) = self.socket.borrow().handle {
+            handle.send(UdpMsg::Dispose);
+        }
+    }
+
+    pub fn join_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> io::Result<()> {
+        self.with_socket(|socket| socket.join_multicast_v4(multiaddr, interface))
+    }
+
+    pub fn join_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> io::Result<()> {
+        self.with_socket(|socket| socket.join_multicast_v6(multiaddr, interface))
+    }
+
+    pub fn leave_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> io::Result<()> {
+        self.with_socket(|socket| socket.leave_multicast_v4(multiaddr, interface))
+    }
+
+    pub fn leave_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> io::Result<()> {
+        self.with_socket(|socket| socket.leave_multicast_v6(multiaddr, interface))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.with_socket(|socket| socket.local_addr())
+    }
+
+    /// Number of datagrams waiting for the socket to be writable.
+    pub fn queued(&self) -> usize {
+        self.socket.borrow().datagrams.len()
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.with_socket(|socket| socket.recv_from(buffer))
+    }
+
+    /// Send `data` to `addr` without blocking.
+    ///
+    /// If the socket is not writable, the datagram is queued and sent when it becomes writable.
+    pub fn send_to(&self, data: Vec<u8>, addr: SocketAddr) -> io::Result<()> {
+        let mut socket = self.socket.borrow_mut();
+        // NOTE: queue behind the pending datagrams to keep them in order.
+        if !socket.datagrams.is_empty() {
+            socket.datagrams.push_back((data, addr));
+            return Ok(());
+        }
+        let result =
+            match socket.socket {
+                Some(ref udp_socket) => udp_socket.send_to(&data, addr),
+                None => return Err(io::Error::new(ErrorKind::NotConnected, "socket closed")),
+            };
+        match result {
+            Ok(_) => Ok(()),
+            Err(ref error) if error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::Interrupted => {
+                socket.datagrams.push_back((data, addr));
+                if let Some(ref handle) = socket.handle {
+                    handle.send(UdpMsg::Queued);
+                }
+                Ok(())
+            },
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Send the queued datagrams until the socket would block.
+    fn send_queued(&self, udp_notify: &mut dyn UdpNotify) {
+        loop {
+            let mut socket = self.socket.borrow_mut();
+            let result =
+                match (socket.datagrams.front(), socket.socket.as_ref()) {
+                    (Some(&(ref data, addr)), Some(udp_socket)) => udp_socket.send_to(data, addr),
+                    _ => return,
+                };
+            match result {
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::Interrupted =>
+                    return,
+                Err(error) => {
+                    // NOTE: the datagram is dropped, as if it was lost on the network.
+                    socket.datagrams.pop_front();
+                    drop(socket);
+                    udp_notify.error(error);
+                },
+                Ok(_) => {
+                    socket.datagrams.pop_front();
+                },
+            }
+        }
+    }
+
+    pub fn set_multicast_loop_v4(&self, multicast_loop: bool) -> io::Result<()> {
+        self.with_socket(|socket| socket.set_multicast_loop_v4(multicast_loop))
+    }
+
+    pub fn set_multicast_ttl_v4(&self, ttl: u32) -> io::Result<()> {
+        self.with_socket(|socket| socket.set_multicast_ttl_v4(ttl))
+    }
+
+    fn with_socket<F, T>(&self, callback: F) -> io::Result<T>
+    where F: FnOnce(&net::UdpSocket) -> io::Result<T>,
+    {
+        match self.socket.borrow().socket {
+            Some(ref socket) => callback(socket),
+            None => Err(io::Error::new(ErrorKind::NotConnected, "socket closed")),
+        }
+    }
+}
+
+struct UdpComponent<NOTIFY> {
+    buffer: Vec<u8>,
+    event_loop: Loop,
+    socket: UdpSocket,
+    udp_notify: NOTIFY,
+}
+
+impl<NOTIFY> UdpComponent<NOTIFY>
+where NOTIFY: UdpNotify,
+{
+    fn wait_writable(&mut self, stream: &Stream<UdpMsg>) {
+        if self.socket.queued() == 0 || self.socket.socket.borrow().write_socket.is_some() {
+            return;
+        }
+        let result = self.socket.with_socket(|socket| socket.try_clone())
+            .and_then(|write_socket| {
+                let event = self.event_loop.try_add_raw_fd_oneshot(write_socket.as_raw_fd(), Mode::Write)?;
+                event.set_callback(stream, UdpMsg::WriteEvent);
+                Ok(write_socket)
+            });
+        match result {
+            Ok(write_socket) => self.socket.socket.borrow_mut().write_socket = Some(write_socket),
+            Err(error) => self.udp_notify.error(error),
+        }
+    }
+}
+
+impl<NOTIFY> Handler for UdpComponent<NOTIFY>
+where NOTIFY: UdpNotify,
+{
+    type Msg = UdpMsg;
+
+    fn stopped(&mut self) {
+        if self.socket.as_raw_fd().is_some() {
+            self.socket.close();
+            self.udp_notify.closed(&mut self.socket);
+        }
+    }
+
+    fn update(&mut self, stream: &Stream<Self::Msg>, msg: Self::Msg) {
+        match msg {
+            UdpMsg::Dispose => stream.stop(),
+            UdpMsg::Queued => self.wait_writable(stream),
+            UdpMsg::ReadEvent(event) => {
+                if event.events & StatusMode::Error as u32 != 0 {
+                    // NOTE: errors like ICMP port unreachable do not close the socket.
+                    match self.socket.with_socket(|socket| socket.take_error()) {
+                        Ok(Some(error)) | Err(error) => self.udp_notify.error(error),
+                        Ok(None) => (),
+                    }
+                }
+                if event.events & Mode::Read as u32 != 0 {
+                    match self.socket.recv_from(&mut self.buffer) {
+                        Ok((size, peer_addr)) => {
+                            let data = self.buffer[..size].to_vec();
+                            self.udp_notify.received(&mut self.socket, data, peer_addr);
+                        },
+                        Err(ref error) if error.kind() == ErrorKind::WouldBlock ||
+                            error.kind() == ErrorKind::Interrupted => (),
+                        Err(error) => self.udp_notify.error(error),
+                    }
+                }
+            },
+            UdpMsg::WriteEvent(_event) => {
+                let write_socket = self.socket.socket.borrow_mut().write_socket.take();
+                if let Some(write_socket) = write_socket {
+                    let _ = self.event_loop.remove_fd(&write_socket);
+                }
+                self.socket.send_queued(&mut self.udp_notify);
+                self.wait_writable(stream);
+            },
+        }
+    }
+}
+
 pub mod ffi {
     #![allow(non_camel_case_types)]
 
@@ -801,6 +2166,8 @@ pub mod ffi {
 
     #[repr(i32)]
     pub enum ErrNo {
+        Again = 11,
+        ProtocolType = 91,
         InProgress = 115,
     }
 
@@ -814,6 +2181,10 @@ pub mod ffi {
     pub const SOL_SOCKET: i32 = 1;
     pub const SO_ERROR: i32 = 4;
 
+    pub const AF_UNIX: i32 = 1;
+    pub const AF_INET: i32 = 2;
+    pub const AF_INET6: i32 = 10;
+
     pub const SOCK_STREAM: i32 = 1;
     pub const SOCK_DGRAM: i32 = 2;
     pub const SOCK_NONBLOCK: i32 = 0o4000;
@@ -823,6 +2194,29 @@ pub mod ffi {
 
     pub type socklen_t = i32;
 
+    #[repr(C)]
+    pub struct sockaddr_in {
+        pub sin_family: u16,
+        pub sin_port: u16,
+        pub sin_addr: [u8; 4],
+        pub sin_zero: [u8; 8],
+    }
+
+    #[repr(C)]
+    pub struct sockaddr_in6 {
+        pub sin6_family: u16,
+        pub sin6_port: u16,
+        pub sin6_flowinfo: u32,
+        pub sin6_addr: [u8; 16],
+        pub sin6_scope_id: u32,
+    }
+
+    #[repr(C)]
+    pub struct sockaddr_un {
+        pub sun_family: u16,
+        pub sun_path: [u8; 108],
+    }
+
     #[repr(C)]
     pub struct addrinfo {
         pub ai_flags: i32,
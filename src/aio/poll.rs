@@ -8,11 +8,16 @@ use std::io::{
     Error,
     ErrorKind,
 };
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::mem;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::ptr;
 use std::rc::Rc;
+use std::time::Duration;
 use std::u64;
 
+use crate::aio::signal::{self, Signal};
 use crate::aio::slab::Slab;
 
 const MAX_EVENTS: usize = 100; // TODO: tweak this value.
@@ -38,6 +43,8 @@ enum Callback {
     Empty,
     Normal(Box<dyn FnMut(ffi::epoll_event) -> Action>),
     Oneshot(Box<dyn FnBox>),
+    // The callback was taken out of the slab because it is being called.
+    Running,
 }
 
 #[derive(PartialEq)]
@@ -86,6 +93,47 @@ impl EventOnce {
     }
 }
 
+/// Handle to a timer registered with `EventLoop::add_timeout` or `EventLoop::add_interval`.
+///
+/// The timer can be cancelled with `EventLoop::cancel_timer`. A one-shot timer is automatically
+/// cleaned up after it fired.
+#[derive(Clone)]
+pub struct Timer {
+    fd: Rc<Cell<Option<RawFd>>>,
+}
+
+impl Timer {
+    /// Check whether the timer can still fire, i.e. it was not cancelled and it is not a
+    /// one-shot timer that already fired.
+    pub fn is_active(&self) -> bool {
+        self.fd.get().is_some()
+    }
+}
+
+/// Handle to a signal watched with `EventLoop::add_signal`.
+///
+/// The signal can be unwatched with `EventLoop::remove_signal`.
+#[derive(Clone)]
+pub struct SignalWatch {
+    fd: Rc<Cell<Option<RawFd>>>,
+    signal: Signal,
+}
+
+impl SignalWatch {
+    /// Check whether the signal is still watched.
+    pub fn is_active(&self) -> bool {
+        self.fd.get().is_some()
+    }
+}
+
+// A signal blocked by the watches of the event loop.
+struct BlockedSignal {
+    // Whether the signal was not blocked before the first watch, i.e. it should be unblocked when
+    // the last watch is removed.
+    unblock: bool,
+    watches: usize,
+}
+
 pub enum EpollResult {
     Error(io::Error),
     Interrupted,
@@ -96,10 +144,35 @@ thread_local! {
     static EVENT_FD: RawFd = unsafe { ffi::eventfd(0, ffi::EFD_NONBLOCK) };
 }
 
+/// Handle that can wake up an event loop from any thread.
+#[derive(Clone, Copy)]
+pub struct Waker {
+    event_fd: RawFd,
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        unsafe {
+            ffi::eventfd_write(self.event_fd, 1);
+        }
+    }
+}
+
+struct Registration {
+    callback_entry: usize,
+    id: u64,
+}
+
 #[derive(Clone)]
 pub struct EventLoop {
     callbacks: Rc<RefCell<Slab<Callback>>>,
+    // The eventfd of the thread that created the event loop.
+    event_fd: RawFd,
     fd: RawFd,
+    // Registration of every fd.
+    fds: Rc<RefCell<HashMap<RawFd, Registration>>>,
+    next_registration_id: Rc<Cell<u64>>,
+    signals: Rc<RefCell<HashMap<Signal, BlockedSignal>>>,
     stopped: bool,
 }
 
@@ -109,13 +182,17 @@ impl EventLoop {
         if fd == -1 {
             return Err(Error::last_os_error());
         }
+        let event_fd = EVENT_FD.with(|&event_fd| event_fd);
         let event_loop = Self {
             callbacks: Rc::new(RefCell::new(Slab::new())),
+            event_fd,
             fd,
+            fds: Rc::new(RefCell::new(HashMap::new())),
+            next_registration_id: Rc::new(Cell::new(0)),
+            signals: Rc::new(RefCell::new(HashMap::new())),
             stopped: false,
         };
 
-        let event_fd = EVENT_FD.with(|&event_fd| event_fd);
         event_loop.add_raw_fd_without_callback(event_fd, Mode::Read)?;
 
         Ok(event_loop)
@@ -137,42 +214,71 @@ impl EventLoop {
     pub fn add_raw_fd<F>(&self, fd: RawFd, mode: Mode, callback: F) -> io::Result<()>
     where F: FnMut(ffi::epoll_event) -> Action + 'static,
     {
-        let callback_entry = self.callbacks.borrow_mut().insert(Callback::Normal(Box::new(callback)));
-        let mut event = ffi::epoll_event {
-            events: mode as u32,
-            data: ffi::epoll_data_t {
-                u64: callback_entry as u64,
-            },
-        };
-        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Add, fd, &mut event) } == -1 {
-            // TODO: should probably deallocate memory here.
-            return Err(Error::last_os_error());
-        }
+        self.register(fd, mode as u32, Callback::Normal(Box::new(callback)))?;
         Ok(())
     }
 
     pub fn add_raw_fd_oneshot<F>(&self, fd: RawFd, mode: Mode, callback: F) -> io::Result<()>
     where F: FnOnce(ffi::epoll_event) + 'static,
     {
-        let callback_entry = self.callbacks.borrow_mut().insert(Callback::Oneshot(Box::new(callback)));
+        self.register(fd, mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT, Callback::Oneshot(Box::new(callback)))?;
+        Ok(())
+    }
+
+    /// Get the unique identifier of the current registration of `fd`.
+    ///
+    /// Since fds and callback entries are reused, this is used to check that an fd was not
+    /// removed and registered again by someone else.
+    pub(crate) fn registration_id(&self, fd: RawFd) -> Option<u64> {
+        self.fds.borrow().get(&fd).map(|registration| registration.id)
+    }
+
+    fn register(&self, fd: RawFd, events: u32, callback: Callback) -> io::Result<usize> {
+        let callback_entry = self.callbacks.borrow_mut().insert(callback);
         let mut event = ffi::epoll_event {
-            events: mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT,
+            events,
             data: ffi::epoll_data_t {
                 u64: callback_entry as u64,
             },
         };
         if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Add, fd, &mut event) } == -1 {
-            // TODO: should probably deallocate memory here.
+            self.callbacks.borrow_mut().remove(callback_entry);
             return Err(Error::last_os_error());
         }
-        Ok(())
+        // NOTE: a closed fd is automatically removed from epoll, so the fd might already be in the
+        // map: the new callback replaces the old one.
+        let id = self.next_registration_id.get();
+        self.next_registration_id.set(id + 1);
+        let old_registration = self.fds.borrow_mut().insert(fd, Registration {
+            callback_entry,
+            id,
+        });
+        if let Some(old_registration) = old_registration {
+            self.remove_callback(old_registration.callback_entry);
+        }
+        Ok(callback_entry)
+    }
+
+    fn remove_callback(&self, callback_entry: usize) {
+        let mut callbacks = self.callbacks.borrow_mut();
+        // NOTE: a running callback is removed as well: iterate() will not put it back.
+        if callbacks.contains(callback_entry) {
+            callbacks.remove(callback_entry);
+        }
     }
 
     pub fn remove_fd<A: AsRawFd>(&self, as_fd: &A) -> io::Result<()> {
         self.remove_raw_fd(as_fd.as_raw_fd())
     }
 
+    /// Remove `fd` from the event loop and drop its callback.
     pub fn remove_raw_fd(&self, fd: RawFd) -> io::Result<()> {
+        // NOTE: the callback is dropped even if epoll_ctl() fails because the fd might have been
+        // closed, which removes it from epoll.
+        let registration = self.fds.borrow_mut().remove(&fd);
+        if let Some(registration) = registration {
+            self.remove_callback(registration.callback_entry);
+        }
         if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Delete, fd, ptr::null_mut()) } == -1 {
             return Err(Error::last_os_error());
         }
@@ -180,33 +286,182 @@ impl EventLoop {
     }
 
     pub fn try_add_raw_fd(&self, fd: RawFd, mode: Mode) -> io::Result<Event> {
-        let callback_entry = self.callbacks.borrow_mut().insert(Callback::Empty);
-        let mut event = ffi::epoll_event {
-            events: mode as u32,
-            data: ffi::epoll_data_t {
-                u64: callback_entry as u64,
-            },
-        };
-        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Add, fd, &mut event) } == -1 {
-            // TODO: should probably deallocate memory here.
-            return Err(Error::last_os_error());
-        }
+        let callback_entry = self.register(fd, mode as u32, Callback::Empty)?;
         Ok(Event::new(callback_entry, self))
     }
 
     pub fn try_add_raw_fd_oneshot(&self, fd: RawFd, mode: Mode) -> io::Result<EventOnce> {
-        let callback_entry = self.callbacks.borrow_mut().insert(Callback::Empty);
-        let mut event = ffi::epoll_event {
-            events: mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT,
-            data: ffi::epoll_data_t {
-                u64: callback_entry as u64,
-            },
+        let callback_entry = self.register(fd, mode as u32 & !ffi::EPOLLEXCLUSIVE | ffi::EPOLLONESHOT, Callback::Empty)?;
+        Ok(EventOnce::new(callback_entry, self.clone()))
+    }
+
+    /// Call `callback` every time `signal` is received.
+    ///
+    /// The signal is blocked for the current thread so that it is only received through the event
+    /// loop: this method should be called before spawning any thread, since threads inherit the
+    /// signal mask of their parent.
+    pub fn add_signal<F>(&self, signal: Signal, mut callback: F) -> io::Result<SignalWatch>
+    where F: FnMut(Signal) + 'static,
+    {
+        let (fd, blocked) = signal::signalfd(signal)?;
+        {
+            let mut signals = self.signals.borrow_mut();
+            let blocked_signal = signals.entry(signal).or_insert(BlockedSignal {
+                unblock: !blocked,
+                watches: 0,
+            });
+            blocked_signal.watches += 1;
+        }
+        let watch = SignalWatch {
+            fd: Rc::new(Cell::new(Some(fd))),
+            signal,
         };
-        if unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Add, fd, &mut event) } == -1 {
-            // TODO: should probably deallocate memory here.
+        let callback_fd = watch.fd.clone();
+        let result = self.add_raw_fd(fd, Mode::Read, move |_event| {
+            let mut info: signal::ffi::signalfd_siginfo = unsafe { mem::zeroed() };
+            let size = mem::size_of_val(&info);
+            // NOTE: many signals can be pending for a single event and the callback can remove
+            // the watch.
+            while let Some(fd) = callback_fd.get() {
+                if unsafe { ffi::read(fd, &mut info as *mut _ as *mut _, size) } != size as isize {
+                    break;
+                }
+                if let Some(signal) = Signal::from_raw(info.ssi_signo as i32) {
+                    callback(signal);
+                }
+            }
+            Action::Continue
+        });
+        if let Err(error) = result {
+            let _ = self.remove_signal(&watch);
+            return Err(error);
+        }
+        Ok(watch)
+    }
+
+    /// Stop watching a signal: its signalfd is closed and, when this is the last watch of the
+    /// signal, the signal is unblocked if it was not blocked before the first watch.
+    /// Removing a watch that was already removed does nothing.
+    pub fn remove_signal(&self, watch: &SignalWatch) -> io::Result<()> {
+        if let Some(fd) = watch.fd.take() {
+            let result = self.remove_raw_fd(fd);
+            unsafe { ffi::close(fd) };
+            let unblock = {
+                let mut signals = self.signals.borrow_mut();
+                let last_watch =
+                    match signals.get_mut(&watch.signal) {
+                        Some(blocked_signal) => {
+                            blocked_signal.watches -= 1;
+                            blocked_signal.watches == 0
+                        },
+                        None => false,
+                    };
+                last_watch && signals.remove(&watch.signal).map(|blocked_signal| blocked_signal.unblock).unwrap_or(false)
+            };
+            if unblock {
+                signal::unblock(watch.signal)?;
+            }
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Call `callback` once after `duration`.
+    pub fn add_timeout<F>(&self, duration: Duration, callback: F) -> io::Result<Timer>
+    where F: FnOnce() + 'static,
+    {
+        let mut callback = Some(callback);
+        self.add_timer(duration, false, move || {
+            if let Some(callback) = callback.take() {
+                callback();
+            }
+        })
+    }
+
+    /// Call `callback` every `duration`.
+    ///
+    /// If the event loop was too busy to handle some expirations, the callback is only called
+    /// once for all of them.
+    pub fn add_interval<F>(&self, duration: Duration, callback: F) -> io::Result<Timer>
+    where F: FnMut() + 'static,
+    {
+        self.add_timer(duration, true, callback)
+    }
+
+    fn add_timer<F>(&self, duration: Duration, periodic: bool, mut callback: F) -> io::Result<Timer>
+    where F: FnMut() + 'static,
+    {
+        let fd = unsafe { ffi::timerfd_create(ffi::CLOCK_MONOTONIC, ffi::TFD_NONBLOCK | ffi::TFD_CLOEXEC) };
+        if fd == -1 {
             return Err(Error::last_os_error());
         }
-        Ok(EventOnce::new(callback_entry, self.clone()))
+        // NOTE: a zero it_value disarms the timer, so use the smallest possible value instead.
+        let value = to_timespec(duration.max(Duration::from_nanos(1)));
+        let interval =
+            if periodic {
+                value
+            }
+            else {
+                to_timespec(Duration::from_secs(0))
+            };
+        let spec = ffi::itimerspec {
+            it_interval: interval,
+            it_value: value,
+        };
+        if unsafe { ffi::timerfd_settime(fd, 0, &spec, ptr::null_mut()) } == -1 {
+            let error = Error::last_os_error();
+            unsafe { ffi::close(fd) };
+            return Err(error);
+        }
+
+        let timer_fd = Rc::new(Cell::new(Some(fd)));
+        let callback_timer_fd = timer_fd.clone();
+        let event_loop = self.clone();
+        let callback = Callback::Normal(Box::new(move |_event| {
+            let fd =
+                match callback_timer_fd.get() {
+                    Some(fd) => fd,
+                    None => return Action::Stop,
+                };
+            let mut expirations = 0u64;
+            let size = mem::size_of_val(&expirations);
+            let read = unsafe { ffi::read(fd, &mut expirations as *mut u64 as *mut _, size) };
+            if read != size as isize {
+                // Spurious wakeup: the timer did not expire.
+                return Action::Continue;
+            }
+            if !periodic {
+                callback_timer_fd.set(None);
+                let _ = event_loop.remove_raw_fd(fd);
+                unsafe { ffi::close(fd) };
+            }
+            callback();
+            if periodic && callback_timer_fd.get().is_some() {
+                Action::Continue
+            }
+            else {
+                Action::Stop
+            }
+        }));
+
+        if let Err(error) = self.register(fd, Mode::Read as u32, callback) {
+            unsafe { ffi::close(fd) };
+            return Err(error);
+        }
+
+        Ok(Timer {
+            fd: timer_fd,
+        })
+    }
+
+    /// Cancel a timer. Cancelling a timer that already fired or was already cancelled does nothing.
+    pub fn cancel_timer(&self, timer: &Timer) -> io::Result<()> {
+        if let Some(fd) = timer.fd.take() {
+            let result = self.remove_raw_fd(fd);
+            unsafe { ffi::close(fd) };
+            result?;
+        }
+        Ok(())
     }
 
     pub fn iterate(&self, event_list: &mut [ffi::epoll_event]) -> EpollResult {
@@ -235,11 +490,16 @@ impl EventLoop {
                 }
             }
             let entry = unsafe { event.data.u64 as usize };
+            if !self.callbacks.borrow().contains(entry) {
+                // The callback was removed (e.g. a cancelled timer) by a previous callback in this iteration.
+                continue;
+            }
             // NOTE: Remove the callback because callbacks can be added in the update() method.
-            let callback = std::mem::replace(&mut self.callbacks.borrow_mut()[entry], Callback::Empty);
+            let callback = std::mem::replace(&mut self.callbacks.borrow_mut()[entry], Callback::Running);
             let callback =
                 match callback {
                     Callback::Empty => panic!("callback should not be empty"),
+                    Callback::Running => panic!("callback should not be running"),
                     Callback::Normal(mut callback) => {
                         if callback(event) == Action::Stop {
                             None
@@ -251,23 +511,47 @@ impl EventLoop {
                     Callback::Oneshot(callback) => {
                         let callback: Box<_> = callback;
                         callback.call_box(event);
-                        None
+                        Some(Callback::Empty)
                     },
                 };
-            if let Some(callback) = callback {
-                self.callbacks.borrow_mut()[entry] = callback;
+            let mut callbacks = self.callbacks.borrow_mut();
+            // NOTE: the callback could have removed itself (e.g. by cancelling its timer), in which
+            // case the entry is either vacant or reused by another callback.
+            if callbacks.get(entry).map(|callback| matches!(callback, Callback::Running)).unwrap_or(false) {
+                match callback {
+                    Some(callback) => callbacks[entry] = callback,
+                    None => {
+                        callbacks.remove(entry);
+                        drop(callbacks);
+                        self.remove_stopped_fd(entry);
+                    },
+                }
             }
         }
 
         EpollResult::Ok
     }
 
+    // Remove the fd whose callback stopped, so that it can be added again.
+    fn remove_stopped_fd(&self, callback_entry: usize) {
+        let fd = self.fds.borrow().iter()
+            .find(|(_, registration)| registration.callback_entry == callback_entry)
+            .map(|(&fd, _)| fd);
+        if let Some(fd) = fd {
+            self.fds.borrow_mut().remove(&fd);
+            // NOTE: the fd might have been closed by the callback, which removes it from epoll.
+            unsafe { ffi::epoll_ctl(self.fd, ffi::EpollOperation::Delete, fd, ptr::null_mut()) };
+        }
+    }
+
     pub fn run(&self) -> io::Result<()> {
         let mut event_list = event_list();
 
         while !self.stopped {
             match self.iterate(&mut event_list) {
-                // Restart if interrupted by signal.
+                // NOTE: the signals watched with add_signal() are blocked, so they never interrupt
+                // epoll_wait(): only the other signals with a handler can, in which case the
+                // wait is restarted.
                 EpollResult::Interrupted => continue,
                 EpollResult::Error(error) => return Err(error),
                 EpollResult::Ok => (),
@@ -282,6 +566,13 @@ impl EventLoop {
         EventLoop::wakeup();
     }
 
+    /// Get a handle to wake up this event loop from another thread.
+    pub fn waker(&self) -> Waker {
+        Waker {
+            event_fd: self.event_fd,
+        }
+    }
+
     pub fn wakeup() {
         // TODO: only wake up if currently blocking?
         EVENT_FD.with(|&event_fd| {
@@ -292,6 +583,13 @@ impl EventLoop {
     }
 }
 
+fn to_timespec(duration: Duration) -> ffi::timespec {
+    ffi::timespec {
+        tv_sec: duration.as_secs() as i64,
+        tv_nsec: i64::from(duration.subsec_nanos()),
+    }
+}
+
 pub fn event_list() -> [ffi::epoll_event; MAX_EVENTS] {
     [
         ffi::epoll_event {
@@ -321,6 +619,10 @@ pub mod ffi {
     pub const EFD_NONBLOCK: i32 = 0o4000;
     pub const EPOLLEXCLUSIVE: u32 = 1 << 28;
 
+    pub const CLOCK_MONOTONIC: i32 = 1;
+    pub const TFD_CLOEXEC: i32 = 0o2000000;
+    pub const TFD_NONBLOCK: i32 = 0o4000;
+
    #[repr(C)]
     #[derive(Clone, Copy)]
     pub union epoll_data_t {
@@ -340,6 +642,21 @@ pub mod ffi {
     #[allow(non_camel_case_types)]
     type eventfd_t = u64;
 
+    #[allow(non_camel_case_types)]
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct timespec {
+        pub tv_sec: i64,
+        pub tv_nsec: i64,
+    }
+
+    #[allow(non_camel_case_types)]
+    #[repr(C)]
+    pub struct itimerspec {
+        pub it_interval: timespec,
+        pub it_value: timespec,
+    }
+
     extern "C" {
         pub fn epoll_create1(flags: i32) -> i32;
         pub fn epoll_ctl(epfd: i32, op: EpollOperation, fd: i32, event: *mut epoll_event) -> i32;
@@ -348,5 +665,11 @@ pub mod ffi {
         pub fn eventfd(initval: u32, flags: i32) -> i32;
         pub fn eventfd_read(fd: i32, value: *mut eventfd_t) -> i32;
         pub fn eventfd_write(fd: i32, value: eventfd_t) -> i32;
+
+        pub fn timerfd_create(clockid: i32, flags: i32) -> i32;
+        pub fn timerfd_settime(fd: i32, flags: i32, new_value: *const itimerspec, old_value: *mut itimerspec) -> i32;
+
+        pub fn close(fd: i32) -> i32;
+        pub fn read(fd: i32, buf: *mut c_void, count: usize) -> isize;
     }
 }
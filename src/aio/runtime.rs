@@ -0,0 +1,259 @@
+//! Run one event loop per thread, typically one per core.
+//!
+//! Each loop is initialized by the same function, which can spawn the handler receiving the
+//! messages sent to this loop. The loops can accept connections from the same listener with
+//! `TcpListener::shared`: the listening socket is registered with `EPOLLEXCLUSIVE` in every loop
+//! so that only one of them is woken up for a new connection.
+
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::channel;
+use std::thread::{self, JoinHandle};
+
+use crate::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+    SyncStream,
+};
+
+struct StopHandler {
+    event_loop: Loop,
+}
+
+impl Handler for StopHandler {
+    type Msg = ();
+
+    fn update(&mut self, _stream: &Stream<()>, _msg: ()) {
+        self.event_loop.stop();
+    }
+}
+
+// The stream of the handler of a loop, if any, and the stream stopping it.
+type LoopStreams<MSG> = (Option<SyncStream<MSG>>, SyncStream<()>);
+
+struct Shared<MSG> {
+    stop_streams: RwLock<Vec<SyncStream<()>>>,
+    // The stream of the handler of every loop, if any.
+    streams: RwLock<Vec<Option<SyncStream<MSG>>>>,
+}
+
+impl<MSG> Shared<MSG> {
+    fn broadcast(&self, msg: MSG)
+    where MSG: Clone,
+    {
+        for stream in self.streams.read().unwrap_or_else(|error| error.into_inner()).iter().flatten() {
+            stream.send(msg.clone());
+        }
+    }
+
+    fn num_loops(&self) -> usize {
+        self.streams.read().unwrap_or_else(|error| error.into_inner()).len()
+    }
+
+    fn send(&self, index: usize, msg: MSG) {
+        if let Some(Some(stream)) = self.streams.read().unwrap_or_else(|error| error.into_inner()).get(index) {
+            stream.send(msg);
+        }
+    }
+
+    fn stop(&self) {
+        for stream in self.stop_streams.read().unwrap_or_else(|error| error.into_inner()).iter() {
+            stream.send(());
+        }
+    }
+}
+
+/// Handle given to every event loop of a `Runtime` to communicate with the other loops.
+pub struct RuntimeHandle<MSG> {
+    index: usize,
+    shared: Arc<Shared<MSG>>,
+}
+
+impl<MSG> Clone for RuntimeHandle<MSG> {
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<MSG> RuntimeHandle<MSG> {
+    /// Send `msg` to the handler of every loop, including the current one. The loops without a
+    /// handler are skipped.
+    pub fn broadcast(&self, msg: MSG)
+    where MSG: Clone,
+    {
+        self.shared.broadcast(msg);
+    }
+
+    /// Index of the current loop.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Number of loops in the runtime.
+    pub fn num_loops(&self) -> usize {
+        self.shared.num_loops()
+    }
+
+    /// Send `msg` to the handler of the loop `index`, if it has one.
+    pub fn send(&self, index: usize, msg: MSG) {
+        self.shared.send(index, msg);
+    }
+
+    /// Stop every loop of the runtime.
+    pub fn stop(&self) {
+        self.shared.stop();
+    }
+}
+
+/// A group of event loops, each running in its own thread.
+pub struct Runtime<MSG> {
+    shared: Arc<Shared<MSG>>,
+    threads: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl<MSG> Runtime<MSG>
+where MSG: Send + 'static,
+{
+    /// Start `num_threads` event loops.
+    ///
+    /// `init` is called in the thread of every loop and returns the stream of the handler that
+    /// will receive the messages sent to this loop, or `None` when the loop does not receive
+    /// messages. The loops start running once all of them are initialized. If the initialization of a loop fails, all of them are stopped and the
+    /// error is returned.
+    pub fn new<INIT>(num_threads: usize, init: INIT) -> io::Result<Self>
+    where INIT: Fn(&mut Loop, &RuntimeHandle<MSG>) -> io::Result<Option<Stream<MSG>>> + Send + Sync + 'static,
+    {
+        let init = Arc::new(init);
+        let shared = Arc::new(Shared {
+            stop_streams: RwLock::new(vec![]),
+            streams: RwLock::new(vec![]),
+        });
+        let (sender, receiver) = channel();
+        let mut threads = vec![];
+        let mut start_senders = vec![];
+
+        for index in 0..num_threads {
+            let init = init.clone();
+            let sender = sender.clone();
+            let handle = RuntimeHandle {
+                index,
+                shared: shared.clone(),
+            };
+            let (start_sender, start_receiver) = channel();
+            start_senders.push(start_sender);
+            threads.push(thread::spawn(move || {
+                let result = Loop::new().and_then(|mut event_loop| {
+                    let stream = init(&mut event_loop, &handle)?;
+                    let stop_stream = event_loop.spawn(StopHandler {
+                        event_loop: event_loop.clone(),
+                    });
+                    let stream = stream.map(|stream| event_loop.sync_stream(&stream));
+                    Ok((stream, event_loop.sync_stream(&stop_stream), event_loop))
+                });
+                match result {
+                    Ok((stream, stop_stream, mut event_loop)) => {
+                        let _ = sender.send((index, Ok((stream, stop_stream))));
+                        // NOTE: drop the sender so that the runtime does not wait for this thread
+                        // when another one panics.
+                        drop(sender);
+                        // Wait until every loop is initialized.
+                        if let Ok(true) = start_receiver.recv() {
+                            event_loop.run()?;
+                        }
+                        Ok(())
+                    },
+                    Err(error) => {
+                        let _ = sender.send((index, Err(error)));
+                        Ok(())
+                    },
+                }
+            }));
+        }
+
+        // NOTE: drop the original sender so that receiving fails when an init function panics.
+        drop(sender);
+
+        let mut streams: Vec<Option<LoopStreams<MSG>>> = (0..num_threads).map(|_| None).collect();
+        let mut error = None;
+        for _ in 0..num_threads {
+            match receiver.recv() {
+                Ok((index, Ok(loop_streams))) => streams[index] = Some(loop_streams),
+                Ok((_, Err(init_error))) => error = Some(init_error),
+                Err(_) => error = Some(io::Error::other("event loop thread panicked")),
+            }
+        }
+
+        let start = error.is_none();
+        if start {
+            let mut shared_streams = shared.streams.write().unwrap_or_else(|error| error.into_inner());
+            let mut stop_streams = shared.stop_streams.write().unwrap_or_else(|error| error.into_inner());
+            for (stream, stop_stream) in streams.into_iter().flatten() {
+                shared_streams.push(stream);
+                stop_streams.push(stop_stream);
+            }
+        }
+        for start_sender in start_senders {
+            let _ = start_sender.send(start);
+        }
+
+        match error {
+            Some(error) => {
+                for thread in threads {
+                    let _ = thread.join();
+                }
+                Err(error)
+            },
+            None => Ok(Self {
+                shared,
+                threads,
+            }),
+        }
+    }
+
+    /// Send `msg` to the handler of every loop. The loops without a handler are skipped.
+    pub fn broadcast(&self, msg: MSG)
+    where MSG: Clone,
+    {
+        self.shared.broadcast(msg);
+    }
+
+    /// Wait until every loop is stopped. Returns the first error returned by a loop.
+    pub fn join(self) -> io::Result<()> {
+        let mut result = Ok(());
+        for thread in self.threads {
+            let thread_result =
+                thread.join()
+                    .unwrap_or_else(|_| Err(io::Error::other("event loop thread panicked")));
+            if result.is_ok() {
+                result = thread_result;
+            }
+        }
+        result
+    }
+
+    /// Number of loops in the runtime.
+    pub fn num_loops(&self) -> usize {
+        self.shared.num_loops()
+    }
+
+    /// Send `msg` to the handler of the loop `index`, if it has one.
+    pub fn send(&self, index: usize, msg: MSG) {
+        self.shared.send(index, msg);
+    }
+
+    /// Stop every loop of the runtime.
+    pub fn stop(&self) {
+        self.shared.stop();
+    }
+}
+
+/// Number of cores available to run event loops.
+pub fn num_cpus() -> usize {
+    thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+}
@@ -0,0 +1,120 @@
+//! POSIX signals that can be received by an event loop through a signalfd.
+
+use std::io;
+use std::mem;
+use std::ptr;
+
+/// Signals that can be handled with `EventLoop::add_signal` or `Loop::add_signal`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[repr(i32)]
+pub enum Signal {
+    Alarm = 14,
+    Child = 17,
+    Hangup = 1,
+    Interrupt = 2,
+    Pipe = 13,
+    Quit = 3,
+    Terminate = 15,
+    User1 = 10,
+    User2 = 12,
+    WindowChange = 28,
+}
+
+impl Signal {
+    pub fn from_raw(signal: i32) -> Option<Self> {
+        let signal =
+            match signal {
+                14 => Signal::Alarm,
+                17 => Signal::Child,
+                1 => Signal::Hangup,
+                2 => Signal::Interrupt,
+                13 => Signal::Pipe,
+                3 => Signal::Quit,
+                15 => Signal::Terminate,
+                10 => Signal::User1,
+                12 => Signal::User2,
+                28 => Signal::WindowChange,
+                _ => return None,
+            };
+        Some(signal)
+    }
+}
+
+/// Block `signal` for the current thread and create a non-blocking signalfd to receive it.
+///
+/// The signal should be blocked in every thread of the process, otherwise it could be delivered
+/// to another thread with its default action. Since threads inherit the signal mask of their
+/// parent, this is done by adding the signals before spawning any thread.
+///
+/// Returns the fd and whether the signal was already blocked.
+pub(crate) fn signalfd(signal: Signal) -> io::Result<(i32, bool)> {
+    let set = signal_set(signal)?;
+    let mut old_set: ffi::sigset_t = unsafe { mem::zeroed() };
+    let result = unsafe { ffi::pthread_sigmask(ffi::SIG_BLOCK, &set, &mut old_set) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    let blocked = unsafe { ffi::sigismember(&old_set, signal as i32) } == 1;
+    let fd = unsafe { ffi::signalfd(-1, &set, ffi::SFD_NONBLOCK | ffi::SFD_CLOEXEC) };
+    if fd == -1 {
+        let error = io::Error::last_os_error();
+        if !blocked {
+            let _ = unblock(signal);
+        }
+        return Err(error);
+    }
+    Ok((fd, blocked))
+}
+
+/// Unblock `signal` for the current thread.
+///
+/// A pending signal is delivered as soon as it is unblocked.
+pub(crate) fn unblock(signal: Signal) -> io::Result<()> {
+    let set = signal_set(signal)?;
+    let result = unsafe { ffi::pthread_sigmask(ffi::SIG_UNBLOCK, &set, ptr::null_mut()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    Ok(())
+}
+
+fn signal_set(signal: Signal) -> io::Result<ffi::sigset_t> {
+    let mut set: ffi::sigset_t = unsafe { mem::zeroed() };
+    unsafe {
+        ffi::sigemptyset(&mut set);
+        if ffi::sigaddset(&mut set, signal as i32) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(set)
+}
+
+pub mod ffi {
+    #![allow(non_camel_case_types)]
+
+    pub const SFD_CLOEXEC: i32 = 0o2000000;
+    pub const SFD_NONBLOCK: i32 = 0o4000;
+
+    pub const SIG_BLOCK: i32 = 0;
+    pub const SIG_UNBLOCK: i32 = 1;
+
+    #[repr(C)]
+    pub struct sigset_t {
+        val: [u64; 16],
+    }
+
+    #[repr(C)]
+    pub struct signalfd_siginfo {
+        pub ssi_signo: u32,
+        // The other fields are not used.
+        _padding: [u8; 124],
+    }
+
+    extern "C" {
+        pub fn pthread_sigmask(how: i32, set: *const sigset_t, oldset: *mut sigset_t) -> i32;
+        pub fn sigaddset(set: *mut sigset_t, signum: i32) -> i32;
+        pub fn sigemptyset(set: *mut sigset_t) -> i32;
+        pub fn sigismember(set: *const sigset_t, signum: i32) -> i32;
+        pub fn signalfd(fd: i32, mask: *const sigset_t, flags: i32) -> i32;
+    }
+}
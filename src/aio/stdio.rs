@@ -1,10 +1,23 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
 use std::io::{
     self,
     ErrorKind,
     Read,
     Stdin as StdStdin,
+    Write,
+    stderr,
     stdin,
+    stdout,
 };
+use std::ops::Deref;
+use std::os::unix::io::{
+    AsFd,
+    AsRawFd,
+    OwnedFd,
+};
+use std::rc::{Rc, Weak};
 
 use crate::aio::poll::Mode;
 use crate::aio::poll::ffi::epoll_event;
@@ -13,7 +26,11 @@ use crate::aio::handler::{
     Handler,
     Stream,
 };
-use crate::aio::net::set_nonblocking;
+use crate::aio::net::{
+    file_status_flags,
+    set_file_status_flags,
+    set_nonblocking,
+};
 
 use self::Msg::*;
 
@@ -81,3 +98,315 @@ impl Stdin {
 pub trait InputNotify {
     fn received(&mut self, data: Vec<u8>);
 }
+
+pub trait OutputNotify {
+    fn error(&mut self, _error: io::Error) {
+    }
+
+    /// Called when the pending bytes go above the high watermark set by
+    /// `Output::set_watermarks`.
+    fn throttled(&mut self, _output: &mut Output) {
+    }
+
+    /// Called when the pending bytes go back to the low watermark after being throttled.
+    fn unthrottled(&mut self, _output: &mut Output) {
+    }
+}
+
+impl OutputNotify for () {
+}
+
+enum OutputMsg {
+    ThrottleCheck,
+    WriteEvent(epoll_event),
+}
+
+struct Writer {
+    buffers: VecDeque<Vec<u8>>,
+    event_loop: Loop,
+    file: File,
+    // The file status flags before switching to non-blocking mode.
+    flags: i32,
+    handle: Option<Stream<OutputMsg>>,
+    // Index of the first byte of the first buffer which was not written yet.
+    index: usize,
+    pending_bytes: usize,
+    throttled: bool,
+    waiting_writable: bool,
+    // Low and high watermarks of the pending bytes.
+    watermarks: Option<(usize, usize)>,
+}
+
+impl Writer {
+    // Write the queued buffers until the file would block.
+    fn flush(&mut self) -> io::Result<()> {
+        while let Some(buffer) = self.buffers.front() {
+            match self.file.write(&buffer[self.index..]) {
+                Ok(written) => {
+                    self.index += written;
+                    self.pending_bytes -= written;
+                    if self.index >= buffer.len() {
+                        self.buffers.pop_front();
+                        self.index = 0;
+                    }
+                },
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => {
+                    self.buffers.clear();
+                    self.index = 0;
+                    self.pending_bytes = 0;
+                    return Err(error);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // Return true if the throttled state changed.
+    fn update_throttled(&mut self) -> bool {
+        let throttled =
+            match self.watermarks {
+                Some((_low, high)) if !self.throttled => self.pending_bytes > high,
+                Some((low, _high)) => self.pending_bytes > low,
+                None => false,
+            };
+        let changed = throttled != self.throttled;
+        self.throttled = throttled;
+        changed
+    }
+
+    // Wait for the file to be writable if there are pending bytes.
+    // NOTE: the file is only added to the event loop when needed because a regular file cannot
+    // be added to epoll, but is always writable.
+    fn wait_writable(&mut self) -> io::Result<()> {
+        if self.buffers.is_empty() || self.waiting_writable {
+            return Ok(());
+        }
+        if let Some(ref handle) = self.handle {
+            let event = self.event_loop.try_add_raw_fd_oneshot(self.file.as_raw_fd(), Mode::Write)?;
+            event.set_callback(handle, OutputMsg::WriteEvent);
+            self.waiting_writable = true;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if self.waiting_writable {
+            let _ = self.event_loop.remove_raw_fd(self.file.as_raw_fd());
+        }
+        if let Some(ref handle) = self.handle {
+            handle.stop();
+        }
+        // NOTE: the flags are shared with the other file descriptors of the file, like the
+        // standard output, so they are restored for them.
+        let _ = set_file_status_flags(&self.file, self.flags);
+    }
+}
+
+/// Asynchronous writer which queues the data when the file, like a pipe, is full and writes it
+/// when the file is writable again.
+///
+/// The file is closed and its original mode restored when the last clone of the output is
+/// dropped, discarding the pending bytes.
+#[derive(Clone)]
+pub struct Output {
+    writer: Rc<RefCell<Writer>>,
+}
+
+impl Output {
+    /// Write to `file` without blocking, after switching it to non-blocking mode.
+    pub fn new<FILE, NOTIFY>(event_loop: &mut Loop, file: FILE, output_notify: NOTIFY) -> io::Result<Self>
+    where FILE: Into<OwnedFd>,
+          NOTIFY: OutputNotify + 'static,
+    {
+        let file = File::from(file.into());
+        let flags = file_status_flags(&file)?;
+        set_nonblocking(&file)?;
+        let output = Self {
+            writer: Rc::new(RefCell::new(Writer {
+                buffers: VecDeque::new(),
+                event_loop: event_loop.clone(),
+                file,
+                flags,
+                handle: None,
+                index: 0,
+                pending_bytes: 0,
+                throttled: false,
+                waiting_writable: false,
+                watermarks: None,
+            })),
+        };
+        let stream = event_loop.spawn(OutputHandler {
+            output_notify,
+            throttled: false,
+            writer: Rc::downgrade(&output.writer),
+        });
+        output.writer.borrow_mut().handle = Some(stream);
+        Ok(output)
+    }
+
+    /// Number of bytes written to the output that are waiting for the file to be writable.
+    pub fn pending_bytes(&self) -> usize {
+        self.writer.borrow().pending_bytes
+    }
+
+    /// Call `OutputNotify::throttled` when the pending bytes go above `high`, and
+    /// `OutputNotify::unthrottled` when they go back to `low` or below.
+    pub fn set_watermarks(&self, low: usize, high: usize) {
+        let mut writer = self.writer.borrow_mut();
+        writer.watermarks = Some((low.min(high), high));
+        if writer.update_throttled() {
+            if let Some(ref handle) = writer.handle {
+                handle.send(OutputMsg::ThrottleCheck);
+            }
+        }
+    }
+
+    /// Whether the pending bytes went above the high watermark and did not go back to the low
+    /// watermark yet.
+    pub fn throttled(&self) -> bool {
+        self.writer.borrow().throttled
+    }
+
+    /// Write `buffer` to the output.
+    ///
+    /// What cannot be written without blocking is queued and written when the file is writable.
+    pub fn write(&self, buffer: Vec<u8>) -> io::Result<()> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let mut writer = self.writer.borrow_mut();
+        writer.pending_bytes += buffer.len();
+        writer.buffers.push_back(buffer);
+        // NOTE: only write right away when nothing is queued, to keep the data in order.
+        if writer.buffers.len() == 1 {
+            writer.flush()?;
+        }
+        writer.wait_writable()?;
+        if writer.update_throttled() {
+            if let Some(ref handle) = writer.handle {
+                handle.send(OutputMsg::ThrottleCheck);
+            }
+        }
+        Ok(())
+    }
+}
+
+struct OutputHandler<NOTIFY> {
+    output_notify: NOTIFY,
+    // The throttled state last sent to the notify.
+    throttled: bool,
+    // NOTE: the handler does not keep the output alive, so that the writer stops it when the
+    // output is dropped.
+    writer: Weak<RefCell<Writer>>,
+}
+
+impl<NOTIFY> OutputHandler<NOTIFY>
+where NOTIFY: OutputNotify,
+{
+    fn check_throttled(&mut self, output: &mut Output) {
+        let throttled = output.throttled();
+        if throttled != self.throttled {
+            self.throttled = throttled;
+            if throttled {
+                self.output_notify.throttled(output);
+            }
+            else {
+                self.output_notify.unthrottled(output);
+            }
+        }
+    }
+}
+
+impl<NOTIFY> Handler for OutputHandler<NOTIFY>
+where NOTIFY: OutputNotify,
+{
+    type Msg = OutputMsg;
+
+    fn update(&mut self, _stream: &Stream<OutputMsg>, msg: OutputMsg) {
+        let mut output =
+            match self.writer.upgrade() {
+                Some(writer) => Output { writer },
+                None => return,
+            };
+        match msg {
+            // NOTE: the throttled state is checked after every message.
+            OutputMsg::ThrottleCheck => (),
+            OutputMsg::WriteEvent(_event) => {
+                let result = {
+                    let mut writer = output.writer.borrow_mut();
+                    writer.waiting_writable = false;
+                    let _ = writer.event_loop.remove_raw_fd(writer.file.as_raw_fd());
+                    writer.flush()
+                        .and_then(|()| writer.wait_writable())
+                        .map(|()| writer.update_throttled())
+                };
+                if let Err(error) = result {
+                    self.output_notify.error(error);
+                }
+            },
+        }
+        self.check_throttled(&mut output);
+    }
+}
+
+/// Non-blocking standard output.
+///
+/// NOTE: the non-blocking mode is shared with the other users of the standard output, so
+/// `print!` can fail until the last clone of this handle is dropped: everything should be written
+/// through it.
+#[derive(Clone)]
+pub struct Stdout {
+    output: Output,
+}
+
+impl Stdout {
+    pub fn new<NOTIFY>(event_loop: &mut Loop, output_notify: NOTIFY) -> io::Result<Self>
+    where NOTIFY: OutputNotify + 'static,
+    {
+        let file = stdout().as_fd().try_clone_to_owned()?;
+        Ok(Self {
+            output: Output::new(event_loop, file, output_notify)?,
+        })
+    }
+}
+
+impl Deref for Stdout {
+    type Target = Output;
+
+    fn deref(&self) -> &Output {
+        &self.output
+    }
+}
+
+/// Non-blocking standard error.
+///
+/// NOTE: the non-blocking mode is shared with the other users of the standard error, so
+/// `eprint!` can fail until the last clone of this handle is dropped: everything should be written
+/// through it.
+#[derive(Clone)]
+pub struct Stderr {
+    output: Output,
+}
+
+impl Stderr {
+    pub fn new<NOTIFY>(event_loop: &mut Loop, output_notify: NOTIFY) -> io::Result<Self>
+    where NOTIFY: OutputNotify + 'static,
+    {
+        let file = stderr().as_fd().try_clone_to_owned()?;
+        Ok(Self {
+            output: Output::new(event_loop, file, output_notify)?,
+        })
+    }
+}
+
+impl Deref for Stderr {
+    type Target = Output;
+
+    fn deref(&self) -> &Output {
+        &self.output
+    }
+}
@@ -1,12 +1,14 @@
-//! This crate provides a barebone, zero-allocation parser for [HTTP
+//! A barebone, zero-allocation parser for [HTTP
 //! URIs](https://tools.ietf.org/html/rfc7230#section-2.7) as they appear in a request
-//! header.
+//! header, adapted from the uhttp_uri crate, along with the codec of their components.
 //!
 //! In general, components are extracted along defined delimiters, but further validation
-//! and processing (such as percent decoding, query decoding, and punycode decoding) is
-//! left to higher layers. In the pursuit of simplicity, this crate also contains no
-//! support for generic and non-http URIs such as `file:` and `ftp://` – only the reduced
-//! syntax for [`http://`](https://tools.ietf.org/html/rfc7230#section-2.7.1) and
+//! and processing is left to higher layers. This module also provides the percent-encoding
+//! of the path and the query string (`percent_decode`, `percent_encode` and the form
+//! variants) and a parsed `Query`, while punycode decoding is not supported. In the pursuit
+//! of simplicity, there is no support for generic and non-http URIs such as `file:` and
+//! `ftp://` – only the reduced syntax for
+//! [`http://`](https://tools.ietf.org/html/rfc7230#section-2.7.1) and
 //! [`https://`](https://tools.ietf.org/html/rfc7230#section-2.7.2) schemes is
 //! implemented.
 
@@ -55,20 +57,33 @@ impl<'a> HttpUri<'a> {
             return Err(());
         }
 
-        let (host, port) = match authority.find(':') {
-            Some(idx) => {
-                let (host, port) = authority.split_at(idx);
-                (host, port[1..].parse().map_err(|_| ())?)
-            },
-            None => {
-                let port =
+        // NOTE: an IPv6 address is enclosed in brackets, which are not part of the host.
+        let (host, port) =
+            if authority.starts_with('[') {
+                let end = authority.find(']').ok_or(())?;
+                let host = &authority[1..end];
+                if host.parse::<std::net::Ipv6Addr>().is_err() {
+                    return Err(());
+                }
+                (host, &authority[end + 1..])
+            }
+            else {
+                match authority.find(':') {
+                    Some(idx) => authority.split_at(idx),
+                    None => (authority, ""),
+                }
+            };
+
+        let port =
+            match port.strip_prefix(':') {
+                Some(port) => port.parse().map_err(|_| ())?,
+                None if port.is_empty() =>
                     match scheme {
                         HttpScheme::Http => 80,
                         HttpScheme::Https => 443,
-                    };
-                (authority, port)
-            },
-        };
+                    },
+                None => return Err(()),
+            };
 
         Ok(HttpUri {
             authority: authority,
@@ -201,6 +216,154 @@ impl std::fmt::Display for HttpScheme {
     }
 }
 
+/// Decode the percent-encoded octets of a path, like `/a%20b`.
+///
+/// Fails if a `%` is not followed by two hexadecimal digits or if the decoded octets are not
+/// valid UTF-8.
+pub fn percent_decode(s: &str) -> std::io::Result<String> {
+    decode(s, false)
+}
+
+/// Decode a name or a value of an `application/x-www-form-urlencoded` query string, where `+`
+/// stands for a space.
+pub fn form_decode(s: &str) -> std::io::Result<String> {
+    decode(s, true)
+}
+
+fn decode(s: &str, plus_as_space: bool) -> std::io::Result<String> {
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid percent-encoding");
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let digits = bytes.get(index + 1..index + 3).ok_or_else(invalid)?;
+                let high = hex_value(digits[0]).ok_or_else(invalid)?;
+                let low = hex_value(digits[1]).ok_or_else(invalid)?;
+                decoded.push(high << 4 | low);
+                index += 3;
+                continue;
+            },
+            b'+' if plus_as_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16)
+        .map(|value| value as u8)
+}
+
+/// Percent-encode every character of `s` except the unreserved ones, so that it can be used as
+/// a path segment.
+pub fn percent_encode(s: &str) -> String {
+    encode(s, false)
+}
+
+/// Encode `s` as a name or a value of an `application/x-www-form-urlencoded` query string.
+pub fn form_encode(s: &str) -> String {
+    encode(s, true)
+}
+
+fn encode(s: &str, space_as_plus: bool) -> String {
+    const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut encoded = String::with_capacity(s.len());
+    for &byte in s.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            b' ' if space_as_plus => encoded.push('+'),
+            _ => {
+                encoded.push('%');
+                encoded.push(HEX_DIGITS[(byte >> 4) as usize] as char);
+                encoded.push(HEX_DIGITS[(byte & 0xF) as usize] as char);
+            },
+        }
+    }
+    encoded
+}
+
+/// Fields of an `application/x-www-form-urlencoded` query string, in their original order.
+///
+/// A name can appear multiple times, like in `tag=a&tag=b`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Query {
+    fields: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse and decode a query string like `name=a+b&tag=%C3%A9`. A field without `=` has an
+    /// empty value.
+    pub fn parse(query_string: &str) -> std::io::Result<Self> {
+        let fields = query_string.split('&')
+            .filter(|field| !field.is_empty())
+            .map(|field| {
+                let (name, value) = field.split_once('=').unwrap_or((field, ""));
+                Ok((form_decode(name)?, form_decode(value)?))
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(Self {
+            fields,
+        })
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    /// Get the value of the first field named `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(field_name, _)| field_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Get the values of all the fields named `name`.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.fields.iter()
+            .filter(move |(field_name, _)| field_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.fields.iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn with_field(mut self, name: &str, value: &str) -> Self {
+        self.append(name, value);
+        self
+    }
+}
+
+/// Writes the encoded query string, without the leading `?`.
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, (name, value)) in self.fields.iter().enumerate() {
+            if index > 0 {
+                f.write_str("&")?;
+            }
+            write!(f, "{}={}", form_encode(name), form_encode(value))?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -347,6 +510,36 @@ mod test {
                 }
             });
 
+        assert_eq!(HttpUri::new("http://[::1]:61761/chunks").unwrap(),
+            HttpUri {
+                scheme: HttpScheme::Http,
+                authority: "[::1]:61761",
+                host: "::1",
+                port: 61761,
+                resource: HttpResource {
+                    path: "/chunks",
+                    query: None,
+                    fragment: None,
+                }
+            });
+
+        assert_eq!(HttpUri::new("https://[2001:db8::7]").unwrap(),
+            HttpUri {
+                scheme: HttpScheme::Https,
+                authority: "[2001:db8::7]",
+                host: "2001:db8::7",
+                port: 443,
+                resource: HttpResource {
+                    path: "/",
+                    query: None,
+                    fragment: None,
+                }
+            });
+
+        assert!(HttpUri::new("http://[::1/").is_err());
+        assert!(HttpUri::new("http://[::1]61761/").is_err());
+        assert!(HttpUri::new("http://[example.com]/").is_err());
+        assert!(HttpUri::new("http://::1/").is_err());
         assert!(HttpUri::new("http://").is_err());
         assert!(HttpUri::new("http:///").is_err());
         assert!(HttpUri::new("://example.com").is_err());
@@ -369,4 +562,42 @@ mod test {
                 }
             });
     }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("/a%20b/c%2Fd").unwrap(), "/a b/c/d");
+        assert_eq!(percent_decode("%C3%A9t%c3%a9+").unwrap(), "été+");
+        assert_eq!(form_decode("a+b%2B").unwrap(), "a b+");
+        assert!(percent_decode("%").is_err());
+        assert!(percent_decode("%2").is_err());
+        assert!(percent_decode("%zz").is_err());
+        assert!(percent_decode("%FF").is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(percent_encode("a b/été~"), "a%20b%2F%C3%A9t%C3%A9~");
+        assert_eq!(form_encode("a b+c&d=e"), "a+b%2Bc%26d%3De");
+        assert_eq!(form_decode(&form_encode("a b+c&d=é")).unwrap(), "a b+c&d=é");
+    }
+
+    #[test]
+    fn test_query() {
+        let query = Query::parse("tag=a&name=John+Doe&tag=b%26c&&flag&empty=").unwrap();
+        assert_eq!(query.len(), 5);
+        assert_eq!(query.get("name"), Some("John Doe"));
+        assert_eq!(query.get_all("tag").collect::<Vec<_>>(), vec!["a", "b&c"]);
+        assert_eq!(query.get("flag"), Some(""));
+        assert_eq!(query.get("empty"), Some(""));
+        assert_eq!(query.get("missing"), None);
+        assert_eq!(query.to_string(), "tag=a&name=John+Doe&tag=b%26c&flag=&empty=");
+        assert!(Query::parse("a=%G0").is_err());
+
+        let query = Query::new()
+            .with_field("q", "mini rs")
+            .with_field("page", "2");
+        assert_eq!(query.to_string(), "q=mini+rs&page=2");
+        assert_eq!(Query::parse(&query.to_string()).unwrap(), query);
+        assert_eq!(Query::new().to_string(), "");
+    }
 }
@@ -0,0 +1,397 @@
+//! Owned HTTP URIs which can be built from their parts, normalized and resolved.
+//!
+//! A `Uri` is always normalized (RFC 3986 section 6.2.2): the scheme and the host are
+//! lowercased, the default port is omitted, the dot segments are removed from the path and the
+//! percent-encodings are uppercased, with the unreserved characters decoded. Thus, writing a
+//! `Uri` and parsing it back gives the same `Uri`.
+
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+
+use crate::aio::uhttp_uri::{HttpUri, Query, percent_encode};
+pub use crate::aio::uhttp_uri::HttpScheme;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Uri {
+    fragment: Option<String>,
+    host: String,
+    path: String,
+    port: u16,
+    query: Option<String>,
+    scheme: HttpScheme,
+}
+
+impl Uri {
+    /// Create the uri of the root path of `host`, with the default port of `scheme`.
+    ///
+    /// `host` is a domain name, an IPv4 address or an IPv6 address without brackets.
+    pub fn new(scheme: HttpScheme, host: &str) -> io::Result<Self> {
+        if !is_valid_host(host) {
+            return Err(invalid_uri());
+        }
+        Ok(Self {
+            fragment: None,
+            host: host.to_ascii_lowercase(),
+            path: "/".to_string(),
+            port: default_port(scheme),
+            query: None,
+            scheme,
+        })
+    }
+
+    /// Parse an absolute http or https uri.
+    pub fn parse(uri: &str) -> io::Result<Self> {
+        // NOTE: the scheme is case-insensitive, but HttpUri only accepts it in lowercase.
+        let uri =
+            match uri.find("://") {
+                Some(index) => format!("{}{}", uri[..index].to_ascii_lowercase(), &uri[index..]),
+                None => return Err(invalid_uri()),
+            };
+        let http_uri = HttpUri::new(&uri).map_err(|()| invalid_uri())?;
+        if !is_valid_host(http_uri.host) {
+            return Err(invalid_uri());
+        }
+        Ok(Self {
+            fragment: http_uri.resource.fragment.map(normalize_percent_encoding),
+            host: http_uri.host.to_ascii_lowercase(),
+            path: remove_dot_segments(&normalize_percent_encoding(http_uri.resource.path)),
+            port: http_uri.port,
+            query: http_uri.resource.query.map(normalize_percent_encoding),
+            scheme: http_uri.scheme,
+        })
+    }
+
+    /// The host and the port if it is not the default one, with an IPv6 host in brackets.
+    pub fn authority(&self) -> String {
+        let mut authority =
+            if self.host.contains(':') {
+                format!("[{}]", self.host)
+            }
+            else {
+                self.host.clone()
+            };
+        if self.port != default_port(self.scheme) {
+            authority.push_str(&format!(":{}", self.port));
+        }
+        authority
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The percent-encoded path, which always starts with `/`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The percent-encoded query string, without the leading `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    pub fn scheme(&self) -> HttpScheme {
+        self.scheme
+    }
+
+    /// Resolve the uri reference `reference`, like the value of a `Location` header, relative to
+    /// this uri (RFC 3986 section 5.2).
+    pub fn resolve(&self, reference: &str) -> io::Result<Self> {
+        if has_scheme(reference) {
+            return Self::parse(reference);
+        }
+        if reference.starts_with("//") {
+            return Self::parse(&format!("{}:{}", self.scheme, reference));
+        }
+        let (reference, fragment) =
+            match reference.split_once('#') {
+                Some((reference, fragment)) => (reference, Some(normalize_percent_encoding(fragment))),
+                None => (reference, None),
+            };
+        let (path, query) =
+            match reference.split_once('?') {
+                Some((path, query)) => (path, Some(normalize_percent_encoding(query))),
+                None => (reference, None),
+            };
+        let mut uri = self.clone();
+        uri.fragment = fragment;
+        if path.is_empty() {
+            if query.is_some() {
+                uri.query = query;
+            }
+            return Ok(uri);
+        }
+        let path = normalize_percent_encoding(path);
+        uri.path =
+            if path.starts_with('/') {
+                remove_dot_segments(&path)
+            }
+            else {
+                let directory = &self.path[..self.path.rfind('/').map_or(0, |index| index + 1)];
+                remove_dot_segments(&format!("{}{}", directory, path))
+            };
+        uri.query = query;
+        Ok(uri)
+    }
+
+    /// Set the percent-encoded fragment. A `#` in `fragment` is percent-encoded.
+    pub fn with_fragment(mut self, fragment: &str) -> Self {
+        self.fragment = Some(normalize_percent_encoding(&fragment.replace('#', "%23")));
+        self
+    }
+
+    /// Set the percent-encoded path. A missing leading `/` is added and a `?` or a `#` in `path`
+    /// is percent-encoded.
+    pub fn with_path(mut self, path: &str) -> Self {
+        let path = normalize_percent_encoding(&path.replace('?', "%3F").replace('#', "%23"));
+        self.path =
+            if path.starts_with('/') {
+                remove_dot_segments(&path)
+            }
+            else {
+                remove_dot_segments(&format!("/{}", path))
+            };
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Replace the query string by the encoded fields of `query`.
+    pub fn with_query(mut self, query: &Query) -> Self {
+        self.query =
+            if query.is_empty() {
+                None
+            }
+            else {
+                Some(query.to_string())
+            };
+        self
+    }
+
+    /// Append `segment` to the path after percent-encoding it, so that a `/` in `segment` does
+    /// not create a new segment.
+    pub fn with_segment(mut self, segment: &str) -> Self {
+        if !self.path.ends_with('/') {
+            self.path.push('/');
+        }
+        self.path.push_str(&percent_encode(segment));
+        self
+    }
+}
+
+impl Display for Uri {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "{}://{}{}", self.scheme, self.authority(), self.path)?;
+        if let Some(ref query) = self.query {
+            write!(formatter, "?{}", query)?;
+        }
+        if let Some(ref fragment) = self.fragment {
+            write!(formatter, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Uri {
+    type Err = io::Error;
+
+    fn from_str(uri: &str) -> io::Result<Self> {
+        Self::parse(uri)
+    }
+}
+
+fn default_port(scheme: HttpScheme) -> u16 {
+    match scheme {
+        HttpScheme::Http => 80,
+        HttpScheme::Https => 443,
+    }
+}
+
+// Check if the uri reference starts with a scheme (RFC 3986 section 3.1).
+fn has_scheme(reference: &str) -> bool {
+    match reference.find(':') {
+        Some(index) => {
+            let scheme = &reference[..index];
+            scheme.starts_with(|character: char| character.is_ascii_alphabetic()) &&
+                scheme.chars().all(|character| character.is_ascii_alphanumeric() || "+-.".contains(character))
+        },
+        None => false,
+    }
+}
+
+// Check if the host is a domain name or an IPv4 address, made of unreserved characters, or an
+// IPv6 address.
+fn is_valid_host(host: &str) -> bool {
+    if host.contains(':') {
+        return host.parse::<Ipv6Addr>().is_ok();
+    }
+    !host.is_empty() && host.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte))
+}
+
+fn invalid_uri() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "invalid uri")
+}
+
+// Uppercase the hexadecimal digits of the percent-encodings and decode the unreserved characters.
+fn normalize_percent_encoding(s: &str) -> String {
+    let mut normalized = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(index) = rest.find('%') {
+        normalized.push_str(&rest[..index]);
+        let escape = &rest[index..];
+        // NOTE: from_str_radix() accepts a sign, so the digits are checked first.
+        let digits = escape.get(1..3).filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        match digits.and_then(|digits| u8::from_str_radix(digits, 16).ok()) {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                normalized.push(byte as char);
+                rest = &escape[3..];
+            },
+            Some(_) => {
+                normalized.push_str(&escape[..3].to_ascii_uppercase());
+                rest = &escape[3..];
+            },
+            None => {
+                normalized.push('%');
+                rest = &escape[1..];
+            },
+        }
+    }
+    normalized.push_str(rest);
+    normalized
+}
+
+// Remove the segments `.` and `..` from an absolute path (RFC 3986 section 5.2.4).
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments = vec![];
+    for segment in path.split('/').skip(1) {
+        match segment {
+            "." => (),
+            ".." => {
+                segments.pop();
+            },
+            _ => segments.push(segment),
+        }
+    }
+    let mut result = format!("/{}", segments.join("/"));
+    if (path.ends_with("/.") || path.ends_with("/..")) && !result.ends_with('/') {
+        result.push('/');
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalization() {
+        let uri = Uri::parse("HTTP://Example.COM:80/a/./b/../c/%7euser/%2f?q=%3d#Frag").unwrap();
+        assert_eq!(uri.scheme(), HttpScheme::Http);
+        assert_eq!(uri.host(), "example.com");
+        assert_eq!(uri.port(), 80);
+        assert_eq!(uri.path(), "/a/c/~user/%2F");
+        assert_eq!(uri.query(), Some("q=%3D"));
+        assert_eq!(uri.fragment(), Some("Frag"));
+        assert_eq!(uri.to_string(), "http://example.com/a/c/~user/%2F?q=%3D#Frag");
+        assert_eq!(Uri::parse(&uri.to_string()).unwrap(), uri);
+
+        let uri: Uri = "https://[::1]:8443".parse().unwrap();
+        assert_eq!(uri.to_string(), "https://[::1]:8443/");
+        assert_eq!(Uri::parse(&uri.to_string()).unwrap(), uri);
+
+        // A sign is not a hexadecimal digit.
+        let uri = Uri::parse("http://example.com/%+1%-1%zz%4").unwrap();
+        assert_eq!(uri.path(), "/%+1%-1%zz%4");
+        assert_eq!(Uri::parse(&uri.to_string()).unwrap(), uri);
+
+        assert_eq!(remove_dot_segments("/a/b/c/./../../g"), "/a/g");
+        assert_eq!(remove_dot_segments("/mid/content=5/../6"), "/mid/6");
+        assert_eq!(remove_dot_segments("/a/b/.."), "/a/");
+        assert_eq!(remove_dot_segments("/../.."), "/");
+        assert_eq!(remove_dot_segments("/a//b/"), "/a//b/");
+
+        assert!(Uri::parse("example.com/path").is_err());
+        assert!(Uri::parse("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn test_builder() {
+        let query = Query::new().with_field("q", "a b");
+        let uri = Uri::new(HttpScheme::Https, "API.example.com").unwrap()
+            .with_port(8443)
+            .with_path("v1/")
+            .with_segment("users")
+            .with_segment("john/doe")
+            .with_query(&query)
+            .with_fragment("top");
+        assert_eq!(uri.to_string(), "https://api.example.com:8443/v1/users/john%2Fdoe?q=a+b#top");
+        assert_eq!(Uri::parse(&uri.to_string()).unwrap(), uri);
+
+        let uri = Uri::new(HttpScheme::Http, "example.com").unwrap().with_segment("a");
+        assert_eq!(uri.to_string(), "http://example.com/a");
+        assert_eq!(uri.with_port(80).with_segment("b").to_string(), "http://example.com/a/b");
+
+        // The uris built from any part are parsed back to the same uri.
+        let uri = Uri::new(HttpScheme::Http, "::1").unwrap().with_path("a?b#c").with_fragment("x#y");
+        assert_eq!(uri.to_string(), "http://[::1]/a%3Fb%23c#x%23y");
+        assert_eq!(Uri::parse(&uri.to_string()).unwrap(), uri);
+        for host in &["evil.com/x", "a@b", "h:1", "a?b", "a#b", "", "[::1]"] {
+            assert!(Uri::new(HttpScheme::Http, host).is_err(), "{}", host);
+        }
+        assert!(Uri::parse("http://a@b/").is_err());
+        assert!(Uri::parse("http://evil.com?x").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        // Examples of RFC 3986 section 5.4.
+        let base = Uri::parse("http://a/b/c/d;p?q").unwrap();
+        let examples = [
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g/"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("https://other:8080/x", "https://other:8080/x"),
+        ];
+        for &(reference, expected) in &examples {
+            assert_eq!(base.resolve(reference).unwrap().to_string(), expected, "{}", reference);
+        }
+        assert!(base.resolve("mailto:someone@example.com").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+extern crate mini;
+
+use std::cell::RefCell;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use mini::aio::dns;
+use mini::aio::handler::Loop;
+use mini::aio::net::{
+    TcpConnection,
+    TcpConnectionNotify,
+};
+
+#[test]
+fn test_resolve() {
+    let mut event_loop = Loop::new().expect("event loop");
+    let result = Rc::new(RefCell::new(None));
+
+    // The lookup is done in a resolver thread.
+    {
+        let result = result.clone();
+        let mut inner_loop = event_loop.clone();
+        dns::resolve(&mut event_loop, "localhost", 4242, move |addresses| {
+            *result.borrow_mut() = Some(addresses.expect("resolve").collect::<Vec<_>>());
+            inner_loop.stop();
+        });
+    }
+    assert!(result.borrow().is_none());
+    event_loop.run().expect("event loop run");
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 4242);
+    assert!(result.borrow().as_ref().expect("addresses").contains(&localhost));
+
+    // The addresses are then cached.
+    let cached = Rc::new(RefCell::new(None));
+    {
+        let cached = cached.clone();
+        dns::resolve(&mut event_loop, "localhost", 4242, move |addresses| {
+            *cached.borrow_mut() = Some(addresses.expect("resolve").collect::<Vec<_>>());
+        });
+    }
+    assert_eq!(*cached.borrow(), *result.borrow());
+
+    let ip = Rc::new(RefCell::new(None));
+    {
+        let ip = ip.clone();
+        dns::resolve(&mut event_loop, "::1", 80, move |addresses| {
+            *ip.borrow_mut() = Some(addresses.expect("resolve").collect::<Vec<_>>());
+        });
+    }
+    assert_eq!(*ip.borrow(), Some(vec!["[::1]:80".parse().expect("address")]));
+}
+
+struct Client {
+    event_loop: Loop,
+    events: Rc<RefCell<Vec<String>>>,
+}
+
+impl TcpConnectionNotify for Client {
+    fn connect_failed(&mut self) {
+        self.events.borrow_mut().push("connect failed".to_string());
+        self.event_loop.stop();
+    }
+
+    fn connected(&mut self, connection: &mut TcpConnection) {
+        self.events.borrow_mut().push("connected".to_string());
+        connection.dispose();
+        self.event_loop.stop();
+    }
+
+    fn error(&mut self, error: io::Error) {
+        self.events.borrow_mut().push(format!("error {:?}", error.kind()));
+    }
+}
+
+#[test]
+fn test_connect_to_host() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let port = listener.local_addr().expect("local address").port();
+    thread::spawn(move || {
+        let _stream = listener.accept().expect("accept");
+    });
+
+    let mut event_loop = Loop::new().expect("event loop");
+    let events = Rc::new(RefCell::new(vec![]));
+    let client = Client {
+        event_loop: event_loop.clone(),
+        events: events.clone(),
+    };
+    assert!(TcpConnection::ip4(&mut event_loop, "localhost", port, client).is_some());
+    event_loop.run().expect("event loop run");
+    assert_eq!(*events.borrow(), vec!["connected".to_string()]);
+
+    // The resolution errors are reported asynchronously.
+    events.borrow_mut().clear();
+    let mut event_loop = Loop::new().expect("event loop");
+    let client = Client {
+        event_loop: event_loop.clone(),
+        events: events.clone(),
+    };
+    assert!(TcpConnection::ip4(&mut event_loop, "invalid\0host", port, client).is_some());
+    assert!(events.borrow().is_empty());
+    event_loop.run().expect("event loop run");
+    assert_eq!(*events.borrow(), vec!["error InvalidInput".to_string(), "connect failed".to_string()]);
+}
+
+// Answer the A queries for ttl.test with 10.1.2.3 and a TTL of 1 second, the A queries for
+// spoofed.test with 10.1.2.4 after a malformed response, the other queries with no records, and
+// never answer the queries for blackhole.test.
+fn serve_dns(ttl_queries: Arc<AtomicUsize>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind");
+    let address = socket.local_addr().expect("local address");
+    thread::spawn(move || {
+        let mut buffer = [0; 512];
+        loop {
+            let (size, client) = socket.recv_from(&mut buffer).expect("recv");
+            let query = &buffer[..size];
+            let question = &query[12..];
+            if question.starts_with(b"\x09blackhole\x04test\x00") {
+                continue;
+            }
+            let ttl = question.eq_ignore_ascii_case(b"\x03ttl\x04test\x00\x00\x01\x00\x01");
+            if ttl {
+                ttl_queries.fetch_add(1, Ordering::SeqCst);
+            }
+            let spoofed = question == b"\x07spoofed\x04test\x00\x00\x01\x00\x01";
+            if spoofed {
+                let mut response = query[..2].to_vec();
+                response.extend_from_slice(&[0x81, 0x80, 0, 1]);
+                socket.send_to(&response, client).expect("send");
+            }
+            let answer = ttl || spoofed;
+            let mut response = query[..2].to_vec();
+            response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, answer as u8, 0, 0, 0, 0]);
+            response.extend_from_slice(question);
+            if answer {
+                response.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 1, 0, 4, 10, 1, 2, 3 + spoofed as u8]);
+            }
+            socket.send_to(&response, client).expect("send");
+        }
+    });
+    address
+}
+
+fn blocking_resolve(host: &str) -> io::Result<Vec<SocketAddr>> {
+    let mut event_loop = Loop::new().expect("event loop");
+    let result = Rc::new(RefCell::new(None));
+    {
+        let result = result.clone();
+        let mut inner_loop = event_loop.clone();
+        dns::resolve(&mut event_loop, host, 80, move |addresses| {
+            *result.borrow_mut() = Some(addresses.map(Iterator::collect));
+            inner_loop.stop();
+        });
+    }
+    event_loop.run().expect("event loop run");
+    let result = result.borrow_mut().take();
+    result.expect("resolve result")
+}
+
+#[test]
+fn test_name_servers() {
+    let ttl_queries = Arc::new(AtomicUsize::new(0));
+    dns::set_name_servers(vec![serve_dns(ttl_queries.clone())]);
+    dns::set_timeout(Duration::from_millis(500));
+
+    // The addresses are cached for the TTL of the records.
+    let address: SocketAddr = "10.1.2.3:80".parse().expect("address");
+    assert_eq!(blocking_resolve("ttl.test").expect("resolve"), vec![address]);
+    assert_eq!(blocking_resolve("ttl.test").expect("resolve"), vec![address]);
+    assert_eq!(ttl_queries.load(Ordering::SeqCst), 1);
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(blocking_resolve("TTL.test.").expect("resolve"), vec![address]);
+    assert_eq!(ttl_queries.load(Ordering::SeqCst), 2);
+
+    assert_eq!(blocking_resolve("none.test").expect_err("no address").kind(), io::ErrorKind::NotFound);
+
+    // The responses which cannot be parsed are ignored.
+    let address: SocketAddr = "10.1.2.4:80".parse().expect("address");
+    assert_eq!(blocking_resolve("spoofed.test").expect("resolve"), vec![address]);
+
+    // The resolution times out when the name server does not answer.
+    assert_eq!(blocking_resolve("blackhole.test").expect_err("timeout").kind(), io::ErrorKind::TimedOut);
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let mut event_loop = Loop::new().expect("event loop");
+    let client = Client {
+        event_loop: event_loop.clone(),
+        events: events.clone(),
+    };
+    assert!(TcpConnection::connect(&mut event_loop, "blackhole.test", 80, client).is_some());
+    event_loop.run().expect("event loop run");
+    assert_eq!(*events.borrow(), vec!["error TimedOut".to_string(), "connect failed".to_string()]);
+}
@@ -0,0 +1,148 @@
+extern crate mini;
+
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use mini::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+};
+use mini::aio::poll::{
+    self,
+    Action,
+    EventLoop,
+    Mode,
+};
+
+use self::Msg::*;
+
+#[derive(Clone)]
+enum Msg {
+    Stop,
+    Tick,
+    Value(u32),
+}
+
+struct Component {
+    event_loop: Loop,
+    events: Rc<RefCell<Vec<String>>>,
+}
+
+impl Handler for Component {
+    type Msg = Msg;
+
+    fn started(&mut self, stream: &Stream<Msg>) {
+        self.events.borrow_mut().push("started".to_string());
+        // The timer is cancelled when the handler is stopped.
+        self.event_loop.set_interval(Duration::from_millis(10), stream, Tick).expect("set interval");
+    }
+
+    fn stopped(&mut self) {
+        self.events.borrow_mut().push("stopped".to_string());
+        self.event_loop.stop();
+    }
+
+    fn update(&mut self, stream: &Stream<Msg>, msg: Msg) {
+        match msg {
+            Stop => stream.stop(),
+            Tick => (),
+            Value(value) => self.events.borrow_mut().push(value.to_string()),
+        }
+    }
+}
+
+#[test]
+fn test_stop_handler() {
+    let mut event_loop = Loop::new().expect("event loop");
+    let events = Rc::new(RefCell::new(vec![]));
+    let stream = event_loop.spawn(Component {
+        event_loop: event_loop.clone(),
+        events: events.clone(),
+    });
+    stream.send(Value(1));
+    stream.send(Stop);
+    // Messages sent after stopping are dropped.
+    stream.send(Value(2));
+
+    event_loop.run().expect("event loop run");
+
+    assert!(stream.stopped());
+    stream.send(Value(3));
+    assert_eq!(*events.borrow(), vec!["started", "1", "stopped"]);
+}
+
+struct Sum {
+    event_loop: Loop,
+    sum: Rc<RefCell<u32>>,
+}
+
+impl Handler for Sum {
+    type Msg = Option<u32>;
+
+    fn update(&mut self, _stream: &Stream<Option<u32>>, msg: Option<u32>) {
+        match msg {
+            Some(value) => *self.sum.borrow_mut() += value,
+            None => self.event_loop.stop(),
+        }
+    }
+}
+
+#[test]
+fn test_sync_stream() {
+    let mut event_loop = Loop::new().expect("event loop");
+    let sum = Rc::new(RefCell::new(0));
+    let stream = event_loop.spawn(Sum {
+        event_loop: event_loop.clone(),
+        sum: sum.clone(),
+    });
+    let sync_stream = event_loop.sync_stream(&stream);
+
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let sync_stream = sync_stream.clone();
+            thread::spawn(move || {
+                for value in 1..=100 {
+                    sync_stream.send(Some(value));
+                }
+            })
+        })
+        .collect();
+    thread::spawn(move || {
+        for worker in workers {
+            worker.join().expect("join");
+        }
+        sync_stream.send(None);
+    });
+
+    event_loop.run().expect("event loop run");
+
+    assert_eq!(*sum.borrow(), 4 * 5050);
+}
+
+#[test]
+fn test_stop_callback() {
+    let event_loop = EventLoop::new().expect("event loop");
+    let (reader, mut writer) = UnixStream::pair().expect("socket pair");
+    let calls = Rc::new(Cell::new(0));
+    let callback_calls = calls.clone();
+    event_loop.add_raw_fd(reader.as_raw_fd(), Mode::Read, move |_event| {
+        callback_calls.set(callback_calls.get() + 1);
+        Action::Stop
+    }).expect("add fd");
+    writer.write_all(b"a").expect("write");
+    let mut event_list = poll::event_list();
+    event_loop.iterate(&mut event_list);
+    assert_eq!(calls.get(), 1);
+
+    // The fd of a stopped callback is removed from the event loop, so it can be added again.
+    event_loop.add_raw_fd(reader.as_raw_fd(), Mode::Read, move |_event| {
+        calls.set(calls.get() + 1);
+        Action::Stop
+    }).expect("add fd again");
+}
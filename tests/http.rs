@@ -0,0 +1,410 @@
+extern crate mini;
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mini::aio::handler::Loop;
+use mini::aio::http::{
+    Http,
+    HttpHandler,
+    HttpRequest,
+    HttpResponse,
+    Method,
+    PoolConfig,
+    Query,
+    RetryPolicy,
+    Version,
+    percent_encode,
+};
+use mini::aio::poll::event_list;
+use mini::aio::http_server::{
+    self,
+    Request,
+    Response,
+    Router,
+};
+
+// Send `response` to the next client after reading its request, then close the connection.
+fn serve_once(response: &'static [u8]) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let port = listener.local_addr().expect("local address").port();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        read_request(&mut stream);
+        stream.write_all(response).expect("write");
+    });
+    port
+}
+
+// Read the head of a request without a body.
+fn read_request(stream: &mut TcpStream) {
+    let mut request = vec![];
+    let mut buffer = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        let size = stream.read(&mut buffer).expect("read");
+        request.extend_from_slice(&buffer[..size]);
+    }
+}
+
+#[test]
+fn test_chunked_response() {
+    let port = serve_once(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nchunks\r\n1\r\n \r\n7\r\ndecoded\r\n0\r\nTrailer: value\r\n\r\n");
+    let response = Http::new().blocking_get(&format!("http://127.0.0.1:{}/", port)).expect("http get");
+    assert_eq!(response.body, b"chunks decoded");
+}
+
+#[test]
+fn test_close_delimited_response() {
+    let port = serve_once(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nuntil the connection is closed");
+    let response = Http::new().blocking_get(&format!("http://127.0.0.1:{}/", port)).expect("http get");
+    assert_eq!(response.body, b"until the connection is closed");
+}
+
+#[test]
+fn test_response_status_and_headers() {
+    let port = serve_once(b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 120\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\r\nbusy");
+    let response = Http::new().blocking_get(&format!("http://127.0.0.1:{}/", port)).expect("http get");
+    assert!(!response.is_success());
+    assert_eq!(response.status, 503);
+    assert_eq!(response.reason, "Service Unavailable");
+    assert_eq!(response.version, Version::Http11);
+    assert_eq!(response.headers.get("retry-after"), Some("120"));
+    assert_eq!(response.headers.get("CONTENT-TYPE"), Some("text/plain"));
+    assert_eq!(response.body, b"busy");
+}
+
+#[test]
+fn test_truncated_response() {
+    let port = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\ntruncated");
+    assert!(Http::new().blocking_get(&format!("http://127.0.0.1:{}/", port)).is_err());
+}
+
+fn echo(request: &Request) -> Response {
+    Response::text(format!("{} {} {} {}", request.method, request.query_string(),
+        request.headers().get("authorization").unwrap_or(""), String::from_utf8_lossy(request.body())))
+        .with_header("X-Content-Length", request.headers().get("content-length").unwrap_or("none"))
+}
+
+#[test]
+fn test_request_builder() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        let router = Router::new()
+            .route(Method::Get, "/echo", echo)
+            .route(Method::Post, "/echo", echo)
+            .route(Method::Put, "/echo", echo)
+            .route(Method::Patch, "/echo", echo)
+            .route(Method::Delete, "/echo", echo);
+        http_server::serve(&mut event_loop, "127.0.0.1:1343", router).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    let http = Http::new();
+    let request = HttpRequest::post("http://127.0.0.1:1343/echo?id=1")
+        .with_header("Authorization", "Bearer token")
+        .with_header("Content-Type", "application/json")
+        .with_body(r#"{"bid":1}"#);
+    let response = http.blocking_send(request).expect("http post");
+    assert_eq!(response.body, br#"POST id=1 Bearer token {"bid":1}"#.to_vec());
+    assert_eq!(response.headers.get("x-content-length"), Some("9"));
+
+    for &(request, expected) in &[(HttpRequest::put as fn(&str) -> HttpRequest, "PUT"), (HttpRequest::patch, "PATCH")] {
+        let response = http.blocking_send(request("http://127.0.0.1:1343/echo")).expect("http send");
+        assert_eq!(response.body, format!("{}   ", expected).into_bytes());
+        assert_eq!(response.headers.get("x-content-length"), Some("0"));
+    }
+
+    let response = http.blocking_send(HttpRequest::delete("http://127.0.0.1:1343/echo")).expect("http delete");
+    assert_eq!(response.body, b"DELETE   ".to_vec());
+    assert_eq!(response.headers.get("x-content-length"), Some("none"));
+
+    // The response to a HEAD request has no body.
+    let response = http.blocking_send(HttpRequest::head("http://127.0.0.1:1343/echo")).expect("http head");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.headers.get("content-length"), Some("7"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn test_invalid_header_fields() {
+    // The request is rejected before connecting.
+    let mut event_loop = Loop::new().expect("event loop");
+    let responses = Rc::new(RefCell::new(vec![]));
+    let http = Http::new();
+    for &(name, value) in &[("X-Value", "a\r\nX-Injected: 1"), ("X-Value", "a\nb"), ("X-Value", "a\0b"), ("X Name", "a"), ("", "a")] {
+        let request = HttpRequest::get("http://127.0.0.1:1/").with_header(name, value);
+        let error = http.send(request, &mut event_loop, Collect { responses: responses.clone() }).expect_err("invalid header");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{:?}: {:?}", name, value);
+    }
+    assert!(responses.borrow().is_empty());
+}
+
+// Answer the requests of every connection with the index of the connection, and send this index
+// when the connection is closed by the client.
+fn serve_keep_alive(closed: Sender<usize>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let port = listener.local_addr().expect("local address").port();
+    thread::spawn(move || {
+        for (index, stream) in listener.incoming().enumerate() {
+            let mut stream = stream.expect("accept");
+            let closed = closed.clone();
+            thread::spawn(move || {
+                let mut request = vec![];
+                let mut buffer = [0; 1024];
+                loop {
+                    while let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                        request.drain(..end + 4);
+                        // Slow down the responses to get the requests queued.
+                        thread::sleep(Duration::from_millis(10));
+                        let body = index.to_string();
+                        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+                        stream.write_all(response.as_bytes()).expect("write");
+                    }
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(size) => request.extend_from_slice(&buffer[..size]),
+                    }
+                }
+                let _ = closed.send(index);
+            });
+        }
+    });
+    port
+}
+
+struct Collect {
+    responses: Rc<RefCell<Vec<String>>>,
+}
+
+impl HttpHandler for Collect {
+    fn error(&mut self, error: io::Error) {
+        self.responses.borrow_mut().push(format!("error {:?}", error.kind()));
+    }
+
+    fn response(&mut self, response: HttpResponse) {
+        self.responses.borrow_mut().push(String::from_utf8(response.body).expect("utf-8"));
+    }
+}
+
+#[test]
+fn test_connection_pool() {
+    let (sender, closed) = channel();
+    let port = serve_keep_alive(sender);
+    let uri = format!("http://127.0.0.1:{}/", port);
+
+    let mut event_loop = Loop::new().expect("event loop");
+    let http = Http::with_pool_config(PoolConfig {
+        idle_timeout: Some(Duration::from_millis(100)),
+        max_connections_per_host: 2,
+    });
+    let responses = Rc::new(RefCell::new(vec![]));
+    for _ in 0..10 {
+        http.get(&uri, &mut event_loop, Collect { responses: responses.clone() }).expect("http get");
+    }
+
+    let mut event_list = event_list();
+    while responses.borrow().len() < 10 {
+        event_loop.iterate(&mut event_list);
+    }
+    // The queued requests are sent on the 2 connections.
+    let mut connections = responses.borrow().clone();
+    connections.sort();
+    connections.dedup();
+    assert_eq!(connections, vec!["0", "1"]);
+
+    // The idle connections are reused.
+    http.get(&uri, &mut event_loop, Collect { responses: responses.clone() }).expect("http get");
+    while responses.borrow().len() < 11 {
+        event_loop.iterate(&mut event_list);
+    }
+    assert!(connections.contains(&responses.borrow()[10]));
+    assert!(closed.try_recv().is_err());
+
+    // Then closed after the idle timeout.
+    let start = Instant::now();
+    let mut closed_connections = vec![];
+    while closed_connections.len() < 2 && start.elapsed() < Duration::from_secs(5) {
+        event_loop.iterate(&mut event_list);
+        closed_connections.extend(closed.try_iter());
+    }
+    closed_connections.sort();
+    assert_eq!(closed_connections, vec![0, 1]);
+}
+
+#[test]
+fn test_closed_idle_connection() {
+    // The server closes the first connection when it receives the second request.
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let port = listener.local_addr().expect("local address").port();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        read_request(&mut stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n0").expect("write");
+        read_request(&mut stream);
+        drop(stream);
+        let (mut stream, _) = listener.accept().expect("accept");
+        read_request(&mut stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n1").expect("write");
+        let _ = stream.read(&mut [0; 1]);
+    });
+    let uri = format!("http://127.0.0.1:{}/", port);
+
+    let mut event_loop = Loop::new().expect("event loop");
+    let http = Http::new();
+    let responses = Rc::new(RefCell::new(vec![]));
+    let mut event_list = event_list();
+    for count in 1..3 {
+        http.get(&uri, &mut event_loop, Collect { responses: responses.clone() }).expect("http get");
+        while responses.borrow().len() < count {
+            event_loop.iterate(&mut event_list);
+        }
+    }
+    // The request sent on the closed idle connection is sent again on a new connection.
+    assert_eq!(*responses.borrow(), vec!["0", "1"]);
+}
+
+#[test]
+fn test_redirects_and_retries() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        let failures = Rc::new(RefCell::new(0));
+        let unavailable = {
+            let failures = failures.clone();
+            move |_: &Request| {
+                *failures.borrow_mut() += 1;
+                if *failures.borrow() % 3 == 0 {
+                    Response::text("available")
+                }
+                else {
+                    Response::new(503)
+                }
+            }
+        };
+        let router = Router::new()
+            .get("/a", |_| Response::new(302).with_header("Location", "/b"))
+            .get("/b", echo)
+            .post("/form", |_| Response::new(303).with_header("Location", "b"))
+            .get("/loop", |_| Response::new(301).with_header("Location", "http://127.0.0.1:1344/loop"))
+            .get("/secure", |_| Response::new(301).with_header("Location", "https://127.0.0.1:1344/b"))
+            .get("/unavailable", unavailable)
+            .post("/unavailable", |_| Response::new(503));
+        http_server::serve(&mut event_loop, "127.0.0.1:1344", router).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    // The redirections are only followed when enabled.
+    let response = Http::new().blocking_get("http://127.0.0.1:1344/a").expect("http get");
+    assert_eq!(response.status, 302);
+
+    let http = Http::new().with_redirects(3);
+    let response = http.blocking_get("http://127.0.0.1:1344/a").expect("http get");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"GET   ".to_vec());
+
+    // A POST redirected with a 303 becomes a GET without body.
+    let request = HttpRequest::post("http://127.0.0.1:1344/form")
+        .with_header("Authorization", "Bearer token")
+        .with_body("data");
+    let response = http.blocking_send(request).expect("http post");
+    assert_eq!(response.body, b"GET  Bearer token ".to_vec());
+    assert_eq!(response.headers.get("x-content-length"), Some("none"));
+
+    let error = http.blocking_get("http://127.0.0.1:1344/loop").expect_err("too many redirections");
+    assert_eq!(error.to_string(), "too many redirections");
+
+    // The redirections to https are not followed in plain text.
+    let error = http.blocking_get("http://127.0.0.1:1344/secure").expect_err("unsupported redirection");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "unsupported redirection to https://127.0.0.1:1344/b");
+    let error = http.blocking_get("https://127.0.0.1:1344/b").expect_err("unsupported scheme");
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(20),
+        max_delay: Duration::from_millis(50),
+        max_retries: 2,
+    };
+    let http = Http::new().with_retry_policy(policy);
+    let start = Instant::now();
+    let response = http.blocking_get("http://127.0.0.1:1344/unavailable").expect("http get");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"available".to_vec());
+    // The delays are at least 10 and 20 milliseconds with the jitter.
+    assert!(start.elapsed() >= Duration::from_millis(30));
+
+    let response = Http::new().blocking_get("http://127.0.0.1:1344/unavailable").expect("http get");
+    assert_eq!(response.status, 503);
+
+    // A POST is not idempotent, so it is not retried.
+    let request = HttpRequest::post("http://127.0.0.1:1344/unavailable");
+    let response = http.blocking_send(request).expect("http post");
+    assert_eq!(response.status, 503);
+
+    // The connection failures are retried until the maximum number of retries is reached.
+    let port = TcpListener::bind("127.0.0.1:0").expect("bind").local_addr().expect("local address").port();
+    let start = Instant::now();
+    assert!(http.blocking_get(&format!("http://127.0.0.1:{}/", port)).is_err());
+    assert!(start.elapsed() >= Duration::from_millis(30));
+}
+
+#[test]
+fn test_ipv6() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        let router = Router::new()
+            .get("/echo", |request| Response::text(request.headers().get("host").unwrap_or("").to_string()));
+        http_server::serve(&mut event_loop, "[::1]:1345", router).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    let response = Http::new().blocking_get("http://[::1]:1345/echo").expect("http get");
+    assert_eq!(response.body, b"[::1]:1345".to_vec());
+}
+
+#[test]
+fn test_query() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        let router = Router::new()
+            .get("/users/:name", |request| {
+                let query =
+                    match request.query() {
+                        Ok(query) => query,
+                        Err(_) => return Response::new(400),
+                    };
+                let tags = query.get_all("tag").collect::<Vec<_>>().join(",");
+                Response::text(format!("{} {} {}", request.param("name").unwrap_or(""), query.get("page").unwrap_or("1"), tags))
+            });
+        http_server::serve(&mut event_loop, "127.0.0.1:1346", router).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    let http = Http::new();
+    let query = Query::new()
+        .with_field("tag", "a b")
+        .with_field("tag", "c&d")
+        .with_field("page", "2");
+    let uri = format!("http://127.0.0.1:1346/users/{}", percent_encode("John Doe"));
+    let response = http.blocking_send(HttpRequest::get(&uri).with_query(&query)).expect("http get");
+    assert_eq!(response.body, b"John Doe 2 a b,c&d".to_vec());
+
+    let response = http.blocking_get("http://127.0.0.1:1346/users/john?page=%zz").expect("http get");
+    assert_eq!(response.status, 400);
+}
@@ -1,35 +1,362 @@
 extern crate mini;
 
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::channel;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use mini::aio::http::Http;
 use mini::aio::http_server::{
     self,
+    DeferredHttpHandler,
     HttpHandler,
+    Limits,
+    Method,
     Request,
+    Responder,
+    Response,
+    Router,
+};
+use mini::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
 };
-use mini::aio::handler::Loop;
 
 #[derive(Clone)]
 struct HttpServer {
 }
 
 impl HttpHandler for HttpServer {
-    fn request(&mut self, request: &Request) -> String {
-        let content = format!("You're on page {} and you queried {} via {}", request.path, request.query_string,
+    fn request(&mut self, request: &Request) -> Response {
+        let content = format!("You're on page {} and you queried {} via {}", request.path(), request.query_string(),
             request.method);
-        content
+        Response::html(content)
     }
 }
 
 #[test]
 fn test_http_client_server() {
-    thread::spawn(|| {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
         let mut event_loop = Loop::new().expect("event loop");
         http_server::serve(&mut event_loop, "127.0.0.1:1337", HttpServer {}).expect("http serve");
+        sender.send(()).expect("send");
         event_loop.run().expect("event loop run");
     });
+    receiver.recv().expect("recv");
     let http = Http::new();
-    let body = http.blocking_get("http://127.0.0.1:1337").expect("http get");
-    assert_eq!(body, b"You're on page / and you queried  via GET".to_vec());
+    let response = http.blocking_get("http://127.0.0.1:1337").expect("http get");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.headers.get("content-type"), Some("text/html"));
+    assert_eq!(response.body, b"You're on page / and you queried  via GET".to_vec());
+}
+
+#[derive(Clone)]
+struct EchoServer {
+}
+
+impl HttpHandler for EchoServer {
+    fn limits(&self) -> Limits {
+        Limits {
+            max_header_count: 4,
+            ..Limits::default()
+        }
+    }
+
+    fn request(&mut self, request: &Request) -> Response {
+        match request.path() {
+            "/empty" => Response::new(204),
+            "/missing" =>
+                Response::new(404)
+                    .with_header("Cache-Control", "no-cache")
+                    .with_body(b"\xffmissing".to_vec()),
+            _ => Response::html(format!("{} {} {}", request.version, request.headers().get("x-name").unwrap_or(""),
+                String::from_utf8_lossy(request.body()))),
+        }
+    }
+}
+
+fn read_response(stream: &mut TcpStream, expected_len: usize) -> String {
+    let mut response = vec![];
+    let mut buffer = [0; 1024];
+    while response.len() < expected_len {
+        let size = stream.read(&mut buffer).expect("read");
+        if size == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..size]);
+    }
+    String::from_utf8(response).expect("utf-8")
+}
+
+#[test]
+fn test_request_parsing() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        http_server::serve(&mut event_loop, "127.0.0.1:1338", EchoServer {}).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    let mut stream = TcpStream::connect("127.0.0.1:1338").expect("connect");
+    let parts: &[&[u8]] = &[b"POST /echo HTTP/1.1\r\nX-NA", b"ME: mini\r\nContent-Length: 5\r\n", b"\r\nhel", b"lo"];
+    for part in parts {
+        stream.write_all(part).expect("write");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let expected = "HTTP/1.1 200 OK\r\nContent-Length: 19\r\nContent-Type: text/html\r\n\r\nHTTP/1.1 mini hello";
+    assert_eq!(read_response(&mut stream, expected.len()), expected);
+
+    let mut stream = TcpStream::connect("127.0.0.1:1338").expect("connect");
+    stream.write_all(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\nE: 5\r\n\r\n").expect("write");
+    let response = read_response(&mut stream, usize::MAX);
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+    let mut stream = TcpStream::connect("127.0.0.1:1338").expect("connect");
+    stream.write_all(b"GET /missing HTTP/1.1\r\n\r\nGET /empty HTTP/1.1\r\n\r\n").expect("write");
+    let expected: &[u8] = b"HTTP/1.1 404 Not Found\r\nContent-Length: 8\r\nCache-Control: no-cache\r\n\r\n\xffmissingHTTP/1.1 204 No Content\r\n\r\n";
+    let mut response = vec![0; expected.len()];
+    stream.read_exact(&mut response).expect("read");
+    assert_eq!(response, expected);
+}
+
+#[derive(Clone)]
+struct KeepAliveServer {
+}
+
+impl HttpHandler for KeepAliveServer {
+    fn idle_timeout(&self) -> Option<Duration> {
+        Some(Duration::from_millis(100))
+    }
+
+    fn request(&mut self, request: &Request) -> Response {
+        Response::text(request.path().to_string())
+    }
+}
+
+// Read the responses until the server closes the connection.
+fn read_to_close(stream: &mut TcpStream) -> String {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("read");
+    response
+}
+
+#[test]
+fn test_keep_alive() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        http_server::serve(&mut event_loop, "127.0.0.1:1339", KeepAliveServer {}).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    let response = |path: &str, headers: &str| {
+        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/plain; charset=utf-8\r\n{}\r\n{}", path.len(),
+            headers, path)
+    };
+
+    // Pipelined requests are answered in order and the connection is closed after Connection: close.
+    let mut stream = TcpStream::connect("127.0.0.1:1339").expect("connect");
+    stream.write_all(b"GET /1 HTTP/1.1\r\n\r\nGET /2 HTTP/1.1\r\nConnection: close\r\n\r\nGET /3 HTTP/1.1\r\n\r\n")
+        .expect("write");
+    assert_eq!(read_to_close(&mut stream), response("/1", "") + &response("/2", "Connection: close\r\n"));
+
+    // HTTP/1.0 closes the connection by default.
+    let mut stream = TcpStream::connect("127.0.0.1:1339").expect("connect");
+    stream.write_all(b"GET /1 HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").expect("write");
+    let expected = response("/1", "Connection: keep-alive\r\n");
+    assert_eq!(read_response(&mut stream, expected.len()), expected);
+    stream.write_all(b"GET /2 HTTP/1.0\r\n\r\n").expect("write");
+    assert_eq!(read_to_close(&mut stream), response("/2", "Connection: close\r\n"));
+
+    // An idle connection is closed after the timeout.
+    let mut stream = TcpStream::connect("127.0.0.1:1339").expect("connect");
+    let start = Instant::now();
+    assert_eq!(read_to_close(&mut stream), "");
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+fn router() -> Router {
+    Router::new()
+        .get("/", |_request| Response::text("index"))
+        .get("/users/:id", |request| Response::text(format!("user {}", request.param("id").unwrap_or(""))))
+        .post("/users/:id", |request| Response::text(format!("update {}", request.param("id").unwrap_or(""))))
+        .delete("/users/:id", |request| Response::text(format!("delete {}", request.param("id").unwrap_or(""))))
+        .route(Method::Extension("PURGE".to_string()), "/cache", |_request| Response::text("purged"))
+        .get("/users/:id/files/*path", |request| Response::text(format!("{} {}",
+            request.param("id").unwrap_or(""), request.param("path").unwrap_or(""))))
+        .get("/redirect/:to", |request| Response::redirect(302, request.param("to").unwrap_or("")))
+}
+
+#[test]
+fn test_router() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        http_server::serve(&mut event_loop, "127.0.0.1:1340", router()).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    let request = |request: &str| {
+        let mut stream = TcpStream::connect("127.0.0.1:1340").expect("connect");
+        stream.write_all(request.as_bytes()).expect("write");
+        stream.write_all(b"Connection: close\r\n\r\n").expect("write");
+        let response = read_to_close(&mut stream);
+        let status = response.split(' ').nth(1).unwrap_or("").to_string();
+        let body = response.split_once("\r\n\r\n").map(|(_, body)| body).unwrap_or("").to_string();
+        (status, body, response)
+    };
+
+    let (status, body, _) = request("GET / HTTP/1.1\r\n");
+    assert_eq!((status.as_str(), body.as_str()), ("200", "index"));
+    let (status, body, _) = request("GET /users/42 HTTP/1.1\r\n");
+    assert_eq!((status.as_str(), body.as_str()), ("200", "user 42"));
+    let (status, body, _) = request("POST /users/42 HTTP/1.1\r\n");
+    assert_eq!((status.as_str(), body.as_str()), ("200", "update 42"));
+    let (status, body, _) = request("GET /users/42/files/a/b.txt?raw HTTP/1.1\r\n");
+    assert_eq!((status.as_str(), body.as_str()), ("200", "42 a/b.txt"));
+    let (status, body, _) = request("GET /users/42/files HTTP/1.1\r\n");
+    assert_eq!((status.as_str(), body.as_str()), ("200", "42 "));
+    // The :name captures are decoded, but not the wildcard capture.
+    let (status, body, _) = request("GET /users/4%202/files/a%2Fb/c%20d HTTP/1.1\r\n");
+    assert_eq!((status.as_str(), body.as_str()), ("200", "4 2 a%2Fb/c%20d"));
+    let (status, _, _) = request("GET /users/4%2 HTTP/1.1\r\n");
+    assert_eq!(status, "400");
+
+    // The header fields cannot split the response.
+    let (status, _, response) = request("GET /redirect/%2Fusers HTTP/1.1\r\n");
+    assert_eq!(status, "302");
+    assert!(response.contains("\r\nLocation: /users\r\n"));
+    let (status, _, response) = request("GET /redirect/%2F%0D%0ASet-Cookie:%20a=1 HTTP/1.1\r\n");
+    assert_eq!(status, "500");
+    assert!(!response.contains("Set-Cookie"));
+
+    for path in &["/users", "/users/", "/users/42/", "/unknown"] {
+        let (status, _, _) = request(&format!("GET {} HTTP/1.1\r\n", path));
+        assert_eq!(status, "404", "{}", path);
+    }
+
+    let (status, _, response) = request("POST /users/42/files/a HTTP/1.1\r\n");
+    assert_eq!(status, "405");
+    assert!(response.contains("\r\nAllow: GET, HEAD\r\n"));
+}
+
+#[test]
+fn test_methods() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        http_server::serve(&mut event_loop, "127.0.0.1:1341", router()).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    let request = |request: &str| {
+        let mut stream = TcpStream::connect("127.0.0.1:1341").expect("connect");
+        stream.write_all(request.as_bytes()).expect("write");
+        read_to_close(&mut stream)
+    };
+
+    let response = request("DELETE /users/42 HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\ndelete 42"));
+
+    // The GET route answers the HEAD request without the body.
+    let response = request("HEAD /users/42 HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Length: 7\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+
+    let response = request("PURGE /cache HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\npurged"));
+
+    // Unknown methods are not handled as GET.
+    let response = request("PROPFIND /users/42 HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+    let response = request("G{ET /users/42 HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+struct Delayed {
+    event_loop: Loop,
+}
+
+enum DelayedMsg {
+    Request(String, Responder),
+    Respond(String, Responder),
+}
+
+impl Handler for Delayed {
+    type Msg = DelayedMsg;
+
+    fn update(&mut self, stream: &Stream<DelayedMsg>, msg: DelayedMsg) {
+        match msg {
+            DelayedMsg::Request(path, responder) => {
+                let delay = path.trim_start_matches('/').parse().unwrap_or(0);
+                self.event_loop.set_timeout(Duration::from_millis(delay), stream, DelayedMsg::Respond(path, responder))
+                    .expect("set timeout");
+            },
+            DelayedMsg::Respond(path, responder) => responder.respond(Response::text(path)),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct DeferredServer {
+    delayed: Stream<DelayedMsg>,
+}
+
+impl DeferredHttpHandler for DeferredServer {
+    fn idle_timeout(&self) -> Option<Duration> {
+        Some(Duration::from_millis(100))
+    }
+
+    fn request(&mut self, request: &Request, responder: Responder) {
+        if request.path() == "/drop" {
+            return;
+        }
+        self.delayed.send(DelayedMsg::Request(request.path().to_string(), responder));
+    }
+}
+
+#[test]
+fn test_deferred_responses() {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let mut event_loop = Loop::new().expect("event loop");
+        let delayed = event_loop.spawn(Delayed {
+            event_loop: event_loop.clone(),
+        });
+        http_server::serve(&mut event_loop, "127.0.0.1:1342", DeferredServer { delayed }).expect("http serve");
+        sender.send(()).expect("send");
+        event_loop.run().expect("event loop run");
+    });
+    receiver.recv().expect("recv");
+
+    let response = |path: &str, headers: &str| {
+        format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/plain; charset=utf-8\r\n{}\r\n{}", path.len(),
+            headers, path)
+    };
+
+    // The responses are sent in the order of the requests and the idle timeout does not close the
+    // connection while a response is pending.
+    let mut stream = TcpStream::connect("127.0.0.1:1342").expect("connect");
+    stream.write_all(b"GET /300 HTTP/1.1\r\n\r\nGET /0 HTTP/1.1\r\n\r\n").expect("write");
+    let expected = response("/300", "") + &response("/0", "");
+    assert_eq!(read_response(&mut stream, expected.len()), expected);
+    stream.write_all(b"GET /10 HTTP/1.1\r\nConnection: close\r\n\r\n").expect("write");
+    assert_eq!(read_to_close(&mut stream), response("/10", "Connection: close\r\n"));
+
+    // A dropped responder sends an error.
+    let mut stream = TcpStream::connect("127.0.0.1:1342").expect("connect");
+    stream.write_all(b"GET /drop HTTP/1.1\r\nConnection: close\r\n\r\n").expect("write");
+    assert!(read_to_close(&mut stream).starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
 }
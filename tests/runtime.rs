@@ -0,0 +1,123 @@
+extern crate mini;
+
+use std::io;
+use std::sync::mpsc::{Sender, channel};
+use std::sync::Mutex;
+
+use mini::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+};
+use mini::aio::runtime::{
+    Runtime,
+    RuntimeHandle,
+};
+
+use self::Msg::*;
+
+#[derive(Clone)]
+enum Msg {
+    Hello,
+    Ping,
+}
+
+struct Worker {
+    handle: RuntimeHandle<Msg>,
+    sender: Sender<(usize, &'static str)>,
+}
+
+impl Handler for Worker {
+    type Msg = Msg;
+
+    fn update(&mut self, _stream: &Stream<Msg>, msg: Msg) {
+        match msg {
+            Hello => {
+                let _ = self.sender.send((self.handle.index(), "hello"));
+                // Every loop pings the next one.
+                let next = (self.handle.index() + 1) % self.handle.num_loops();
+                self.handle.send(next, Ping);
+            },
+            Ping => {
+                let _ = self.sender.send((self.handle.index(), "ping"));
+            },
+        }
+    }
+}
+
+#[test]
+fn test_runtime() {
+    const NUM_LOOPS: usize = 4;
+
+    let (sender, receiver) = channel();
+    let sender = Mutex::new(sender);
+    let runtime = Runtime::new(NUM_LOOPS, move |event_loop: &mut Loop, handle| {
+        let sender = sender.lock().expect("lock").clone();
+        Ok(Some(event_loop.spawn(Worker {
+            handle: handle.clone(),
+            sender,
+        })))
+    }).expect("runtime");
+
+    assert_eq!(runtime.num_loops(), NUM_LOOPS);
+    runtime.broadcast(Hello);
+
+    let mut hellos = vec![];
+    let mut pings = vec![];
+    for _ in 0..2 * NUM_LOOPS {
+        let (index, event) = receiver.recv().expect("recv");
+        match event {
+            "hello" => hellos.push(index),
+            _ => pings.push(index),
+        }
+    }
+    hellos.sort();
+    pings.sort();
+    assert_eq!(hellos, vec![0, 1, 2, 3]);
+    assert_eq!(pings, vec![0, 1, 2, 3]);
+
+    runtime.stop();
+    runtime.join().expect("runtime join");
+}
+
+#[test]
+fn test_runtime_without_handler() {
+    let runtime = Runtime::<Msg>::new(2, |_event_loop: &mut Loop, _handle| Ok(None)).expect("runtime");
+    assert_eq!(runtime.num_loops(), 2);
+    // The messages to the loops without a handler are dropped.
+    runtime.broadcast(Hello);
+    runtime.send(0, Ping);
+    runtime.stop();
+    runtime.join().expect("runtime join");
+}
+
+#[test]
+fn test_runtime_init_error() {
+    let result = Runtime::new(3, |event_loop: &mut Loop, handle| {
+        if handle.index() == 1 {
+            return Err(io::Error::other("init failed"));
+        }
+        let (sender, _receiver) = channel();
+        Ok(Some(event_loop.spawn(Worker {
+            handle: handle.clone(),
+            sender,
+        })))
+    });
+    // The loops that were initialized are not started and the error is returned.
+    assert_eq!(result.err().map(|error| error.to_string()), Some("init failed".to_string()));
+}
+
+#[test]
+fn test_runtime_init_panic() {
+    let result = Runtime::new(3, |event_loop: &mut Loop, handle| {
+        if handle.index() == 1 {
+            panic!("init panicked");
+        }
+        let (sender, _receiver) = channel();
+        Ok(Some(event_loop.spawn(Worker {
+            handle: handle.clone(),
+            sender,
+        })))
+    });
+    assert_eq!(result.err().map(|error| error.to_string()), Some("event loop thread panicked".to_string()));
+}
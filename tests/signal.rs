@@ -0,0 +1,78 @@
+extern crate mini;
+
+use std::cell::RefCell;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+
+use mini::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+};
+use mini::aio::signal::{Signal, ffi};
+
+use self::Msg::*;
+
+enum Msg {
+    Received(Signal),
+}
+
+struct SignalHandler {
+    event_loop: Loop,
+    signals: Rc<RefCell<Vec<Signal>>>,
+}
+
+impl Handler for SignalHandler {
+    type Msg = Msg;
+
+    fn update(&mut self, _stream: &Stream<Msg>, msg: Msg) {
+        match msg {
+            Received(signal) => {
+                self.signals.borrow_mut().push(signal);
+                self.event_loop.stop();
+            },
+        }
+    }
+}
+
+extern "C" {
+    fn raise(signal: i32) -> i32;
+}
+
+#[test]
+fn test_signal() {
+    let mut event_loop = Loop::new().expect("event loop");
+    let signals = Rc::new(RefCell::new(vec![]));
+    let stream = event_loop.spawn(SignalHandler {
+        event_loop: event_loop.clone(),
+        signals: signals.clone(),
+    });
+    let watch = event_loop.add_signal(Signal::User1, &stream, Received).expect("add signal");
+    assert!(is_blocked(Signal::User1));
+
+    // NOTE: raise() sends the signal to the current thread, which has it blocked.
+    assert_eq!(unsafe { raise(Signal::User1 as i32) }, 0);
+
+    event_loop.run().expect("event loop run");
+
+    assert_eq!(*signals.borrow(), vec![Signal::User1]);
+
+    // The signal can be watched again after the watch is removed, which restores the signal mask.
+    assert!(watch.is_active());
+    event_loop.remove_signal(&watch).expect("remove signal");
+    assert!(!watch.is_active());
+    assert!(!is_blocked(Signal::User1));
+    let watch = event_loop.add_signal(Signal::User1, &stream, Received).expect("add signal again");
+    assert!(is_blocked(Signal::User1));
+    event_loop.remove_signal(&watch).expect("remove signal");
+    assert!(!is_blocked(Signal::User1));
+}
+
+fn is_blocked(signal: Signal) -> bool {
+    unsafe {
+        let mut set: ffi::sigset_t = mem::zeroed();
+        assert_eq!(ffi::pthread_sigmask(ffi::SIG_BLOCK, ptr::null(), &mut set), 0);
+        ffi::sigismember(&set, signal as i32) == 1
+    }
+}
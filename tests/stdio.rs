@@ -0,0 +1,109 @@
+extern crate mini;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
+
+use mini::aio::handler::Loop;
+use mini::aio::poll::event_list;
+use mini::aio::stdio::{
+    Output,
+    OutputNotify,
+};
+
+struct Notify {
+    events: Rc<RefCell<Vec<(&'static str, usize)>>>,
+    reader: Sender<()>,
+}
+
+impl OutputNotify for Notify {
+    fn throttled(&mut self, output: &mut Output) {
+        assert!(output.throttled());
+        self.events.borrow_mut().push(("throttled", output.pending_bytes()));
+        let _ = self.reader.send(());
+    }
+
+    fn unthrottled(&mut self, output: &mut Output) {
+        assert!(!output.throttled());
+        self.events.borrow_mut().push(("unthrottled", output.pending_bytes()));
+        // The remaining bytes are still written after the pipe is drained.
+        output.write(b"end".to_vec()).expect("write");
+    }
+}
+
+const SIZE: usize = 32 * 64 * 1024;
+
+#[test]
+fn test_output_backpressure() {
+    let mut event_loop = Loop::new().expect("event loop");
+    let (mut pipe_reader, pipe_writer) = io::pipe().expect("pipe");
+
+    let (sender, receiver) = channel();
+    let reader = thread::spawn(move || {
+        // Only start reading once the output is throttled.
+        receiver.recv().expect("recv");
+        let mut data = vec![];
+        pipe_reader.read_to_end(&mut data).expect("read");
+        data
+    });
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let notify = Notify {
+        events: events.clone(),
+        reader: sender,
+    };
+    let output = Output::new(&mut event_loop, pipe_writer, notify).expect("output");
+    output.set_watermarks(64 * 1024, 1024 * 1024);
+    // Write more than what the pipe can hold while nothing reads it.
+    for index in 0..32_u8 {
+        output.write(vec![index; 64 * 1024]).expect("write");
+    }
+    assert!(output.pending_bytes() > 0);
+
+    let mut event_list = event_list();
+    while events.borrow().len() < 2 || output.pending_bytes() > 0 {
+        event_loop.iterate(&mut event_list);
+    }
+    // The pipe is closed when the output is dropped.
+    drop(output);
+    let data = reader.join().expect("join");
+
+    let events = events.borrow();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].0, "throttled");
+    assert!(events[0].1 > 1024 * 1024);
+    assert_eq!(events[1].0, "unthrottled");
+    assert!(events[1].1 <= 64 * 1024);
+
+    // The data is written in order.
+    for (index, chunk) in data[..SIZE].chunks(64 * 1024).enumerate() {
+        assert!(chunk.iter().all(|&byte| byte == index as u8));
+    }
+    assert_eq!(data.len(), SIZE + 3);
+    assert!(data.ends_with(b"end"));
+}
+
+fn nonblocking(fd: RawFd) -> bool {
+    let fdinfo = fs::read_to_string(format!("/proc/self/fdinfo/{}", fd)).expect("fdinfo");
+    let flags = fdinfo.lines().find_map(|line| line.strip_prefix("flags:")).expect("flags");
+    i32::from_str_radix(flags.trim(), 8).expect("flags") & 0o4000 != 0
+}
+
+#[test]
+fn test_output_restores_flags() {
+    let mut event_loop = Loop::new().expect("event loop");
+    let (_pipe_reader, pipe_writer) = io::pipe().expect("pipe");
+    let fd = pipe_writer.as_raw_fd();
+    // The mode is shared with the other file descriptors of the pipe.
+    let output = Output::new(&mut event_loop, pipe_writer.try_clone().expect("clone"), ()).expect("output");
+    assert!(nonblocking(fd));
+    let clone = output.clone();
+    drop(output);
+    assert!(nonblocking(fd));
+    drop(clone);
+    assert!(!nonblocking(fd));
+}
@@ -1,10 +1,14 @@
 extern crate mini;
 
+use std::cell::RefCell;
 use std::io::Write;
 use std::net;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Sender, channel};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use mini::aio::poll::{
     EpollResult,
@@ -12,6 +16,7 @@ use mini::aio::poll::{
 };
 use mini::aio::handler::Loop;
 use mini::aio::net::{
+    Idle,
     TcpConnection,
     TcpConnectionNotify,
     TcpListenNotify,
@@ -105,3 +110,255 @@ fn test_blocked_write() {
         }
     }
 }
+
+struct IdleListener {
+    event_loop: Loop,
+}
+
+impl TcpListenNotify for IdleListener {
+    fn connected(&mut self, _listener: &net::TcpListener) -> Box<dyn TcpConnectionNotify> {
+        Box::new(IdleServer {
+            event_loop: self.event_loop.clone(),
+        })
+    }
+}
+
+struct IdleServer {
+    event_loop: Loop,
+}
+
+impl TcpConnectionNotify for IdleServer {
+    fn accepted(&mut self, connection: &mut TcpConnection) {
+        connection.set_read_idle_timeout(Some(Duration::from_millis(50)));
+    }
+
+    fn idle(&mut self, connection: &mut TcpConnection, idle: Idle) {
+        assert_eq!(idle, Idle::Read);
+        connection.dispose();
+        self.event_loop.stop();
+    }
+}
+
+#[test]
+fn test_read_idle_timeout() {
+    let mut event_loop = Loop::new().expect("event loop");
+
+    let listener = IdleListener {
+        event_loop: event_loop.clone(),
+    };
+    let (_stream, address) = TcpListener::ip4(&mut event_loop, "127.0.0.1:0", listener).expect("listen");
+
+    let (sender, receiver) = channel();
+    let client = thread::spawn(move || {
+        use std::net::TcpStream;
+
+        let _stream = TcpStream::connect(address).expect("stream");
+        // Stay quiet until the server noticed the connection is idle.
+        receiver.recv().expect("recv");
+    });
+
+    let start = Instant::now();
+    event_loop.run().expect("event loop run");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    sender.send(()).expect("send");
+    client.join().expect("join");
+}
+
+struct BackpressureListener {
+    events: Rc<RefCell<Vec<(&'static str, usize)>>>,
+    event_loop: Loop,
+    reader: Sender<()>,
+}
+
+impl TcpListenNotify for BackpressureListener {
+    fn connected(&mut self, _listener: &net::TcpListener) -> Box<dyn TcpConnectionNotify> {
+        Box::new(BackpressureServer {
+            events: self.events.clone(),
+            event_loop: self.event_loop.clone(),
+            reader: self.reader.clone(),
+        })
+    }
+}
+
+struct BackpressureServer {
+    events: Rc<RefCell<Vec<(&'static str, usize)>>>,
+    event_loop: Loop,
+    reader: Sender<()>,
+}
+
+impl TcpConnectionNotify for BackpressureServer {
+    fn accepted(&mut self, connection: &mut TcpConnection) {
+        connection.set_watermarks(64 * 1024, 1024 * 1024);
+        // Write more than what the socket buffers can hold while the client does not read.
+        for _ in 0..512 {
+            connection.write(vec![b'a'; 64 * 1024]).expect("write");
+        }
+    }
+
+    fn throttled(&mut self, connection: &mut TcpConnection) {
+        assert!(connection.throttled());
+        self.events.borrow_mut().push(("throttled", connection.pending_bytes()));
+        let _ = self.reader.send(());
+    }
+
+    fn unthrottled(&mut self, connection: &mut TcpConnection) {
+        assert!(!connection.throttled());
+        self.events.borrow_mut().push(("unthrottled", connection.pending_bytes()));
+        connection.dispose();
+        self.event_loop.stop();
+    }
+}
+
+#[test]
+fn test_write_backpressure() {
+    let mut event_loop = Loop::new().expect("event loop");
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let (sender, receiver) = channel();
+    let listener = BackpressureListener {
+        events: events.clone(),
+        event_loop: event_loop.clone(),
+        reader: sender,
+    };
+    let (_stream, address) = TcpListener::ip4(&mut event_loop, "127.0.0.1:0", listener).expect("listen");
+
+    let done = Arc::new(AtomicBool::new(false));
+    let thread_done = done.clone();
+    let client = thread::spawn(move || {
+        use std::io::Read;
+        use std::net::TcpStream;
+
+        let mut stream = TcpStream::connect(address).expect("stream");
+        stream.set_read_timeout(Some(Duration::from_millis(10))).expect("set read timeout");
+        // Only start reading once the server is throttled.
+        receiver.recv().expect("recv");
+        let mut buffer = vec![0; 64 * 1024];
+        while !thread_done.load(Ordering::SeqCst) {
+            let _ = stream.read(&mut buffer);
+        }
+    });
+
+    event_loop.run().expect("event loop run");
+    done.store(true, Ordering::SeqCst);
+    client.join().expect("join");
+
+    let events = events.borrow();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].0, "throttled");
+    assert!(events[0].1 > 1024 * 1024);
+    assert_eq!(events[1].0, "unthrottled");
+    assert!(events[1].1 <= 64 * 1024);
+}
+
+struct DualStackListener {
+}
+
+impl TcpListenNotify for DualStackListener {
+    fn connected(&mut self, _listener: &net::TcpListener) -> Box<dyn TcpConnectionNotify> {
+        Box::new(Server {})
+    }
+}
+
+struct DualStackClient {
+    event_loop: Loop,
+    events: Rc<RefCell<Vec<&'static str>>>,
+}
+
+impl DualStackClient {
+    fn push(&mut self, event: &'static str) {
+        self.events.borrow_mut().push(event);
+        if self.events.borrow().len() == 2 {
+            self.event_loop.stop();
+        }
+    }
+}
+
+impl TcpConnectionNotify for DualStackClient {
+    fn connect_failed(&mut self) {
+        self.push("connect failed");
+    }
+
+    fn connected(&mut self, connection: &mut TcpConnection) {
+        connection.dispose();
+        self.push("connected");
+    }
+}
+
+#[test]
+fn test_ipv6() {
+    let mut event_loop = Loop::new().expect("event loop");
+    assert!(TcpListener::ip4(&mut event_loop, "[::1]:0", DualStackListener {}).is_err());
+    let (_stream, address) = TcpListener::ip6(&mut event_loop, "[::1]:0", DualStackListener {}).expect("listen");
+    assert!(address.is_ipv6());
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let client = DualStackClient {
+        event_loop: event_loop.clone(),
+        events: events.clone(),
+    };
+    // The host has no IPv4 address.
+    assert!(TcpConnection::ip4(&mut event_loop, "::1", address.port(), client).is_some());
+    let client = DualStackClient {
+        event_loop: event_loop.clone(),
+        events: events.clone(),
+    };
+    assert!(TcpConnection::connect(&mut event_loop, "::1", address.port(), client).is_some());
+    event_loop.run().expect("event loop run");
+    events.borrow_mut().sort();
+    assert_eq!(*events.borrow(), vec!["connect failed", "connected"]);
+}
+
+struct WriteIdleListener {
+    event_loop: Loop,
+}
+
+impl TcpListenNotify for WriteIdleListener {
+    fn connected(&mut self, _listener: &net::TcpListener) -> Box<dyn TcpConnectionNotify> {
+        Box::new(WriteIdleServer {
+            event_loop: self.event_loop.clone(),
+        })
+    }
+}
+
+struct WriteIdleServer {
+    event_loop: Loop,
+}
+
+impl TcpConnectionNotify for WriteIdleServer {
+    fn accepted(&mut self, connection: &mut TcpConnection) {
+        connection.set_write_idle_timeout(Some(Duration::from_millis(50)));
+    }
+
+    fn idle(&mut self, connection: &mut TcpConnection, idle: Idle) {
+        assert_eq!(idle, Idle::Write);
+        connection.dispose();
+        self.event_loop.stop();
+    }
+}
+
+#[test]
+fn test_write_idle_timeout() {
+    let mut event_loop = Loop::new().expect("event loop");
+
+    let listener = WriteIdleListener {
+        event_loop: event_loop.clone(),
+    };
+    let (_stream, address) = TcpListener::ip4(&mut event_loop, "127.0.0.1:0", listener).expect("listen");
+
+    let (sender, receiver) = channel();
+    let client = thread::spawn(move || {
+        use std::net::TcpStream;
+
+        // Keep the connection busy in the read direction: only the write idle timeout can expire.
+        let mut stream = TcpStream::connect(address).expect("stream");
+        while receiver.recv_timeout(Duration::from_millis(10)).is_err() {
+            let _ = stream.write_all(b"ping");
+        }
+    });
+
+    let start = Instant::now();
+    event_loop.run().expect("event loop run");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    sender.send(()).expect("send");
+    client.join().expect("join");
+}
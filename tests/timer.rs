@@ -0,0 +1,77 @@
+extern crate mini;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use mini::aio::handler::{
+    Handler,
+    Loop,
+    Stream,
+};
+use mini::aio::poll::Timer;
+
+use self::Msg::*;
+
+#[derive(Clone)]
+enum Msg {
+    Cancelled,
+    Tick,
+    Timeout,
+}
+
+struct TimerHandler {
+    event_loop: Loop,
+    events: Rc<RefCell<Vec<&'static str>>>,
+    interval: Rc<RefCell<Option<Timer>>>,
+    ticks: u32,
+}
+
+impl Handler for TimerHandler {
+    type Msg = Msg;
+
+    fn update(&mut self, _stream: &Stream<Msg>, msg: Msg) {
+        match msg {
+            Cancelled => self.events.borrow_mut().push("cancelled"),
+            Tick => {
+                self.ticks += 1;
+                self.events.borrow_mut().push("tick");
+                if self.ticks == 3 {
+                    if let Some(ref interval) = *self.interval.borrow() {
+                        self.event_loop.cancel_timer(interval).expect("cancel timer");
+                    }
+                }
+            },
+            Timeout => {
+                self.events.borrow_mut().push("timeout");
+                self.event_loop.stop();
+            },
+        }
+    }
+}
+
+#[test]
+fn test_timers() {
+    let mut event_loop = Loop::new().expect("event loop");
+    let events = Rc::new(RefCell::new(vec![]));
+    let interval = Rc::new(RefCell::new(None));
+    let stream = event_loop.spawn(TimerHandler {
+        event_loop: event_loop.clone(),
+        events: events.clone(),
+        interval: interval.clone(),
+        ticks: 0,
+    });
+
+    let start = Instant::now();
+    event_loop.set_timeout(Duration::from_millis(200), &stream, Timeout).expect("set timeout");
+    let cancelled = event_loop.set_timeout(Duration::from_millis(10), &stream, Cancelled).expect("set timeout");
+    event_loop.cancel_timer(&cancelled).expect("cancel timer");
+    // Cancelling twice does nothing.
+    event_loop.cancel_timer(&cancelled).expect("cancel timer");
+    *interval.borrow_mut() = Some(event_loop.set_interval(Duration::from_millis(20), &stream, Tick).expect("set interval"));
+
+    event_loop.run().expect("event loop run");
+
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(*events.borrow(), vec!["tick", "tick", "tick", "timeout"]);
+}
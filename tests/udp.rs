@@ -0,0 +1,72 @@
+extern crate mini;
+
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use mini::aio::handler::Loop;
+use mini::aio::poll::event_list;
+use mini::aio::net::{
+    UdpNotify,
+    UdpSocket,
+};
+
+struct Echo {
+}
+
+impl UdpNotify for Echo {
+    fn received(&mut self, socket: &mut UdpSocket, data: Vec<u8>, peer_addr: SocketAddr) {
+        let mut response = b"echo: ".to_vec();
+        response.extend(data);
+        socket.send_to(response, peer_addr).expect("send to");
+    }
+}
+
+struct Client {
+    closed: Rc<RefCell<bool>>,
+    received: Rc<RefCell<Vec<String>>>,
+    server_addr: SocketAddr,
+}
+
+impl UdpNotify for Client {
+    fn closed(&mut self, _socket: &mut UdpSocket) {
+        *self.closed.borrow_mut() = true;
+    }
+
+    fn received(&mut self, socket: &mut UdpSocket, data: Vec<u8>, peer_addr: SocketAddr) {
+        assert_eq!(peer_addr, self.server_addr);
+        let mut received = self.received.borrow_mut();
+        received.push(String::from_utf8(data).expect("utf-8"));
+        if received.len() == 3 {
+            socket.dispose();
+        }
+    }
+}
+
+#[test]
+fn test_udp() {
+    let mut event_loop = Loop::new().expect("event loop");
+    let server = UdpSocket::bind(&mut event_loop, "127.0.0.1:0", Echo {}).expect("bind server");
+    let server_addr = server.local_addr().expect("local address");
+
+    let closed = Rc::new(RefCell::new(false));
+    let received = Rc::new(RefCell::new(vec![]));
+    let client = UdpSocket::bind(&mut event_loop, "127.0.0.1:0", Client {
+        closed: closed.clone(),
+        received: received.clone(),
+        server_addr,
+    }).expect("bind client");
+
+    for message in &["one", "two", "three"] {
+        client.send_to(message.as_bytes().to_vec(), server_addr).expect("send to");
+    }
+
+    let mut event_list = event_list();
+    while !*closed.borrow() {
+        event_loop.iterate(&mut event_list);
+    }
+
+    assert_eq!(*received.borrow(), vec!["echo: one", "echo: two", "echo: three"]);
+    assert!(client.local_addr().is_err());
+    assert!(client.send_to(b"closed".to_vec(), server_addr).is_err());
+}
@@ -0,0 +1,147 @@
+extern crate mini;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::net;
+use std::process;
+use std::rc::Rc;
+
+use mini::aio::handler::{
+    Loop,
+    Stream,
+};
+use mini::aio::poll::event_list;
+use mini::aio::net::{
+    ListenerMsg,
+    TcpConnection,
+    TcpConnectionNotify,
+    UnixConnection,
+    UnixListener,
+    UnixListenNotify,
+};
+
+struct Listener {
+}
+
+impl UnixListenNotify for Listener {
+    fn connected(&mut self, _listener: &net::UnixListener) -> Box<dyn TcpConnectionNotify> {
+        Box::new(Server {})
+    }
+}
+
+struct Server {
+}
+
+impl TcpConnectionNotify for Server {
+    fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
+        let _ = connection.write(data);
+    }
+}
+
+struct Client {
+    event_loop: Loop,
+    listener: Stream<ListenerMsg>,
+    received: Rc<RefCell<Vec<u8>>>,
+}
+
+impl TcpConnectionNotify for Client {
+    fn connected(&mut self, connection: &mut TcpConnection) {
+        let _ = connection.write(b"hello".to_vec());
+    }
+
+    fn received(&mut self, connection: &mut TcpConnection, data: Vec<u8>) {
+        self.received.borrow_mut().extend(data);
+        connection.dispose();
+        self.listener.send(ListenerMsg::Dispose);
+        self.event_loop.stop();
+    }
+}
+
+fn echo(event_loop: &mut Loop, listener: Stream<ListenerMsg>, connect: impl FnOnce(&mut Loop, Client)) -> Vec<u8> {
+    let received = Rc::new(RefCell::new(vec![]));
+    let client = Client {
+        event_loop: event_loop.clone(),
+        listener,
+        received: received.clone(),
+    };
+    connect(event_loop, client);
+    event_loop.run().expect("event loop run");
+    // Process the dispose messages.
+    event_loop.iterate(&mut event_list());
+    let received = received.borrow().clone();
+    received
+}
+
+#[test]
+fn test_unix_socket_file() {
+    let path = env::temp_dir().join(format!("mini-test-{}.sock", process::id()));
+    // A stale socket file is left when the listener is dropped.
+    drop(net::UnixListener::bind(&path).expect("bind"));
+    assert!(path.exists());
+
+    let mut event_loop = Loop::new().expect("event loop");
+    let listener = UnixListener::bind(&mut event_loop, &path, Listener {}).expect("bind");
+    // The socket is in use now.
+    assert!(UnixListener::bind(&mut event_loop, &path, Listener {}).is_err());
+
+    // Checking if the socket is in use does not connect to it.
+    let other_path = env::temp_dir().join(format!("mini-test-{}-other.sock", process::id()));
+    let _ = fs::remove_file(&other_path);
+    let other_listener = net::UnixListener::bind(&other_path).expect("bind");
+    let error = UnixListener::bind(&mut event_loop, &other_path, Listener {}).err().expect("socket in use");
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+    other_listener.set_nonblocking(true).expect("set nonblocking");
+    assert_eq!(other_listener.accept().err().map(|error| error.kind()), Some(io::ErrorKind::WouldBlock));
+    drop(other_listener);
+    fs::remove_file(&other_path).expect("remove socket");
+
+    let received = echo(&mut event_loop, listener, |event_loop, client| {
+        UnixConnection::connect(event_loop, &path, client).expect("connect");
+    });
+    assert_eq!(received, b"hello");
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_unix_abstract_socket() {
+    let name = format!("mini-test-{}", process::id());
+    let mut event_loop = Loop::new().expect("event loop");
+    let listener = UnixListener::bind_abstract(&mut event_loop, name.as_bytes(), Listener {}).expect("bind");
+
+    let received = echo(&mut event_loop, listener, |event_loop, client| {
+        UnixConnection::connect_abstract(event_loop, name.as_bytes(), client).expect("connect");
+    });
+    assert_eq!(received, b"hello");
+}
+
+struct FailingClient {
+    event_loop: Loop,
+    events: Rc<RefCell<Vec<String>>>,
+}
+
+impl TcpConnectionNotify for FailingClient {
+    fn connect_failed(&mut self) {
+        self.events.borrow_mut().push("connect failed".to_string());
+        self.event_loop.stop();
+    }
+
+    fn error(&mut self, error: io::Error) {
+        self.events.borrow_mut().push(format!("error {:?}", error.kind()));
+    }
+}
+
+#[test]
+fn test_unix_connect_failed() {
+    let path = env::temp_dir().join(format!("mini-test-{}-missing.sock", process::id()));
+    let mut event_loop = Loop::new().expect("event loop");
+    let events = Rc::new(RefCell::new(vec![]));
+    let client = FailingClient {
+        event_loop: event_loop.clone(),
+        events: events.clone(),
+    };
+    UnixConnection::connect(&mut event_loop, &path, client).expect("connect");
+    event_loop.run().expect("event loop run");
+    assert_eq!(*events.borrow(), vec!["error NotFound".to_string(), "connect failed".to_string()]);
+}